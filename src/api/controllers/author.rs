@@ -0,0 +1,215 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::{Response, StatusCode};
+
+use super::common::{
+    created_response, json_response, no_content_response, page_response, parse_body, parse_page_params,
+    record_response,
+};
+use crate::api::models::author::*;
+use crate::api::models::version::{self, Precondition};
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the /author/all
+route. `limit` and `offset` select the page (25 authors from the start
+by default), and authors are ordered by name.
+
+The response body is a page object holding the authors under `items`
+along with `total`, `limit` and `offset`, with status code 200.
+
+**/
+pub async fn all_authors_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_all_authors(conn, &page)).await {
+        Ok(authors) => page_response(&authors),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/author/id/:id route. The response body is the author with status code
+200 and the author's version as the ETag (or an empty 304 if the
+request's If-None-Match already holds it), or an `author_not_found`
+error with status code 404.
+
+**/
+pub async fn author_by_id_handler(state: AppState, id: u32, if_none_match: Option<String>) -> Response<String> {
+    match state.run(move |conn| query_author_by_id(conn, id)).await {
+        Ok(author) => record_response(&author, author.version(), if_none_match.as_deref()),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/author/id/:id/books route. The response body is a JSON array of the
+books the author is credited on, each with the author's `role` on it,
+or an `author_not_found` error with status code 404.
+
+**/
+pub async fn books_by_author_id_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| query_books_by_author_id(conn, id)).await {
+        Ok(books) => json_response(StatusCode::OK, &books),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for post requests to the
+/create/author route. The body is an author without an id, holding a
+`name` and optionally `notes`.
+
+1. If the author is stored, the response has status code 201, the
+   stored author as the body and a Location header pointing at
+   /author/id/:id. Names are stored first name first, so
+   "Tolkien, J.R.R." is stored as "J.R.R. Tolkien".
+
+2. If the body isn't a valid author, the response is a 400 error, and
+   if an author with a matching name already exists a
+   `constraint_violation` error with status code 422.
+
+**/
+pub async fn create_author_handler(state: AppState, payload: String) -> Response<String> {
+    let author: Author = match parse_body(&payload) {
+        Ok(author) => author,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| create_author(conn, author)).await {
+        Ok(author) => {
+            let location = format!("/author/id/{}", author.id().unwrap_or_default());
+            created_response(location, &author, author.version())
+        }
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for put requests to the
+/update/author route, which replace the author with the id given in
+the body. Renaming an author rewrites the `author` text of the books
+they're credited on, which shows in each book's history with the
+X-Actor header as the actor. With an If-Match header, the author is only
+replaced if its current ETag matches, otherwise the response is a 412
+`precondition_failed` error.
+
+**/
+pub async fn update_author_handler(
+    state: AppState,
+    payload: String,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let author: Author = match parse_body(&payload) {
+        Ok(author) => author,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "author", author.id().unwrap_or_default(), &precondition)?;
+            let rows_changed = update_author_in_db(&tx, author, actor.as_deref())?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for delete requests to the
+/author/id/:id route. The response is an empty 204 whether or not the
+author existed, a `conflict` error with status code 409 if books are
+still credited to the author, or a 412 error if the If-Match header
+doesn't match the author's current ETag.
+
+**/
+pub async fn delete_author_handler(state: AppState, id: u32, if_match: Option<String>) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "author", id, &precondition)?;
+            let rows_changed = delete_author_by_id(&tx, id)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions generate the responses for get and put requests to the
+/book/id/:id/authors route, which reads or replaces the people credited
+on a book. The put body is a JSON array of credits, each naming an
+author by `author` id or by `name` (found or added the same way as the
+names in a book's `author` text) with a `role` of author, translator,
+editor or illustrator (author if left out):
+
+```json
+[{"author": 12}, {"name": "Richard Pevear", "role": "translator"}]
+```
+
+Both respond with the book's credits in order as a JSON array, with
+status code 200. Replacing the credits rewrites the book's `author`
+text to match, which goes into the book's history, so a put honors
+If-Match against the book's ETag. A
+book that doesn't exist gets a `book_not_found` error with status code
+404, and a bad credit a 400 error naming it.
+
+**/
+pub async fn book_credits_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| query_credits(conn, id)).await {
+        Ok(credits) => json_response(StatusCode::OK, &credits),
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn set_book_credits_handler(
+    state: AppState,
+    id: u32,
+    payload: String,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let requests: Vec<CreditRequest> = match parse_body(&payload) {
+        Ok(requests) => requests,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "book", id, &precondition)?;
+            let credits = set_credits(&tx, id, requests, actor.as_deref())?;
+            tx.commit()?;
+            Ok(credits)
+        })
+        .await;
+    match result {
+        Ok(credits) => json_response(StatusCode::OK, &credits),
+        Err(error) => error.to_response(),
+    }
+}
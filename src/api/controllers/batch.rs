@@ -0,0 +1,74 @@
+use rusqlite::TransactionBehavior;
+use serde::Serialize;
+use warp::http::{Response, StatusCode};
+
+use super::common::{json_response, parse_body};
+use crate::api::error::ErrorBody;
+use crate::api::models::batch::*;
+use crate::api::state::AppState;
+
+/// The body of the error response for a failed batch.
+#[derive(Serialize, Debug)]
+struct BatchFailure {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<usize>,
+    #[serde(flatten)]
+    error: ErrorBody,
+}
+
+/**
+
+This function generates a response for post requests to the /batch
+route. The body is a JSON array of operations on books and readings,
+see `models::batch` for what they look like, and they all run in one
+transaction. The response will be either:
+
+1. A JSON array with what each operation did, in order, with status
+   code 200. Each result has the `op`, the `type` and the `id` of the
+   record, and creates and updates also the `record` as stored.
+
+2. The error of the first operation that failed, with that error's
+   status code and the `index` of the operation added to its body.
+   Nothing in the batch is written. An operation whose `version`
+   doesn't match its record gets a 412 `precondition_failed` error,
+   and a `$ref` to a name no earlier create has gets an
+   `invalid_field` error on `$ref`, and deleting a record that doesn't
+   exist or is already in the trash a 404 `book_not_found` or
+   `reading_not_found` error.
+
+3. An `invalid_body` error with status code 400, without an `index`,
+   if the body isn't a list of operations or has more than 100 of them.
+
+**/
+pub async fn batch_handler(state: AppState, payload: String, actor: Option<String>) -> Response<String> {
+    let operations: Vec<Operation> = match parse_body(&payload) {
+        Ok(operations) => operations,
+        Err(error) => return error.to_response(),
+    };
+
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let outcome = run_batch(&tx, operations, actor.as_deref());
+            // Dropping the transaction without committing rolls the batch back
+            if outcome.is_ok() {
+                tx.commit()?;
+            }
+            Ok(outcome)
+        })
+        .await;
+    match result {
+        Ok(Ok(results)) => json_response(StatusCode::OK, &results),
+        Ok(Err(failed)) => {
+            if failed.error.status().is_server_error() {
+                println!("{:#?}", failed);
+            }
+            let failure = BatchFailure {
+                index: failed.index,
+                error: failed.error.to_body(),
+            };
+            json_response(failed.error.status(), &failure)
+        }
+        Err(error) => error.to_response(),
+    }
+}
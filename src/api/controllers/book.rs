@@ -1,7 +1,114 @@
-use serde_json::ser;
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
 use warp::http::{Response, StatusCode};
 
+use super::common::{
+    created_response, decode_path_param, json_response, no_content_response, page_response,
+    parse_body, parse_fuzzy_flag, parse_min_score, parse_page_params, record_response, parse_sort_direction,
+};
+use crate::api::error::ApiError;
+use crate::api::models::fuzzy::{fuzzy_books, FuzzyField};
+use crate::api::models::isbn::Isbn;
+use crate::api::models::patch::Patch;
+use crate::api::models::version::{self, Precondition};
 use crate::api::models::book::*;
+use crate::api::models::common::Page;
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for any get requests to the
+/book/all route. The query parameters are all optional:
+
+- `limit` and `offset` select the page (25 books from the start by default).
+- `sort` is one of id, title, author, rating or pages (id by default).
+- `order` is either asc or desc (asc by default).
+
+The response body is a page object holding the books under `items`
+along with `total`, `limit` and `offset`, with status code 200. Bad
+parameters get a 400 `invalid_parameter` error naming the parameter.
+
+**/
+pub async fn all_books_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    match all_books(state, params).await {
+        Ok(books) => page_response(&books),
+        Err(error) => error.to_response(),
+    }
+}
+
+async fn all_books(state: AppState, params: HashMap<String, String>) -> Result<Page<Book>, ApiError> {
+    let page = parse_page_params(&params)?;
+    let direction = parse_sort_direction(&params)?;
+    let sort = BOOK_FIELDS.sortable(params.get("sort").map_or("id", String::as_str))?;
+
+    state.run(move |conn| query_all_books(conn, &page, sort, direction)).await
+}
+
+/**
+
+These functions generate the responses for get requests to the
+/book/title/:title and /book/author/:author routes. The parameter is
+percent-decoded and matched case-insensitively against any part of the
+title or author, so /book/author/le%20guin finds "Ursula K. Le Guin".
+
+With `?fuzzy=true` the parameter is matched loosely instead, so
+/book/author/dostoyevsky?fuzzy=true finds "Fyodor Dostoevsky", and each
+book comes with a `score` from 0 to 1. `min_score` (0.5 by default)
+drops worse matches, and the best matches come first.
+
+The response body is a JSON array of the matching books (possibly
+empty) with status code 200, or a 400 error if the parameter can't be
+decoded or a query parameter is bad.
+
+**/
+pub async fn books_by_title_handler(
+    state: AppState,
+    title: String,
+    params: HashMap<String, String>,
+) -> Response<String> {
+    books_matching_handler(state, title, params, FuzzyField::Title, query_books_by_title).await
+}
+
+pub async fn books_by_author_handler(
+    state: AppState,
+    author: String,
+    params: HashMap<String, String>,
+) -> Response<String> {
+    books_matching_handler(state, author, params, FuzzyField::Author, query_books_by_author).await
+}
+
+async fn books_matching_handler(
+    state: AppState,
+    param: String,
+    params: HashMap<String, String>,
+    field: FuzzyField,
+    query: fn(&rusqlite::Connection, &str) -> Result<Vec<Book>, ApiError>,
+) -> Response<String> {
+    let term = match decode_path_param("term", &param) {
+        Ok(term) => term,
+        Err(error) => return error.to_response(),
+    };
+    let fuzzy = match parse_fuzzy_flag(&params) {
+        Ok(fuzzy) => fuzzy,
+        Err(error) => return error.to_response(),
+    };
+
+    if fuzzy {
+        let min_score = match parse_min_score(&params) {
+            Ok(min_score) => min_score,
+            Err(error) => return error.to_response(),
+        };
+        return match state.run(move |conn| fuzzy_books(conn, field, &term, min_score)).await {
+            Ok(matches) => json_response(StatusCode::OK, &matches),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    match state.run(move |conn| query(conn, &term)).await {
+        Ok(books) => json_response(StatusCode::OK, &books),
+        Err(error) => error.to_response(),
+    }
+}
 
 /**
 
@@ -10,59 +117,126 @@ This function generates a response for any get requests to the
 
 1. If the id matches a book in the database, and the program does
    not produce any errors, the response body is the book record
-   in JSON form, with status code 200.
+   in JSON form, with status code 200 and the book's version as the
+   ETag. If the request's If-None-Match holds that ETag already, the
+   response is an empty 304 instead.
 
-2. If the does not match any book in the database, the response body
-   is a simple error message describing that no book was found, with
-   status code 404.
+2. If the does not match any book in the database, the response is a
+   `book_not_found` error with status code 404.
 
-3. If the program encounters any other problems, the response body
-   is the exception as a string, with status code 500.
+3. If the program encounters any other problems, the response is a
+   JSON error with status code 500 (or 503 if the database is busy).
 
 **/
-pub fn book_by_id_handler(id: u32) -> Response<String> {
-    let res_builder = Response::builder();
-    let maybe_book = query_book_by_id(id);
-
-    if maybe_book.is_err() {
-        let error = maybe_book.unwrap_err();
-        match error {
-            rusqlite::Error::QueryReturnedNoRows => res_builder
-                .status(StatusCode::NOT_FOUND)
-                .body(String::from("No book was found with that id"))
-                .unwrap(),
-            _ => res_builder
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(error.to_string())
-                .unwrap(),
-        }
-    } else {
-        let book = maybe_book.unwrap();
-        let json_str = ser::to_string(&book).unwrap();
-        let response = res_builder.status(StatusCode::OK).body(json_str).unwrap();
-        return response;
+pub async fn book_by_id_handler(state: AppState, id: u32, if_none_match: Option<String>) -> Response<String> {
+    match state.run(move |conn| query_book_by_id(conn, id)).await {
+        Ok(book) => record_response(&book, book.version(), if_none_match.as_deref()),
+        Err(error) => error.to_response(),
     }
 }
 
+/**
+
+These functions generate the responses for get requests to the
+/book/isbn/:isbn and /isbn/:isbn routes. Either form of ISBN is
+accepted, with or without hyphens.
+
+/book/isbn/:isbn responds with the book that has the ISBN the same way
+/book/id/:id does, including the ETag, or a `book_not_found` error with
+status code 404.
+
+/isbn/:isbn checks an ISBN without looking for a book, and responds
+with both of its forms, as in
+`{"isbn13": "9780306406157", "isbn10": "0306406152"}`. ISBN-13s that
+start with 979 have no ISBN-10, so theirs is null.
+
+Both respond with a 400 error naming the `isbn` parameter if it isn't a
+valid ISBN, saying what is wrong with it.
+
+**/
+pub async fn book_by_isbn_handler(state: AppState, isbn: String, if_none_match: Option<String>) -> Response<String> {
+    let isbn = match parse_isbn_param(&isbn) {
+        Ok(isbn) => isbn,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_book_by_isbn(conn, &isbn)).await {
+        Ok(book) => record_response(&book, book.version(), if_none_match.as_deref()),
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn isbn_handler(isbn: String) -> Response<String> {
+    match parse_isbn_param(&isbn) {
+        Ok(isbn) => json_response(StatusCode::OK, &isbn.forms()),
+        Err(error) => error.to_response(),
+    }
+}
+
+fn parse_on_readings(params: &HashMap<String, String>) -> Result<OnReadings, ApiError> {
+    match params.get("on_readings") {
+        None => Ok(OnReadings::Restrict),
+        Some(param) => OnReadings::from_param(param).ok_or_else(|| {
+            ApiError::invalid_parameter("on_readings", "on_readings must be one of: restrict, cascade, detach")
+        }),
+    }
+}
+
+fn parse_isbn_param(param: &str) -> Result<Isbn, ApiError> {
+    let text = decode_path_param("isbn", param)?;
+    Isbn::parse(&text).map_err(|message| ApiError::invalid_parameter("isbn", message))
+}
 
 /**
 
 This function generates a response for any delete requests to the
-/book/id/:id route. This response will be either:
+/book/id/:id route. The book is moved to the trash, where it can be
+restored from until it is purged (see /trash). The optional
+`on_readings` query parameter says what happens to the readings of the
+book: `restrict` (the default) refuses to delete a book that has
+readings, `cascade` sends them to the trash along with it and `detach`
+keeps them with a null `book`. This response will be either:
 
 1. A response with HTTP status 204, indicating that either the deletion
    was a success or there was no record by that id to begin with.
 
-2. A response with HTTP status 500 and a body with a string version
-   of the exception that caused the problem.
+2. A `conflict` error with HTTP status 409, if the book has readings
+   and `on_readings` is `restrict`. The message says how many.
 
-**/
+3. A `precondition_failed` error with HTTP status 412, if the request
+   has an If-Match header that doesn't match the book's current ETag.
+   Nothing is deleted.
+
+4. An `invalid_parameter` error with HTTP status 400, if `on_readings`
+   is something else.
 
-pub fn delete_book_handler(id: u32) -> Response<String> {
-    let res_builder = Response::builder();
-    let delete_result = delete_book_by_id(id);
+5. A JSON error response with HTTP status 500 describing the
+   exception that caused the problem.
 
-    match delete_result {
+**/
+pub async fn delete_book_handler(
+    state: AppState,
+    id: u32,
+    params: HashMap<String, String>,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let on_readings = match parse_on_readings(&params) {
+        Ok(on_readings) => on_readings,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "book", id, &precondition)?;
+            let changed_rows = delete_book_by_id(&tx, id, on_readings, actor.as_deref())?;
+            tx.commit()?;
+            Ok(changed_rows)
+        })
+        .await;
+    match result {
         Ok(changed_rows) => {
             let message: String;
             if changed_rows == 0 {
@@ -75,78 +249,143 @@ pub fn delete_book_handler(id: u32) -> Response<String> {
                     changed_rows
                 );
             }
-            res_builder
+            Response::builder()
                 .status(StatusCode::NO_CONTENT)
                 .header("RowsChanged", message)
                 .body(String::from(""))
                 .unwrap()
         }
-        Err(error) => res_builder
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(error.to_string())
-            .unwrap(),
+        Err(error) => error.to_response(),
     }
 }
 
-pub fn create_book_handler(payload: String) -> Response<String> {
-    let res_builder = Response::builder();
-    let maybe_book = serde_json::from_str(payload.as_str());
-    match maybe_book {
+/**
+
+This function generates a response for post requests to the
+/create/book route. The body must be a complete book without an id.
+
+1. If the book is stored, the response has status code 201, the stored
+   book (including its new id) as the body and a Location header
+   pointing at /book/id/:id.
+
+2. If the body isn't a valid book, the response is an `invalid_body`
+   error with status code 400, and if the book breaks one of the
+   table's constraints (an unknown medium, say) a
+   `constraint_violation` error with status code 422. A bad `isbn`
+   gets an `invalid_field` error, and one that another book already
+   has a `conflict` error with status code 409.
+
+3. If the program encounters any other problems, the response is a
+   JSON error with status code 500.
+
+**/
+pub async fn create_book_handler(state: AppState, payload: String, actor: Option<String>) -> Response<String> {
+    let book: Book = match parse_body(&payload) {
+        Ok(book) => book,
+        Err(error) => return error.to_response(),
+    };
+
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let book = create_book(&tx, book, actor.as_deref())?;
+            tx.commit()?;
+            Ok(book)
+        })
+        .await;
+    match result {
         Ok(book) => {
-            match write_book_to_db(book) {
-                Ok(rows_changed) => {
-                    res_builder
-                        .status(StatusCode::NO_CONTENT)
-                        .header("RowsChanged", rows_changed)
-                        .body(String::from(""))
-                        .unwrap()
-                }
-                Err(db_err) => {
-                    println!("{:#?}", db_err);
-                    res_builder
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(db_err.to_string())
-                        .unwrap()
-                }
-            }
-        }
-        Err(payload_err) => {
-            res_builder
-                .status(StatusCode::BAD_REQUEST)
-                .body(payload_err.to_string())
-                .unwrap()
+            let location = format!("/book/id/{}", book.id().unwrap_or_default());
+            created_response(location, &book, book.version())
         }
+        Err(error) => error.to_response(),
     }
+}
+
+/**
+
+This function generates a response for put requests to the
+/update/book route, which replace the book with the id given in the
+body. With an If-Match header, the book is only replaced if its
+current ETag matches, otherwise the response is a 412
+`precondition_failed` error. Giving the book another book's ISBN gets
+a `conflict` error with status code 409.
+
+**/
+pub async fn update_book_handler(
+    state: AppState,
+    payload: String,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let book: Book = match parse_body(&payload) {
+        Ok(book) => book,
+        Err(error) => return error.to_response(),
+    };
 
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "book", book.id().unwrap_or_default(), &precondition)?;
+            let rows_changed = update_book_in_db(&tx, book, actor.as_deref())?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
 }
 
-pub fn update_book_handler(payload: String) -> Response<String> {
-    let res_builder = Response::builder();
-    let maybe_book = serde_json::from_str(payload.as_str());
-    match maybe_book {
-        Ok(book) => {
-            match update_book_in_db(book) {
-                Ok(rows_changed) => {
-                    return res_builder
-                        .status(StatusCode::NO_CONTENT)
-                        .header("RowsChanged", rows_changed)
-                        .body(String::from(""))
-                        .unwrap()
-                }
-                Err(db_err) => {
-                    println!("{:#?}", db_err);
-                    return res_builder
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(db_err.to_string())
-                        .unwrap()
-                }
-            }
-        }
-        Err(payload_err) => {
-            return res_builder
-                .status(StatusCode::BAD_REQUEST)
-                .body(payload_err.to_string())
-                .unwrap()
-        }
+/**
+
+This function generates a response for patch requests to the
+/book/id/:id route, which change only some of a book's fields. The
+body is either a JSON merge patch or, with a Content-Type of
+application/json-patch+json, a list of JSON Patch operations.
+
+1. If the patch is applied, the response has status code 200, the
+   updated book as the body and its new version as the ETag. With an
+   If-Match header, the patch is only applied if it matches the
+   book's current ETag, otherwise the response is a 412 error.
+
+2. If there is no book with that id, the response is a
+   `book_not_found` error with status code 404.
+
+3. If the patch can't be read, or would leave the book with a missing
+   or badly typed field, the response is a 400 error. A JSON Patch
+   whose `test` operation fails gets a `patch_conflict` error with
+   status code 409, and nothing is written, as does an `isbn` that
+   another book already has (with a `conflict` error).
+
+**/
+pub async fn patch_book_handler(
+    state: AppState,
+    id: u32,
+    content_type: Option<String>,
+    if_match: Option<String>,
+    actor: Option<String>,
+    body: Vec<u8>,
+) -> Response<String> {
+    let patch = match Patch::from_body(content_type.as_deref(), &body) {
+        Ok(patch) => patch,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "book", id, &precondition)?;
+            let book = patch_book(&tx, id, &patch, actor.as_deref())?;
+            tx.commit()?;
+            Ok(book)
+        })
+        .await;
+    match result {
+        Ok(book) => record_response(&book, book.version(), None),
+        Err(error) => error.to_response(),
     }
 }
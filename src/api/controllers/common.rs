@@ -0,0 +1,156 @@
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use warp::http::{Response, StatusCode};
+
+use crate::api::error::ApiError;
+use crate::api::models::common::{Page, PageParams, SortDirection, MAX_PAGE_LIMIT};
+use crate::api::models::fuzzy::DEFAULT_MIN_SCORE;
+use crate::api::models::version;
+
+/// Generates a response with `body` serialized as JSON.
+pub fn json_response<T: serde::Serialize>(status: StatusCode, body: &T) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(serde_json::ser::to_string(body).unwrap())
+        .unwrap()
+}
+
+/**
+Generates the 200 response for a single record, with the record's
+version as its ETag. A client whose `If-None-Match` already holds that
+version gets an empty 304 instead, since its copy is still current.
+*/
+pub fn record_response<T: serde::Serialize>(
+    record: &T,
+    version: u32,
+    if_none_match: Option<&str>,
+) -> Response<String> {
+    if version::none_match_hits(if_none_match, version) {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("ETag", version::etag(version))
+            .body(String::from(""))
+            .unwrap();
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("ETag", version::etag(version))
+        .body(serde_json::ser::to_string(record).unwrap())
+        .unwrap()
+}
+
+/// Generates the empty 204 response for a write, noting how many rows it changed.
+pub fn no_content_response(rows_changed: usize) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("RowsChanged", rows_changed)
+        .body(String::from(""))
+        .unwrap()
+}
+
+/// Reads a JSON request body, failing with an `invalid_body` error.
+pub fn parse_body<T: serde::de::DeserializeOwned>(payload: &str) -> Result<T, ApiError> {
+    Ok(serde_json::from_str(payload)?)
+}
+
+/**
+Reads the `limit` and `offset` query parameters shared by every listing
+route. Both are optional, and either one being present but unusable
+is an `invalid_parameter` error naming it.
+*/
+pub fn parse_page_params(params: &HashMap<String, String>) -> Result<PageParams, ApiError> {
+    let mut page = PageParams::default();
+
+    if let Some(limit) = params.get("limit") {
+        page.limit = match limit.parse() {
+            Ok(limit) if limit > 0 && limit <= MAX_PAGE_LIMIT => limit,
+            _ => {
+                return Err(ApiError::invalid_parameter(
+                    "limit",
+                    format!("limit must be a number between 1 and {}", MAX_PAGE_LIMIT),
+                ))
+            }
+        };
+    }
+    if let Some(offset) = params.get("offset") {
+        page.offset = offset
+            .parse()
+            .map_err(|_| ApiError::invalid_parameter("offset", "offset must be a positive number"))?;
+    }
+    Ok(page)
+}
+
+/// Reads the `min_score` query parameter of a fuzzy search, a number from 0 to 1.
+pub fn parse_min_score(params: &HashMap<String, String>) -> Result<f64, ApiError> {
+    match params.get("min_score") {
+        None => Ok(DEFAULT_MIN_SCORE),
+        Some(min_score) => min_score
+            .parse()
+            .ok()
+            .filter(|min_score: &f64| (0.0..=1.0).contains(min_score))
+            .ok_or_else(|| ApiError::invalid_parameter("min_score", "min_score must be a number between 0 and 1")),
+    }
+}
+
+/// Reads the `fuzzy` query parameter of a lookup, which is false unless given.
+pub fn parse_fuzzy_flag(params: &HashMap<String, String>) -> Result<bool, ApiError> {
+    match params.get("fuzzy").map(String::as_str) {
+        None | Some("false") => Ok(false),
+        Some("true") | Some("") => Ok(true),
+        Some(_) => Err(ApiError::invalid_parameter("fuzzy", "fuzzy must be either true or false")),
+    }
+}
+
+/// Reads the `order` query parameter, which defaults to ascending.
+pub fn parse_sort_direction(params: &HashMap<String, String>) -> Result<SortDirection, ApiError> {
+    match params.get("order") {
+        None => Ok(SortDirection::Asc),
+        Some(order) => SortDirection::from_param(order)
+            .ok_or_else(|| ApiError::invalid_parameter("order", "order must be one of: asc, desc")),
+    }
+}
+
+/**
+Generates the response for a listing, with the page as the JSON body
+and the total size of the listing repeated in the X-Total-Count header
+for clients that only care about the count.
+*/
+pub fn page_response<T: serde::Serialize>(page: &Page<T>) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("X-Total-Count", page.total)
+        .body(serde_json::ser::to_string(page).unwrap())
+        .unwrap()
+}
+
+/**
+Path parameters reach the handlers still percent-encoded, so a request
+for /book/title/The%20Dispossessed arrives as "The%20Dispossessed".
+This decodes them, failing if the result isn't valid UTF-8.
+*/
+pub fn decode_path_param(name: &str, param: &str) -> Result<String, ApiError> {
+    percent_decode_str(param)
+        .decode_utf8()
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| {
+            ApiError::invalid_parameter(name, format!("{} is not valid percent-encoded UTF-8", param))
+        })
+}
+
+/**
+Generates the 201 response for a newly created record, with the
+record as the JSON body, its URL in the Location header and its
+version as the ETag.
+*/
+pub fn created_response<T: serde::Serialize>(location: String, record: &T, version: u32) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header("Content-Type", "application/json")
+        .header("Location", location)
+        .header("ETag", version::etag(version))
+        .body(serde_json::ser::to_string(record).unwrap())
+        .unwrap()
+}
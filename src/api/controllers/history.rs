@@ -0,0 +1,102 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::Response;
+
+use super::common::{page_response, parse_page_params, record_response};
+use crate::api::models::book::revert_book;
+use crate::api::models::history::{query_history, Entity};
+use crate::api::models::reading::revert_reading;
+use crate::api::models::version::{self, Precondition};
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the
+/book/id/:id/history and /reading/id/:id/history routes, which list
+the revisions of the record, latest first. `limit` and `offset` select
+the page (25 revisions from the start by default).
+
+Each revision has its `revision` number, the `action` it was (create,
+update, delete, restore or revert), the `actor` from the X-Actor
+header of the request that made it, when it was `changed_at`, the
+`changes` it made as a list of fields with their values `from` and
+`to`, and the whole record `before` and `after`.
+
+Records in the trash, or purged from it, still have their history. A
+record that never existed gets a `book_not_found` or
+`reading_not_found` error with status code 404, and bad parameters a
+400 `invalid_parameter` error naming the parameter.
+
+**/
+pub async fn history_handler(
+    state: AppState,
+    entity: Entity,
+    id: u32,
+    params: HashMap<String, String>,
+) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_history(conn, entity, id, &page)).await {
+        Ok(history) => page_response(&history),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for post requests to the
+/book/id/:id/history/:revision/revert and
+/reading/id/:id/history/:revision/revert routes, which put the record
+back the way it was after that revision. The revert is itself recorded
+as a revision. The response will be either:
+
+1. The record as it is now stored with status code 200 and its new
+   version as the ETag. With an If-Match header, the record is only
+   reverted if it matches the record's current ETag, otherwise the
+   response is a 412 `precondition_failed` error.
+
+2. A `book_not_found`, `reading_not_found` or `revision_not_found`
+   error with status code 404. Records in the trash have to be
+   restored before they can be reverted.
+
+3. A `conflict` error with status code 409, if the revision deleted
+   the record, or if going back would give the book an ISBN another
+   book has taken since. Going back to a reading of a book that is now
+   in the trash gets an `invalid_field` error on `book`.
+
+**/
+pub async fn revert_handler(
+    state: AppState,
+    entity: Entity,
+    id: u32,
+    revision: u32,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, entity.as_str(), id, &precondition)?;
+            let reverted = match entity {
+                Entity::Book => {
+                    let book = revert_book(&tx, id, revision, actor.as_deref())?;
+                    (serde_json::to_value(&book)?, book.version())
+                }
+                Entity::Reading => {
+                    let reading = revert_reading(&tx, id, revision, actor.as_deref())?;
+                    (serde_json::to_value(&reading)?, reading.version())
+                }
+            };
+            tx.commit()?;
+            Ok(reverted)
+        })
+        .await;
+    match result {
+        Ok((record, version)) => record_response(&record, version, None),
+        Err(error) => error.to_response(),
+    }
+}
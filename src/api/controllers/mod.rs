@@ -1,3 +1,12 @@
+pub mod author;
+pub mod batch;
 pub mod book;
 pub mod reading;
 pub mod search;
+pub mod series;
+pub mod shelf;
+pub mod tag;
+pub mod trash;
+pub mod history;
+pub mod note;
+pub mod common;
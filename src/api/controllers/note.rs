@@ -0,0 +1,173 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::Response;
+
+use super::common::{created_response, no_content_response, page_response, parse_page_params, record_response};
+use crate::api::models::history::Entity;
+use crate::api::models::note::*;
+use crate::api::models::version::{self, Precondition};
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the
+/book/id/:id/notes and /reading/id/:id/notes routes, which list the
+record's notes, oldest first. `limit` and `offset` select the page (25
+notes from the start by default).
+
+Each note has its Markdown `body` and the `title`, `excerpt` and
+`word_count` worked out from it. A record that doesn't exist, or is in
+the trash, gets a `book_not_found` or `reading_not_found` error with
+status code 404, and bad parameters a 400 `invalid_parameter` error
+naming the parameter.
+
+**/
+pub async fn notes_handler(
+    state: AppState,
+    owner: Entity,
+    id: u32,
+    params: HashMap<String, String>,
+) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_notes(conn, owner, id, &page)).await {
+        Ok(notes) => page_response(&notes),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/note/id/:id route. The response will be either the note with status
+code 200 and its version as the ETag, or a `note_not_found` error with
+status code 404, also for the notes of records in the trash.
+
+**/
+pub async fn get_note_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| query_note_by_id(conn, id)).await {
+        Ok(note) => record_response(&note, note.version(), None),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for post requests to the
+/book/id/:id/notes and /reading/id/:id/notes routes, which add a note
+to the record. The body is the Markdown itself, sent as text/markdown
+or text/plain, or a JSON object with the Markdown as its `body`. The
+response will be either:
+
+1. The new note with status code 201, its URL in the Location header
+   and its version as the ETag.
+
+2. A `book_not_found` or `reading_not_found` error with status code
+   404, if the record doesn't exist or is in the trash.
+
+3. An `invalid_field` error on `body` with status code 400 if the note
+   is empty, or an `invalid_body` error if the body can't be read.
+
+4. A `payload_too_large` error with status code 413 if the body is
+   over the `note_body_limit_kb` setting.
+
+**/
+pub async fn create_note_handler(
+    state: AppState,
+    owner: Entity,
+    id: u32,
+    content_type: Option<String>,
+    body: Vec<u8>,
+) -> Response<String> {
+    let request = match NoteRequest::from_body(content_type.as_deref(), &body) {
+        Ok(request) => request,
+        Err(error) => return error.to_response(),
+    };
+
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let note = create_note(&tx, owner, id, request)?;
+            tx.commit()?;
+            Ok(note)
+        })
+        .await;
+    match result {
+        Ok(note) => created_response(format!("/note/id/{}", note.id()), &note, note.version()),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for put requests to the
+/note/id/:id route, which replace the note's Markdown with the body,
+given as for creating a note. The response will be either:
+
+1. The note as it is now stored with status code 200 and its new
+   version as the ETag. With an If-Match header, the note is only
+   changed if it matches the note's current ETag, otherwise the
+   response is a 412 `precondition_failed` error.
+
+2. A `note_not_found` error with status code 404.
+
+3. The same 400 and 413 errors as creating a note.
+
+**/
+pub async fn update_note_handler(
+    state: AppState,
+    id: u32,
+    content_type: Option<String>,
+    if_match: Option<String>,
+    body: Vec<u8>,
+) -> Response<String> {
+    let request = match NoteRequest::from_body(content_type.as_deref(), &body) {
+        Ok(request) => request,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "note", id, &precondition)?;
+            let note = update_note(&tx, id, request)?;
+            tx.commit()?;
+            Ok(note)
+        })
+        .await;
+    match result {
+        Ok(note) => record_response(&note, note.version(), None),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for delete requests to the
+/note/id/:id route. Notes don't go to the trash, they are erased. The
+response will be either an empty 204 response, a `note_not_found`
+error with status code 404, or a 412 `precondition_failed` error if an
+If-Match header doesn't match the note's ETag.
+
+**/
+pub async fn delete_note_handler(state: AppState, id: u32, if_match: Option<String>) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "note", id, &precondition)?;
+            query_note_by_id(&tx, id)?;
+            let rows_changed = delete_note_by_id(&tx, id)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
@@ -1,98 +1,443 @@
-use serde_json::ser;
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
 use warp::http::{Response, StatusCode};
 
+use super::common::{
+    created_response, decode_path_param, json_response, no_content_response, page_response,
+    parse_body, parse_fuzzy_flag, parse_min_score, parse_page_params, record_response,
+};
+use crate::api::error::ApiError;
+use crate::api::models::common::{normalize_date, PageParams};
+use crate::api::models::fuzzy::{fuzzy_readings, FuzzyField};
+use crate::api::models::patch::Patch;
+use crate::api::models::progress::{self, ProgressRequest};
+use crate::api::models::version::{self, Precondition};
 use crate::api::models::reading::*;
+use crate::api::state::AppState;
 
-pub fn reading_by_id_handler(id: u32) -> Response<String> {
-    let res_builder = Response::builder();
-    let maybe_reading = query_reading_by_id(id);
-
-    if maybe_reading.is_err() {
-        let error = maybe_reading.unwrap_err();
-        match error {
-            rusqlite::Error::QueryReturnedNoRows => res_builder
-                .status(StatusCode::NOT_FOUND)
-                .body(String::from("No reading was found with that id"))
-                .unwrap(),
-            _ => res_builder
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(error.to_string())
-                .unwrap(),
-        }
+/**
+
+This function generates a response for any get requests to the
+/reading/all route. The query parameters are all optional:
+
+- `limit` and `offset` select the page (25 readings from the start by default).
+- `started_after` keeps readings started on or after the given date.
+- `finished_before` keeps readings finished on or before the given date.
+- `in_progress=true` keeps only unfinished readings (no end date), and
+  `in_progress=false` only finished ones.
+- `expand=book` replaces each reading's book id with the book itself.
+
+The response body is a page object holding the readings under `items`
+along with `total`, `limit` and `offset`, with status code 200. Bad
+parameters get a 400 `invalid_parameter` error naming the parameter.
+
+**/
+pub async fn all_readings_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let (filter, page, expand) = match parse_listing_params(&params) {
+        Ok(parsed) => parsed,
+        Err(error) => return error.to_response(),
+    };
+
+    let result = if expand {
+        state
+            .run(move |conn| {
+                let readings = query_all_readings(conn, &filter, &page)?;
+                expand_readings(conn, readings).map(|expanded| page_response(&expanded))
+            })
+            .await
     } else {
-        let reading = maybe_reading.unwrap();
-        let json_str = ser::to_string(&reading).unwrap();
-        let response = res_builder.status(StatusCode::OK).body(json_str).unwrap();
-        return response;
+        state
+            .run(move |conn| query_all_readings(conn, &filter, &page).map(|page| page_response(&page)))
+            .await
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(error) => error.to_response(),
     }
 }
 
-pub fn delete_reading_handler(id: u32) -> Response<String> {
-    let res_builder = Response::builder();
-    let delete_result = delete_reading_by_id(id);
-    println!("{:#?}", delete_result);
-    match delete_result {
-        Ok(rows_changed) => res_builder
-            .status(StatusCode::NO_CONTENT)
-            .header("RowsChanged", rows_changed)
-            .body(String::from(""))
-            .unwrap(),
-        Err(error) => res_builder
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(error.to_string())
-            .unwrap(),
+fn parse_listing_params(
+    params: &HashMap<String, String>,
+) -> Result<(ReadingFilter, PageParams, bool), ApiError> {
+    let page = parse_page_params(params)?;
+    let mut filter = ReadingFilter::default();
+
+    if let Some(date) = params.get("started_after") {
+        let date = normalize_date(date).ok_or_else(|| {
+            ApiError::invalid_parameter(
+                "started_after",
+                format!("started_after is not a valid date: {}", date),
+            )
+        })?;
+        filter.started_after = Some(date);
+    }
+    if let Some(date) = params.get("finished_before") {
+        let date = normalize_date(date).ok_or_else(|| {
+            ApiError::invalid_parameter(
+                "finished_before",
+                format!("finished_before is not a valid date: {}", date),
+            )
+        })?;
+        filter.finished_before = Some(date);
+    }
+    if let Some(in_progress) = params.get("in_progress") {
+        let in_progress = in_progress
+            .parse()
+            .map_err(|_| ApiError::invalid_parameter("in_progress", "in_progress must be true or false"))?;
+        filter.in_progress = Some(in_progress);
+    }
+
+    let expand = match params.get("expand").map(String::as_str) {
+        None => false,
+        Some("book") => true,
+        Some(other) => {
+            return Err(ApiError::invalid_parameter(
+                "expand",
+                format!("Can't expand {:?}, only book", other),
+            ))
+        }
+    };
+
+    Ok((filter, page, expand))
+}
+
+/**
+
+These functions generate the responses for get requests to the
+/reading/title/:title and /reading/author/:author routes, which look
+up readings through the book they refer to. The parameter is
+percent-decoded and matched case-insensitively against any part of the
+book's title or author.
+
+With `?fuzzy=true` the parameter is matched loosely instead, forgiving
+typos and accents, and each reading comes with the `score` of its book
+from 0 to 1. `min_score` (0.5 by default) drops worse matches, and the
+best matches come first. See `models::fuzzy` for how scores are found.
+
+The response body is a JSON array of the matching readings (possibly
+empty) with status code 200, or a 400 error if the parameter can't be
+decoded or a query parameter is bad.
+
+**/
+pub async fn readings_by_title_handler(
+    state: AppState,
+    title: String,
+    params: HashMap<String, String>,
+) -> Response<String> {
+    readings_matching_handler(state, title, params, FuzzyField::Title, query_readings_by_book_title).await
+}
+
+pub async fn readings_by_author_handler(
+    state: AppState,
+    author: String,
+    params: HashMap<String, String>,
+) -> Response<String> {
+    readings_matching_handler(state, author, params, FuzzyField::Author, query_readings_by_book_author).await
+}
+
+async fn readings_matching_handler(
+    state: AppState,
+    param: String,
+    params: HashMap<String, String>,
+    field: FuzzyField,
+    query: fn(&rusqlite::Connection, &str) -> Result<Vec<Reading>, ApiError>,
+) -> Response<String> {
+    let term = match decode_path_param("term", &param) {
+        Ok(term) => term,
+        Err(error) => return error.to_response(),
+    };
+    let fuzzy = match parse_fuzzy_flag(&params) {
+        Ok(fuzzy) => fuzzy,
+        Err(error) => return error.to_response(),
+    };
+
+    if fuzzy {
+        let min_score = match parse_min_score(&params) {
+            Ok(min_score) => min_score,
+            Err(error) => return error.to_response(),
+        };
+        return match state.run(move |conn| fuzzy_readings(conn, field, &term, min_score)).await {
+            Ok(matches) => json_response(StatusCode::OK, &matches),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    match state.run(move |conn| query(conn, &term)).await {
+        Ok(readings) => json_response(StatusCode::OK, &readings),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions handle a single reading by id. GET responses carry the
+reading's version as the ETag and answer a matching If-None-Match with
+an empty 304. Deletes and updates honor If-Match, answering a stale
+ETag with a 412 `precondition_failed` error and leaving the reading as
+it was.
+
+**/
+pub async fn reading_by_id_handler(state: AppState, id: u32, if_none_match: Option<String>) -> Response<String> {
+    match state.run(move |conn| query_reading_by_id(conn, id)).await {
+        Ok(reading) => record_response(&reading, reading.version(), if_none_match.as_deref()),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for delete requests to the
+/reading/id/:id route, which moves the reading to the trash (see
+/trash). The response is an empty 204 whether or not the reading
+existed, or a 412 if If-Match doesn't match its current ETag.
+
+**/
+pub async fn delete_reading_handler(
+    state: AppState,
+    id: u32,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "reading", id, &precondition)?;
+            let changed_rows = delete_reading_by_id(&tx, id, actor.as_deref())?;
+            tx.commit()?;
+            Ok(changed_rows)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
     }
 }
 
-pub fn create_reading_handler(payload: String) -> Response<String> {
-    let res_builder = Response::builder();
-    let maybe_reading = serde_json::from_str(payload.as_str());
-    match maybe_reading {
-        Ok(reading) => match write_reading_to_db(reading) {
-            Ok(rows_changed) => res_builder
-                .status(StatusCode::NO_CONTENT)
-                .header("RowsChanged", rows_changed)
-                .body(String::from(""))
-                .unwrap(),
-            Err(db_err) => {
-                println!("{:#?}", db_err);
-                res_builder
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(db_err.to_string())
-                    .unwrap()
-            }
-        },
-        Err(payload_err) => {
-            println!("{:#?}", payload_err);
-            res_builder
-                .status(StatusCode::BAD_REQUEST)
-                .body(payload_err.to_string())
-                .unwrap()
+/**
+
+This function generates a response for post requests to the
+/create/reading route. The body must be a complete reading without an
+id. Dates may be given as YYYY-MM-DD or MM/DD/YYYY and are stored as
+YYYY-MM-DD.
+
+1. If the reading is stored, the response has status code 201, the
+   stored reading (including its new id) as the body and a Location
+   header pointing at /reading/id/:id.
+
+2. If the body isn't a valid reading, the response is an
+   `invalid_body` error with status code 400, or an `invalid_field`
+   error naming the date that couldn't be read, or `book` if the book
+   is missing or doesn't exist.
+
+3. If the program encounters any other problems, the response is a
+   JSON error with status code 500.
+
+**/
+pub async fn create_reading_handler(state: AppState, payload: String, actor: Option<String>) -> Response<String> {
+    let reading = match parse_reading(&payload) {
+        Ok(reading) => reading,
+        Err(error) => return error.to_response(),
+    };
+
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let reading = create_reading(&tx, reading, actor.as_deref())?;
+            tx.commit()?;
+            Ok(reading)
+        })
+        .await;
+    match result {
+        Ok(reading) => {
+            let location = format!("/reading/id/{}", reading.id().unwrap_or_default());
+            created_response(location, &reading, reading.version())
         }
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn update_reading_handler(
+    state: AppState,
+    payload: String,
+    if_match: Option<String>,
+    actor: Option<String>,
+) -> Response<String> {
+    let reading = match parse_reading_update(&payload) {
+        Ok(reading) => reading,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "reading", reading.id().unwrap_or_default(), &precondition)?;
+            let rows_changed = update_reading_in_db(&tx, reading, actor.as_deref())?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+A put replaces the whole reading, so a body without `book` is a
+mistake rather than a request to detach the reading from its book,
+which takes an explicit `"book": null`.
+*/
+fn parse_reading_update(payload: &str) -> Result<Reading, ApiError> {
+    let fields: serde_json::Map<String, serde_json::Value> = parse_body(payload)?;
+    if !fields.contains_key("book") {
+        return Err(ApiError::invalid_field(
+            "book",
+            "a reading needs the id of its book, or null to detach it from its book",
+        ));
+    }
+    parse_reading(payload)
+}
+
+fn parse_reading(payload: &str) -> Result<Reading, ApiError> {
+    let mut reading: Reading = parse_body(payload)?;
+    reading.normalize_dates()?;
+    Ok(reading)
+}
+
+/**
+
+This function generates a response for patch requests to the
+/reading/id/:id route, which change only some of a reading's fields. The
+body is either a JSON merge patch or, with a Content-Type of
+application/json-patch+json, a list of JSON Patch operations.
+
+1. If the patch is applied, the response has status code 200, the
+   updated reading as the body and its new version as the ETag. With
+   an If-Match header, the patch is only applied if it matches the
+   reading's current ETag, otherwise the response is a 412 error.
+
+2. If there is no reading with that id, the response is a
+   `reading_not_found` error with status code 404.
+
+3. If the patch can't be read, or would leave the reading with a missing
+   or badly typed field, the response is a 400 error. A JSON Patch
+   whose `test` operation fails gets a `patch_conflict` error with
+   status code 409, and nothing is written.
+
+**/
+pub async fn patch_reading_handler(
+    state: AppState,
+    id: u32,
+    content_type: Option<String>,
+    if_match: Option<String>,
+    actor: Option<String>,
+    body: Vec<u8>,
+) -> Response<String> {
+    let patch = match Patch::from_body(content_type.as_deref(), &body) {
+        Ok(patch) => patch,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "reading", id, &precondition)?;
+            let reading = patch_reading(&tx, id, &patch, actor.as_deref())?;
+            tx.commit()?;
+            Ok(reading)
+        })
+        .await;
+    match result {
+        Ok(reading) => record_response(&reading, reading.version(), None),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/reading/id/:id/progress route, which is the reading's progress log as
+a JSON array, oldest entry first. Each entry has the `page` or the
+`percent` reached, the time it was `logged_at` and an optional `note`.
+A reading that doesn't exist gets a `reading_not_found` error with
+status code 404.
+
+**/
+pub async fn reading_progress_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| progress::query_progress(conn, id)).await {
+        Ok(entries) => json_response(StatusCode::OK, &entries),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions generate the responses for post requests to the
+/reading/id/:id/progress route, which log progress, and delete requests
+to /reading/id/:id/progress/:entry, which take an entry back out.
+
+A post takes a body like `{"page": 120, "note": "halfway"}`, or
+`{"percent": 45.5}` for books without page numbers, and an optional
+`logged_at` that defaults to now. It responds with the stored entry and
+status code 201. An entry with both or neither of `page` and `percent`,
+a page past the end of the book or a time before the reading started
+gets an `invalid_field` error with status code 400.
+
+Both change the reading's version, since its `current_page`,
+`percent_complete` and `estimated_finish` follow its latest entry, and
+honor If-Match against it. A reading that doesn't exist or is in the
+trash gets a `reading_not_found` error with status code 404.
+
+**/
+pub async fn log_progress_handler(
+    state: AppState,
+    id: u32,
+    payload: String,
+    if_match: Option<String>,
+) -> Response<String> {
+    let request: ProgressRequest = match parse_body(&payload) {
+        Ok(request) => request,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "reading", id, &precondition)?;
+            let entry = progress::log_progress(&tx, id, request)?;
+            tx.commit()?;
+            Ok(entry)
+        })
+        .await;
+    match result {
+        Ok(entry) => json_response(StatusCode::CREATED, &entry),
+        Err(error) => error.to_response(),
     }
 }
 
-pub fn update_reading_handler(payload: String) -> Response<String> {
-    let res_builder = Response::builder();
-    let maybe_reading = serde_json::from_str(payload.as_str());
-    match maybe_reading {
-        Ok(reading) => match update_reading_in_db(reading) {
-            Ok(rows_changed) => res_builder
-                .status(StatusCode::NO_CONTENT)
-                .header("RowsChanged", rows_changed)
-                .body(String::from(""))
-                .unwrap(),
-            Err(db_err) => {
-                println!("{:#?}", db_err);
-                res_builder
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(db_err.to_string())
-                    .unwrap()
-            }
-        },
-        Err(payload_err) => res_builder
-            .status(StatusCode::BAD_REQUEST)
-            .body(payload_err.to_string())
-            .unwrap(),
+pub async fn delete_progress_handler(
+    state: AppState,
+    id: u32,
+    entry: u32,
+    if_match: Option<String>,
+) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "reading", id, &precondition)?;
+            let rows_changed = progress::delete_progress_entry(&tx, id, entry)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
     }
 }
@@ -1,107 +1,234 @@
-use serde_json::ser;
 use std::collections::HashMap;
 use warp::http::{Response, StatusCode};
 
+use super::common::{json_response, page_response, parse_min_score, parse_page_params};
+use crate::api::error::ApiError;
 use crate::api::models::book;
+use crate::api::models::common::PageParams;
+use crate::api::models::filter::{CompareOp, Filter, Value};
+use crate::api::models::fuzzy::{self, FuzzyField};
 use crate::api::models::reading;
+use crate::api::models::search::{FtsIndex, FtsQuery};
+use crate::api::state::AppState;
 
-enum SearchParam {
-    FilterBy,
-    Query
+/**
+
+These functions generate the responses for get requests to the
+/search/books and /search/readings routes, which search in one of
+several ways depending on the query parameters:
+
+- `q` is a full-text search of book titles, authors and notes, or of
+  reading notes. The response is a page of matches, best first, each
+  with a `snippet` of escaped HTML, the matches in `<mark>` tags, and
+  a `score`. `limit` and `offset` select the page and `weights`
+  (like `title:4,notes:1`) changes how much a match in each field
+  counts.
+
+- `filter` picks out records by their fields, as in
+  `rating >= 4 AND genre CONTAINS fantasy` (see `models::filter` for
+  the whole language). The response is a page of matches ordered by
+  id, with `limit` and `offset` selecting the page.
+
+- `fuzzy` finds the records whose book's title or author resembles it,
+  forgiving typos and accents, so `fuzzy=dostoyevsky` finds books by
+  "Fyodor Dostoevsky". `in` narrows the comparison to `title` or
+  `author` and `min_score` (0.5 by default) drops matches scoring
+  lower. The response is a JSON array of at most 50 matches, best
+  first, each with a `score` from 0 to 1.
+
+- `filterBy` and `query` together find the records whose `filterBy`
+  column exactly equals `query`, as a JSON array.
+
+Book searches also take `tag` and `shelf`, comma-separated names of
+tags and shelves a book has to have all of, as in
+`tag=fantasy,signed&shelf=to read`. They narrow a `filter` search or
+make one on their own, and can't be combined with `q` or `fuzzy`.
+
+Missing or bad parameters get a 400 error naming the parameter.
+
+**/
+pub async fn search_books_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let narrowing = match shelf_and_tag_params(&params) {
+        Ok(narrowing) => narrowing,
+        Err(error) => return error.to_response(),
+    };
+
+    if params.contains_key("q") {
+        let (query, page) = match full_text_params(&book::BOOK_INDEX, &params) {
+            Ok(search) => search,
+            Err(error) => return error.to_response(),
+        };
+        return match state.run(move |conn| book::search_books(conn, &query, &page)).await {
+            Ok(hits) => page_response(&hits),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    if params.contains_key("filter") || !narrowing.is_empty() {
+        let (filter, page) = match book_filter_params(&params, narrowing) {
+            Ok(search) => search,
+            Err(error) => return error.to_response(),
+        };
+        return match state.run(move |conn| book::query_books_matching(conn, &filter, &page)).await {
+            Ok(matches) => page_response(&matches),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    if let Some(term) = params.get("fuzzy") {
+        let (field, min_score) = match fuzzy_params(&params) {
+            Ok(search) => search,
+            Err(error) => return error.to_response(),
+        };
+        let term = term.to_owned();
+        return match state.run(move |conn| fuzzy::fuzzy_books(conn, field, &term, min_score)).await {
+            Ok(matches) => json_response(StatusCode::OK, &matches),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    let (filter_col, filter_query) = match search_params(&params) {
+        Ok(search) => search,
+        Err(error) => return error.to_response(),
+    };
+
+    let results = state
+        .run(move |conn| book::query_books_by_filter(conn, filter_col, filter_query))
+        .await;
+    match results {
+        Ok(results) => json_response(StatusCode::OK, &results),
+        Err(error) => error.to_response(),
+    }
 }
 
-pub fn search_books_handler(params: HashMap<String, String>) -> Response<String> {
-    let res_builder = Response::builder();
-
-    let param_check = params_are_valid(&params);
-    if param_check.is_err() {
-        let err = param_check.unwrap_err();
-        match err {
-            SearchParam::FilterBy => res_builder
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .body("Missing required parameter: filterBy".to_string())
-                .unwrap(),
-            SearchParam::Query => res_builder
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .body("Missing require parameter: query".to_string())
-                .unwrap()
-        }
-    } else {
-        let filter_col = params.get("filterBy").unwrap().to_owned();
-        let filter_query = params.get("query").unwrap().to_owned();
-        let results = book::query_books_by_filter(filter_col, filter_query);
-
-        if results.is_err() {
-            let err = results.unwrap_err();
-            match err {
-                rusqlite::Error::InvalidColumnName(col) => res_builder
-                    .status(StatusCode::NOT_FOUND)
-                    .body(format!("Invalid column name for query: {}", col))
-                    .unwrap(),
-                _ => res_builder
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(err.to_string())
-                    .unwrap(),
-            }
-        } else {
-            let encoded_results = ser::to_string(&results.unwrap()).unwrap();
-            res_builder
-                .status(StatusCode::OK)
-                .body(encoded_results)
-                .unwrap()
-        }
+pub async fn search_readings_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    if params.contains_key("q") {
+        let (query, page) = match full_text_params(&reading::READING_INDEX, &params) {
+            Ok(search) => search,
+            Err(error) => return error.to_response(),
+        };
+        return match state.run(move |conn| reading::search_readings(conn, &query, &page)).await {
+            Ok(hits) => page_response(&hits),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    if let Some(filter) = params.get("filter") {
+        let (filter, page) = match filter_params(filter, &params) {
+            Ok(search) => search,
+            Err(error) => return error.to_response(),
+        };
+        return match state.run(move |conn| reading::query_readings_matching(conn, &filter, &page)).await {
+            Ok(matches) => page_response(&matches),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    if let Some(term) = params.get("fuzzy") {
+        let (field, min_score) = match fuzzy_params(&params) {
+            Ok(search) => search,
+            Err(error) => return error.to_response(),
+        };
+        let term = term.to_owned();
+        return match state.run(move |conn| fuzzy::fuzzy_readings(conn, field, &term, min_score)).await {
+            Ok(matches) => json_response(StatusCode::OK, &matches),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    let (filter_col, filter_query) = match search_params(&params) {
+        Ok(search) => search,
+        Err(error) => return error.to_response(),
+    };
+
+    let results = state
+        .run(move |conn| reading::query_readings_by_filter(conn, filter_col, filter_query))
+        .await;
+    match results {
+        Ok(results) => json_response(StatusCode::OK, &results),
+        Err(error) => error.to_response(),
     }
 }
 
-pub fn search_readings_handler(params: HashMap<String, String>) -> Response<String> {
-    let res_builder = Response::builder();
-
-    let param_check = params_are_valid(&params);
-    if param_check.is_err() {
-        let err = param_check.unwrap_err();
-        match err {
-            SearchParam::FilterBy => res_builder
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .body("Missing required parameter: filterBy".to_string())
-                .unwrap(),
-            SearchParam::Query => res_builder
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .body("Missing require parameter: query".to_string())
-                .unwrap()
-        }
+/// Reads the `q`, `weights`, `limit` and `offset` parameters of a full-text search.
+fn full_text_params(
+    index: &FtsIndex,
+    params: &HashMap<String, String>,
+) -> Result<(FtsQuery, PageParams), ApiError> {
+    let text = params.get("q").ok_or(ApiError::MissingParameter("q"))?;
+    let query = FtsQuery::new(index, text, params.get("weights").map(String::as_str))?;
+    let page = parse_page_params(params)?;
+    Ok((query, page))
+}
+
+/// Parses a `filter` parameter, along with `limit` and `offset`.
+fn filter_params(filter: &str, params: &HashMap<String, String>) -> Result<(Filter, PageParams), ApiError> {
+    let filter = Filter::parse(filter)?;
+    let page = parse_page_params(params)?;
+    Ok((filter, page))
+}
+
+/// Reads a book search's `filter`, if any, and adds the `tag` and `shelf` conditions to it.
+fn book_filter_params(
+    params: &HashMap<String, String>,
+    mut narrowing: Vec<Filter>,
+) -> Result<(Filter, PageParams), ApiError> {
+    if let Some(filter) = params.get("filter") {
+        narrowing.insert(0, Filter::parse(filter)?);
+    }
+    let filter = if narrowing.len() == 1 {
+        narrowing.remove(0)
     } else {
-        let filter_col = params.get("filterBy").unwrap().to_owned();
-        let filter_query = params.get("query").unwrap().to_owned();
-        let results = reading::query_readings_by_filter(filter_col, filter_query);
-
-        if results.is_err() {
-            let err = results.unwrap_err();
-            match err {
-                rusqlite::Error::InvalidColumnName(col) => res_builder
-                    .status(StatusCode::NOT_FOUND)
-                    .body(format!("Invalid column name for query: {}", col))
-                    .unwrap(),
-                _ => res_builder
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(err.to_string())
-                    .unwrap(),
-            }
-        } else {
-            let encoded_results = ser::to_string(&results.unwrap()).unwrap();
-            res_builder
-                .status(StatusCode::OK)
-                .body(encoded_results)
-                .unwrap()
+        Filter::And(narrowing)
+    };
+    let page = parse_page_params(params)?;
+    Ok((filter, page))
+}
+
+/**
+Turns the `tag` and `shelf` parameters into a condition per name. They
+only go with `filter` searches, so with `q` or `fuzzy` they're an
+error rather than silently ignored.
+*/
+fn shelf_and_tag_params(params: &HashMap<String, String>) -> Result<Vec<Filter>, ApiError> {
+    let mut conditions = Vec::new();
+    for param in &["tag", "shelf"] {
+        let names = match params.get(*param) {
+            Some(names) => names,
+            None => continue,
+        };
+        if params.contains_key("q") || params.contains_key("fuzzy") {
+            return Err(ApiError::invalid_parameter(param, format!("{} can't be combined with q or fuzzy", param)));
+        }
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            conditions.push(Filter::Compare {
+                field: param.to_string(),
+                op: CompareOp::Eq,
+                value: Value::Text(name.to_string()),
+            });
         }
     }
+    Ok(conditions)
 }
 
-fn params_are_valid(params: &HashMap<String, String>) -> Result<(), SearchParam> {
-    if !params.contains_key("filterBy") {
-        Err(SearchParam::FilterBy)
-    } else if !params.contains_key("query") {
-        Err(SearchParam::Query)
-    } else {
-        Ok(())
-    }
+/// Reads the `in` and `min_score` parameters of a fuzzy search.
+fn fuzzy_params(params: &HashMap<String, String>) -> Result<(FuzzyField, f64), ApiError> {
+    let field = match params.get("in") {
+        None => FuzzyField::Any,
+        Some(field) => FuzzyField::from_param(field)
+            .ok_or_else(|| ApiError::invalid_parameter("in", "in must be one of: title, author, any"))?,
+    };
+    let min_score = parse_min_score(params)?;
+    Ok((field, min_score))
+}
+
+/// Reads the `filterBy` and `query` parameters, which are both required.
+fn search_params(params: &HashMap<String, String>) -> Result<(String, String), ApiError> {
+    let filter_col = params
+        .get("filterBy")
+        .ok_or(ApiError::MissingParameter("filterBy"))?;
+    let filter_query = params
+        .get("query")
+        .ok_or(ApiError::MissingParameter("query"))?;
+    Ok((filter_col.to_owned(), filter_query.to_owned()))
 }
@@ -0,0 +1,243 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::{Response, StatusCode};
+
+use super::common::{
+    created_response, json_response, no_content_response, page_response, parse_body, parse_page_params,
+    record_response,
+};
+use crate::api::models::series::*;
+use crate::api::models::version::{self, Precondition};
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the /series/all
+route. `limit` and `offset` select the page (25 series from the start
+by default), and series are ordered by name.
+
+**/
+pub async fn all_series_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_all_series(conn, &page)).await {
+        Ok(series) => page_response(&series),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/series/id/:id route. The response body is the series with status
+code 200 and the series' version as the ETag (or an empty 304 if the
+request's If-None-Match already holds it), or a `series_not_found`
+error with status code 404.
+
+**/
+pub async fn series_by_id_handler(state: AppState, id: u32, if_none_match: Option<String>) -> Response<String> {
+    match state.run(move |conn| query_series_by_id(conn, id)).await {
+        Ok(series) => record_response(&series, series.version(), if_none_match.as_deref()),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/series/id/:id/books route. The response body is a JSON array of the
+series' books in reading order, each with its `position`, or a
+`series_not_found` error with status code 404.
+
+**/
+pub async fn series_books_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| query_series_books(conn, id)).await {
+        Ok(books) => json_response(StatusCode::OK, &books),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions generate the responses for get requests to the
+/series/id/:id/next and /series/next routes, which suggest what to
+read next.
+
+/series/id/:id/next responds with the first book of the series, in
+reading order, that has no reading yet, with status code 200. If every
+book in the series has been started the response is an empty 204.
+
+/series/next responds with a JSON array holding the next unread book
+of every series at least one book of which has been read, as objects
+with the `series` and the `next` book.
+
+**/
+pub async fn next_in_series_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| next_unread(conn, id)).await {
+        Ok(Some(entry)) => json_response(StatusCode::OK, &entry),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(String::new())
+            .unwrap(),
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn next_in_started_series_handler(state: AppState) -> Response<String> {
+    match state.run(|conn| next_unread_in_started_series(conn)).await {
+        Ok(next) => json_response(StatusCode::OK, &next),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for post requests to the
+/create/series route. The body is a series without an id, holding a
+`name` and optionally `notes`. The response has status code 201, the
+stored series as the body and a Location header pointing at
+/series/id/:id, or a 400 error if the body isn't a valid series.
+
+**/
+pub async fn create_series_handler(state: AppState, payload: String) -> Response<String> {
+    let series: Series = match parse_body(&payload) {
+        Ok(series) => series,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| create_series(conn, series)).await {
+        Ok(series) => {
+            let location = format!("/series/id/{}", series.id().unwrap_or_default());
+            created_response(location, &series, series.version())
+        }
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for put requests to the
+/update/series route, which replace the series with the id given in
+the body. With an If-Match header, the series is only replaced if its
+current ETag matches, otherwise the response is a 412
+`precondition_failed` error.
+
+**/
+pub async fn update_series_handler(state: AppState, payload: String, if_match: Option<String>) -> Response<String> {
+    let series: Series = match parse_body(&payload) {
+        Ok(series) => series,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "series", series.id().unwrap_or_default(), &precondition)?;
+            let rows_changed = update_series_in_db(&tx, series)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for delete requests to the
+/series/id/:id route. The books of the series are kept. The response is
+an empty 204, or a 412 error if the If-Match header doesn't match the
+series' current ETag.
+
+**/
+pub async fn delete_series_handler(state: AppState, id: u32, if_match: Option<String>) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "series", id, &precondition)?;
+            let rows_changed = delete_series_by_id(&tx, id)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions generate the responses for put and delete requests to
+the /series/id/:id/books/:book route, which put a book in a series or
+take it out. The put body is optional and looks like
+`{"position": 2.5}`, leaving the position out puts the book in the
+series without a place in its order. Putting a book that's already in
+the series moves it.
+
+A put responds with the book and its position with status code 200,
+and a delete with an empty 204. Both change the series' version, and
+honor If-Match against it. A series or book that doesn't exist gets a
+404 error.
+
+**/
+pub async fn put_book_in_series_handler(
+    state: AppState,
+    id: u32,
+    book: u32,
+    payload: String,
+    if_match: Option<String>,
+) -> Response<String> {
+    let membership: Membership = if payload.trim().is_empty() {
+        Membership::default()
+    } else {
+        match parse_body(&payload) {
+            Ok(membership) => membership,
+            Err(error) => return error.to_response(),
+        }
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "series", id, &precondition)?;
+            let entry = put_book_in_series(&tx, id, book, &membership)?;
+            tx.commit()?;
+            Ok(entry)
+        })
+        .await;
+    match result {
+        Ok(entry) => json_response(StatusCode::OK, &entry),
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn remove_book_from_series_handler(
+    state: AppState,
+    id: u32,
+    book: u32,
+    if_match: Option<String>,
+) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "series", id, &precondition)?;
+            let rows_changed = remove_book_from_series(&tx, id, book)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
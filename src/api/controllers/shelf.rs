@@ -0,0 +1,217 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::{Response, StatusCode};
+
+use super::common::{
+    created_response, json_response, no_content_response, page_response, parse_body, parse_page_params,
+    record_response,
+};
+use crate::api::models::shelf::*;
+use crate::api::models::version::{self, Precondition};
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the /shelf/all
+route. `limit` and `offset` select the page (25 shelves from the start
+by default), and shelves are ordered by name.
+
+**/
+pub async fn all_shelves_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_all_shelves(conn, &page)).await {
+        Ok(shelves) => page_response(&shelves),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/shelf/id/:id route. The response body is the shelf with status code
+200 and the shelf's version as the ETag (or an empty 304 if the
+request's If-None-Match already holds it), or a `shelf_not_found` error
+with status code 404.
+
+**/
+pub async fn shelf_by_id_handler(state: AppState, id: u32, if_none_match: Option<String>) -> Response<String> {
+    match state.run(move |conn| query_shelf_by_id(conn, id)).await {
+        Ok(shelf) => record_response(&shelf, shelf.version(), if_none_match.as_deref()),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for get requests to the
+/shelf/id/:id/books route. The response body is a JSON array of the
+books on the shelf in order, or a `shelf_not_found` error with status
+code 404.
+
+**/
+pub async fn shelf_books_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| query_shelf_books(conn, id)).await {
+        Ok(books) => json_response(StatusCode::OK, &books),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for post requests to the
+/create/shelf route. The body is a shelf without an id, holding a
+`name` and optionally a `description`. The response has status code
+201, the stored shelf as the body and a Location header pointing at
+/shelf/id/:id, a 400 error if the body isn't a valid shelf, or a
+`constraint_violation` error with status code 422 if a shelf with that
+name (ignoring case) already exists.
+
+**/
+pub async fn create_shelf_handler(state: AppState, payload: String) -> Response<String> {
+    let shelf: Shelf = match parse_body(&payload) {
+        Ok(shelf) => shelf,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| create_shelf(conn, shelf)).await {
+        Ok(shelf) => {
+            let location = format!("/shelf/id/{}", shelf.id().unwrap_or_default());
+            created_response(location, &shelf, shelf.version())
+        }
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for put requests to the
+/update/shelf route, which replace the name and description of the
+shelf with the id given in the body. With an If-Match header, the
+shelf is only replaced if its current ETag matches, otherwise the
+response is a 412 `precondition_failed` error.
+
+**/
+pub async fn update_shelf_handler(state: AppState, payload: String, if_match: Option<String>) -> Response<String> {
+    let shelf: Shelf = match parse_body(&payload) {
+        Ok(shelf) => shelf,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "shelf", shelf.id().unwrap_or_default(), &precondition)?;
+            let rows_changed = update_shelf_in_db(&tx, shelf)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for delete requests to the
+/shelf/id/:id route. The books on the shelf are kept. The response is
+an empty 204, or a 412 error if the If-Match header doesn't match the
+shelf's current ETag.
+
+**/
+pub async fn delete_shelf_handler(state: AppState, id: u32, if_match: Option<String>) -> Response<String> {
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "shelf", id, &precondition)?;
+            let rows_changed = delete_shelf_by_id(&tx, id)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions generate the responses for post and put requests to
+the /shelf/id/:id/books route, which change the books on a shelf in
+bulk.
+
+A post adds and removes books by id, with a body like
+`{"add": [4, 8], "remove": [15]}`. Books are added to the end of the
+shelf in the order given, and books already on it stay where they are.
+
+A put replaces the books on the shelf with the JSON array of book ids
+in the body, in that order, which is also how a shelf is reordered.
+
+Both respond with the books on the shelf afterwards as a JSON array,
+with status code 200. They change the shelf's version and honor
+If-Match against it. A shelf that doesn't exist gets a
+`shelf_not_found` error with status code 404, and an id that isn't a
+book a 400 error naming it, in which case nothing changes.
+
+**/
+pub async fn change_shelf_books_handler(
+    state: AppState,
+    id: u32,
+    payload: String,
+    if_match: Option<String>,
+) -> Response<String> {
+    let changes: ShelfChanges = match parse_body(&payload) {
+        Ok(changes) => changes,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "shelf", id, &precondition)?;
+            let books = change_shelf_books(&tx, id, &changes)?;
+            tx.commit()?;
+            Ok(books)
+        })
+        .await;
+    match result {
+        Ok(books) => json_response(StatusCode::OK, &books),
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn set_shelf_books_handler(
+    state: AppState,
+    id: u32,
+    payload: String,
+    if_match: Option<String>,
+) -> Response<String> {
+    let books: Vec<u32> = match parse_body(&payload) {
+        Ok(books) => books,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "shelf", id, &precondition)?;
+            let books = replace_shelf_books(&tx, id, &books)?;
+            tx.commit()?;
+            Ok(books)
+        })
+        .await;
+    match result {
+        Ok(books) => json_response(StatusCode::OK, &books),
+        Err(error) => error.to_response(),
+    }
+}
@@ -0,0 +1,101 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::{Response, StatusCode};
+
+use super::common::{json_response, no_content_response, page_response, parse_body, parse_page_params};
+use crate::api::models::tag::*;
+use crate::api::models::version::{self, Precondition};
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the /tag/all
+route. `limit` and `offset` select the page (25 tags from the start by
+default), and tags are ordered by name, each with the number of
+`books` carrying it.
+
+**/
+pub async fn all_tags_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_all_tags(conn, &page)).await {
+        Ok(tags) => page_response(&tags),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for delete requests to the
+/tag/id/:id route, which takes the tag off every book, changing the
+version of each. The response is an empty 204 whether or not the tag
+existed.
+
+**/
+pub async fn delete_tag_handler(state: AppState, id: u32) -> Response<String> {
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let rows_changed = delete_tag_by_id(&tx, id)?;
+            tx.commit()?;
+            Ok(rows_changed)
+        })
+        .await;
+    match result {
+        Ok(rows_changed) => no_content_response(rows_changed),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+These functions generate the responses for get and put requests to the
+/book/id/:id/tags route, which reads or replaces a book's tags. The
+put body is a JSON array of tag names, like `["fantasy", "to read"]`,
+and names that aren't tags yet become new tags. Names ignore case.
+
+Both respond with the book's tag names in alphabetical order as a JSON
+array, with status code 200. Changing the tags changes the book's
+version, so a put honors If-Match against the book's ETag, and one
+that doesn't match gets a 412 `precondition_failed` error. A book that
+doesn't exist gets a
+`book_not_found` error with status code 404, and an empty name a 400
+error naming it.
+
+**/
+pub async fn book_tags_handler(state: AppState, id: u32) -> Response<String> {
+    match state.run(move |conn| query_book_tags(conn, id)).await {
+        Ok(tags) => json_response(StatusCode::OK, &tags),
+        Err(error) => error.to_response(),
+    }
+}
+
+pub async fn set_book_tags_handler(
+    state: AppState,
+    id: u32,
+    payload: String,
+    if_match: Option<String>,
+) -> Response<String> {
+    let names: Vec<String> = match parse_body(&payload) {
+        Ok(names) => names,
+        Err(error) => return error.to_response(),
+    };
+
+    let precondition = Precondition::from_if_match(if_match.as_deref());
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            version::check(&tx, "book", id, &precondition)?;
+            let tags = set_book_tags(&tx, id, names)?;
+            tx.commit()?;
+            Ok(tags)
+        })
+        .await;
+    match result {
+        Ok(tags) => json_response(StatusCode::OK, &tags),
+        Err(error) => error.to_response(),
+    }
+}
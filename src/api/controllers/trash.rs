@@ -0,0 +1,81 @@
+use rusqlite::TransactionBehavior;
+use std::collections::HashMap;
+use warp::http::{Response, StatusCode};
+
+use super::common::{json_response, page_response, parse_page_params};
+use crate::api::error::ApiError;
+use crate::api::models::trash::*;
+use crate::api::state::AppState;
+
+/**
+
+This function generates a response for get requests to the /trash
+route, which lists the deleted books and readings that haven't been
+purged yet, most recently deleted first. `limit` and `offset` select
+the page (25 records from the start by default), and `type`, either
+book or reading, leaves out the other kind.
+
+Each item has the `type` of the record, the time it was `deleted_at`
+and the `record` itself. Bad parameters get a 400 `invalid_parameter`
+error naming the parameter.
+
+**/
+pub async fn trash_handler(state: AppState, params: HashMap<String, String>) -> Response<String> {
+    let page = match parse_page_params(&params) {
+        Ok(page) => page,
+        Err(error) => return error.to_response(),
+    };
+    let kind = match params.get("type").map(String::as_str).map(parse_kind).transpose() {
+        Ok(kind) => kind,
+        Err(error) => return error.to_response(),
+    };
+
+    match state.run(move |conn| query_trash(conn, kind, &page)).await {
+        Ok(trash) => page_response(&trash),
+        Err(error) => error.to_response(),
+    }
+}
+
+/**
+
+This function generates a response for post requests to the
+/trash/:type/:id/restore route, which takes a book or a reading out of
+the trash. A book comes back with the readings that were deleted along
+with it. The response will be either:
+
+1. The restored record with status code 200.
+
+2. A `book_not_found` or `reading_not_found` error with status code
+   404, if there is no such record in the trash.
+
+3. A `conflict` error with status code 409, if the reading's book is
+   still in the trash, or another book has taken the book's ISBN since.
+
+4. An `invalid_parameter` error with status code 400, if :type is
+   neither book nor reading.
+
+**/
+pub async fn restore_handler(state: AppState, kind: String, id: u32, actor: Option<String>) -> Response<String> {
+    let kind = match parse_kind(&kind) {
+        Ok(kind) => kind,
+        Err(error) => return error.to_response(),
+    };
+
+    let result = state
+        .run(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let record = restore(&tx, kind, id, actor.as_deref())?;
+            tx.commit()?;
+            Ok(record)
+        })
+        .await;
+    match result {
+        Ok(record) => json_response(StatusCode::OK, &record),
+        Err(error) => error.to_response(),
+    }
+}
+
+fn parse_kind(param: &str) -> Result<TrashKind, ApiError> {
+    TrashKind::from_param(param)
+        .ok_or_else(|| ApiError::invalid_parameter("type", "type must be one of: book, reading"))
+}
@@ -0,0 +1,227 @@
+/*!
+
+# error
+
+`ApiError` is the one error type shared by the models and controllers.
+Each variant knows the HTTP status it maps to and a stable `code` that
+clients can match on without parsing messages. Every error response
+has the same JSON body:
+
+```json
+{"code": "book_not_found", "message": "No book was found with that id"}
+{"code": "invalid_field", "message": "rating must be between 1 and 5", "field": "rating"}
+```
+
+`field` is only present when the error is about one particular field
+or parameter of the request.
+
+!*/
+
+use rusqlite::ErrorCode;
+use serde::Serialize;
+use std::fmt;
+use warp::http::{Response, StatusCode};
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// There is no record of the named entity ("book", "reading") with the requested id.
+    NotFound(&'static str),
+    /// The request body couldn't be read as the expected JSON.
+    InvalidBody(String),
+    /// The request body is larger than the route's limit, in bytes.
+    PayloadTooLarge(u64),
+    /// The request body was readable but one of its fields has a bad value.
+    InvalidField { field: String, message: String },
+    /// A path or query parameter has a bad value.
+    InvalidParameter { param: String, message: String },
+    /// A required query parameter wasn't given.
+    MissingParameter(&'static str),
+    /// The request body is in a format the route doesn't accept.
+    UnsupportedMediaType(String),
+    /// A JSON Patch couldn't be applied to the record as it currently is,
+    /// a `test` operation failed or a path doesn't exist, say.
+    PatchConflict(String),
+    /// The request can't be carried out with the records as they are, like
+    /// deleting an author some books are still credited to.
+    Conflict(String),
+    /// The record's current version doesn't match the request's `If-Match`.
+    PreconditionFailed(String),
+    /// The write would break one of the database's constraints.
+    Constraint(String),
+    /// No database connection could be had, the server is overloaded or
+    /// the database file can't be opened.
+    Unavailable(String),
+    /// Any other database error.
+    Database(rusqlite::Error),
+    /// Something went wrong that isn't the client's fault or the database's.
+    Internal(String),
+}
+
+/// The JSON body of every error response.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl ApiError {
+    pub fn invalid_field(field: &str, message: impl Into<String>) -> ApiError {
+        ApiError::InvalidField {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_parameter(param: &str, message: impl Into<String>) -> ApiError {
+        ApiError::InvalidParameter {
+            param: param.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidBody(_)
+            | ApiError::InvalidField { .. }
+            | ApiError::InvalidParameter { .. }
+            | ApiError::MissingParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::PatchConflict(_) | ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::Constraint(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> String {
+        let code = match self {
+            ApiError::NotFound(entity) => return format!("{}_not_found", entity),
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::InvalidField { .. } => "invalid_field",
+            ApiError::InvalidParameter { .. } => "invalid_parameter",
+            ApiError::MissingParameter(_) => "missing_parameter",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::PatchConflict(_) => "patch_conflict",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::Constraint(_) => "constraint_violation",
+            ApiError::Unavailable(_) => "database_unavailable",
+            ApiError::Database(_) => "database_error",
+            ApiError::Internal(_) => "internal_error",
+        };
+        code.to_string()
+    }
+
+    /// The field or parameter the error is about, if any.
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            ApiError::InvalidField { field, .. } => Some(field),
+            ApiError::InvalidParameter { param, .. } => Some(param),
+            ApiError::MissingParameter(param) => Some(param),
+            _ => None,
+        }
+    }
+
+    pub fn to_body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            field: self.field().map(String::from),
+        }
+    }
+
+    pub fn to_response(&self) -> Response<String> {
+        if self.status().is_server_error() {
+            println!("{:#?}", self);
+        }
+        error_response(self.status(), &self.to_body())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::NotFound(entity) => write!(f, "No {} was found with that id", entity),
+            ApiError::InvalidBody(message) => write!(f, "Invalid request body: {}", message),
+            ApiError::InvalidField { message, .. } => write!(f, "{}", message),
+            ApiError::InvalidParameter { message, .. } => write!(f, "{}", message),
+            ApiError::MissingParameter(param) => write!(f, "Missing required parameter: {}", param),
+            ApiError::UnsupportedMediaType(message) => write!(f, "{}", message),
+            ApiError::PayloadTooLarge(limit) => write!(
+                f,
+                "The request body is too large, this route accepts at most {} kB",
+                limit / 1024
+            ),
+            ApiError::PatchConflict(message) => write!(f, "The patch could not be applied: {}", message),
+            ApiError::Conflict(message) => write!(f, "{}", message),
+            ApiError::PreconditionFailed(message) => write!(f, "Precondition failed: {}", message),
+            ApiError::Constraint(message) => write!(f, "Constraint violated: {}", message),
+            ApiError::Unavailable(message) => write!(f, "Database unavailable: {}", message),
+            ApiError::Database(err) => write!(f, "Database error: {}", err),
+            ApiError::Internal(message) => write!(f, "Internal error: {}", message),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            rusqlite::Error::SqliteFailure(ref failure, ref message)
+                if failure.code == ErrorCode::ConstraintViolation =>
+            {
+                ApiError::Constraint(message.clone().unwrap_or_else(|| failure.to_string()))
+            }
+            other => ApiError::Database(other),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::InvalidBody(err.to_string())
+    }
+}
+
+impl warp::reject::Reject for ApiError {}
+
+/// Builds a JSON error response from its parts.
+pub fn error_response(status: StatusCode, body: &ErrorBody) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(serde_json::ser::to_string(body).unwrap())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::common::test_connection;
+
+    #[test]
+    fn constraint_violations_are_client_errors() {
+        let conn = test_connection();
+        let err: ApiError = conn
+            .execute_batch("INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'scroll');")
+            .unwrap_err()
+            .into();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(err.code(), "constraint_violation");
+    }
+
+    #[test]
+    fn field_is_only_serialized_when_present() {
+        let not_found = serde_json::to_value(ApiError::NotFound("book").to_body()).unwrap();
+        assert_eq!(not_found["code"], "book_not_found");
+        assert!(not_found.get("field").is_none());
+
+        let missing = serde_json::to_value(ApiError::MissingParameter("query").to_body()).unwrap();
+        assert_eq!(missing["field"], "query");
+    }
+}
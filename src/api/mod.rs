@@ -1,2 +1,4 @@
+pub mod error;
 pub mod models;
 pub mod controllers;
+pub mod state;
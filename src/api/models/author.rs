@@ -0,0 +1,569 @@
+/*!
+
+# author
+
+Authors are records of their own, credited to books through the
+`book_author` table with a role: author, translator, editor or
+illustrator. A book can credit any number of people and a person can
+be credited on any number of books.
+
+A book's `author` text stays the readable summary of its credits, like
+`Fyodor Dostoevsky; Richard Pevear (translator)`, and the two are kept
+in step both ways. Writing a book's `author` re-credits the book from
+the text, finding each person by name or adding them, and changing a
+book's credits or renaming an author rewrites the text of the books
+involved.
+
+Names are matched on a key that ignores case, accents, punctuation and
+the "Last, First" order, so "Tolkien, J.R.R." and "J. R. R. Tolkien"
+are the same author. Splitting text into names is a heuristic: names
+are separated by `;`, `&` or "and", and by commas unless the comma
+looks like it's inverting a single name.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension, Row, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+
+use super::book::{query_book_by_id, Book, BOOK_COLUMNS};
+use super::common::{Page, PageParams};
+use super::fuzzy::fold;
+use super::history::{self, Action, Entity};
+use crate::api::error::ApiError;
+
+/// The roles a person can be credited with on a book.
+pub const ROLES: &[&str] = &["author", "translator", "editor", "illustrator"];
+
+/// Name suffixes that follow a comma without inverting the name.
+const NAME_SUFFIXES: &[&str] = &["jr", "sr", "ii", "iii", "iv"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Author {
+    // id is optional because it is missing in author creation
+    id: Option<u32>,
+    name: String,
+    #[serde(default)]
+    notes: Option<String>,
+    // Set by the database and bumped on every write, see `models::version`
+    #[serde(default)]
+    version: u32,
+}
+
+/// The columns `Author::from_row` expects, in order.
+pub const AUTHOR_COLUMNS: &str = "author.id, author.name, author.notes, author.version";
+
+/// A person credited on a book.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Credit {
+    pub author: u32,
+    pub name: String,
+    pub role: String,
+}
+
+/**
+One credit in a request to change a book's credits. The person is
+either an existing author's id or a name, which is looked up the same
+way names in a book's `author` text are and added if they're new.
+*/
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CreditRequest {
+    author: Option<u32>,
+    name: Option<String>,
+    #[serde(default = "default_role")]
+    role: String,
+}
+
+fn default_role() -> String {
+    ROLES[0].to_string()
+}
+
+/// A book an author is credited on, serialized as the book plus `role`.
+#[derive(Serialize, Debug)]
+pub struct CreditedBook {
+    #[serde(flatten)]
+    pub book: Book,
+    pub role: String,
+}
+
+impl Author {
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn from_row(row: &Row) -> Result<Author, rusqlite::Error> {
+        Ok(Author {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            notes: row.get(2)?,
+            version: row.get(3)?,
+        })
+    }
+
+    // Names are stored the way they're displayed, so "Tolkien, J.R.R." becomes "J.R.R. Tolkien"
+    fn normalized(mut self) -> Result<Author, ApiError> {
+        self.name = display_name(&self.name);
+        if name_key(&self.name).is_empty() {
+            return Err(ApiError::invalid_field("name", "name must hold a letter or digit"));
+        }
+        Ok(self)
+    }
+}
+
+/**
+Inserts the author and returns it as it was stored. An author whose
+name matches an existing author's breaks the table's unique key.
+*/
+pub fn create_author(conn: &Connection, author: Author) -> Result<Author, ApiError> {
+    let author = author.normalized()?;
+    conn.execute_named(
+        "INSERT INTO author (name, name_key, notes) VALUES (:name, :name_key, :notes);",
+        &[
+            (":name", &author.name),
+            (":name_key", &name_key(&author.name)),
+            (":notes", &author.notes),
+        ],
+    )?;
+    query_author_by_id(conn, conn.last_insert_rowid() as u32)
+}
+
+pub fn query_author_by_id(conn: &Connection, id: u32) -> Result<Author, ApiError> {
+    let sql = format!("SELECT {} FROM author WHERE id = :id;", AUTHOR_COLUMNS);
+    conn.query_row_named(&sql, &[(":id", &id)], Author::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("author"))
+}
+
+/// Returns one page of authors ordered by name, along with the total number of authors.
+pub fn query_all_authors(conn: &Connection, page: &PageParams) -> Result<Page<Author>, ApiError> {
+    let total: u32 = conn.query_row("SELECT count(*) FROM author;", NO_PARAMS, |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT {} FROM author ORDER BY name COLLATE NOCASE, id LIMIT :limit OFFSET :offset;",
+        AUTHOR_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":limit", &page.limit), (":offset", &page.offset)];
+    let items = stmt
+        .query_map_named(params, Author::from_row)?
+        .collect::<Result<Vec<Author>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/**
+Replaces the author with the id given in `author`, and rewrites the
+`author` text of every book crediting them if the name changed, which
+goes into those books' history as an update by `actor`.
+*/
+pub fn update_author_in_db(conn: &Connection, author: Author, actor: Option<&str>) -> Result<usize, ApiError> {
+    let author = author.normalized()?;
+    let rows_changed = conn.execute_named(
+        "UPDATE author SET name = :name, name_key = :name_key, notes = :notes, version = version + 1
+WHERE id = :id;",
+        &[
+            (":id", &author.id),
+            (":name", &author.name),
+            (":name_key", &name_key(&author.name)),
+            (":notes", &author.notes),
+        ],
+    )?;
+
+    let mut stmt = conn.prepare("SELECT DISTINCT book FROM book_author WHERE author = :author;")?;
+    let books = stmt
+        .query_map_named(&[(":author", &author.id)], |row| row.get(0))?
+        .collect::<Result<Vec<u32>, rusqlite::Error>>()?;
+    for book in books {
+        refresh_book_author(conn, book, actor)?;
+    }
+    Ok(rows_changed)
+}
+
+/**
+Deletes the author with the given id and returns the number of rows
+deleted. Authors still credited on a book can't be deleted, the book's
+credits have to change first.
+*/
+pub fn delete_author_by_id(conn: &Connection, id: u32) -> Result<usize, ApiError> {
+    let credited: u32 = conn.query_row_named(
+        "SELECT count(DISTINCT book) FROM book_author WHERE author = :id;",
+        &[(":id", &id)],
+        |row| row.get(0),
+    )?;
+    if credited > 0 {
+        return Err(ApiError::Conflict(format!(
+            "the author is still credited on {} book(s), change their credits first",
+            credited
+        )));
+    }
+    Ok(conn.execute_named("DELETE FROM author WHERE id = :id;", &[(":id", &id)])?)
+}
+
+/// Returns the books the author is credited on, with the role they have on each.
+pub fn query_books_by_author_id(conn: &Connection, id: u32) -> Result<Vec<CreditedBook>, ApiError> {
+    query_author_by_id(conn, id)?;
+    let sql = format!(
+        "SELECT {}, book_author.role FROM book JOIN book_author ON book_author.book = book.id
+WHERE book_author.author = :id AND book.deleted_at IS NULL
+ORDER BY book.title COLLATE NOCASE, book.id, book_author.position;",
+        BOOK_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let books = stmt
+        .query_map_named(&[(":id", &id)], |row| {
+            Ok(CreditedBook {
+                book: Book::from_row(row)?,
+                role: row.get("role")?,
+            })
+        })?
+        .collect::<Result<Vec<CreditedBook>, rusqlite::Error>>()?;
+    Ok(books)
+}
+
+/// Returns the people credited on the book, in the order they're credited.
+pub fn query_credits(conn: &Connection, book: u32) -> Result<Vec<Credit>, ApiError> {
+    query_book_by_id(conn, book)?;
+    Ok(credits_of(conn, book)?)
+}
+
+fn credits_of(conn: &Connection, book: u32) -> Result<Vec<Credit>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT author.id, author.name, book_author.role
+FROM book_author JOIN author ON author.id = book_author.author
+WHERE book_author.book = :book
+ORDER BY book_author.position;",
+    )?;
+    let credits = stmt
+        .query_map_named(&[(":book", &book)], |row| {
+            Ok(Credit {
+                author: row.get(0)?,
+                name: row.get(1)?,
+                role: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<Credit>, rusqlite::Error>>()?;
+    Ok(credits)
+}
+
+/**
+Replaces the credits of a book and rewrites its `author` text to match,
+returning the new credits. Callers should check the book's version
+first, since its text changes.
+*/
+pub fn set_credits(
+    conn: &Connection,
+    book: u32,
+    requests: Vec<CreditRequest>,
+    actor: Option<&str>,
+) -> Result<Vec<Credit>, ApiError> {
+    query_book_by_id(conn, book)?;
+
+    let mut credits: Vec<(u32, String)> = Vec::new();
+    for (i, request) in requests.into_iter().enumerate() {
+        let field = format!("[{}]", i);
+        if !ROLES.contains(&request.role.as_str()) {
+            return Err(ApiError::invalid_field(
+                &format!("{}.role", field),
+                format!("role must be one of: {}", ROLES.join(", ")),
+            ));
+        }
+        let author = match (request.author, request.name) {
+            (Some(id), None) => query_author_by_id(conn, id)?.id.unwrap_or_default(),
+            (None, Some(name)) if !name_key(&name).is_empty() => find_or_create(conn, &name)?,
+            _ => {
+                return Err(ApiError::invalid_field(
+                    &field,
+                    "each credit needs either the id of an author or a name",
+                ))
+            }
+        };
+        if !credits.contains(&(author, request.role.clone())) {
+            credits.push((author, request.role));
+        }
+    }
+
+    replace_credits(conn, book, &credits)?;
+    refresh_book_author(conn, book, actor)?;
+    Ok(credits_of(conn, book)?)
+}
+
+/**
+Credits the book with the people named in `text`, which is a book's
+`author` text, in place of whoever it credited before.
+*/
+pub fn link_book(conn: &Connection, book: u32, text: &str) -> Result<(), rusqlite::Error> {
+    let mut credits: Vec<(u32, String)> = Vec::new();
+    for (name, role) in parse_credits(text) {
+        let author = find_or_create(conn, &name)?;
+        if !credits.contains(&(author, role.to_string())) {
+            credits.push((author, role.to_string()));
+        }
+    }
+    replace_credits(conn, book, &credits)
+}
+
+fn replace_credits(conn: &Connection, book: u32, credits: &[(u32, String)]) -> Result<(), rusqlite::Error> {
+    conn.execute_named("DELETE FROM book_author WHERE book = :book;", &[(":book", &book)])?;
+    let mut insert = conn.prepare(
+        "INSERT INTO book_author (book, author, role, position) VALUES (:book, :author, :role, :position);",
+    )?;
+    for (position, (author, role)) in credits.iter().enumerate() {
+        insert.execute_named(&[
+            (":book", &book as &dyn rusqlite::ToSql),
+            (":author", author),
+            (":role", role),
+            (":position", &(position as u32)),
+        ])?;
+    }
+    Ok(())
+}
+
+// Rewrites a book's author text from its credits, leaving books without credits alone,
+// and records the rewrite in the book's history
+fn refresh_book_author(conn: &Connection, book: u32, actor: Option<&str>) -> Result<(), ApiError> {
+    let credits = credits_of(conn, book)?;
+    if credits.is_empty() {
+        return Ok(());
+    }
+    // Books in the trash are kept in step too, so this doesn't filter them out
+    let sql = format!("SELECT {} FROM book WHERE id = :id;", BOOK_COLUMNS);
+    let before = conn.query_row_named(&sql, &[(":id", &book)], Book::from_row)?;
+    let rows_changed = conn.execute_named(
+        "UPDATE book SET author = :author, version = version + 1 WHERE id = :id AND author != :author;",
+        &[(":id", &book as &dyn rusqlite::ToSql), (":author", &credits_text(&credits))],
+    )?;
+    if rows_changed > 0 {
+        let after = conn.query_row_named(&sql, &[(":id", &book)], Book::from_row)?;
+        history::record(conn, Entity::Book, book, Action::Update, Some(&before), Some(&after), actor)?;
+    }
+    Ok(())
+}
+
+/// Returns the id of the author with a name matching `name`, adding them if there is none.
+fn find_or_create(conn: &Connection, name: &str) -> Result<u32, rusqlite::Error> {
+    let name = display_name(name);
+    let key = name_key(&name);
+    let existing: Option<u32> = conn
+        .query_row_named("SELECT id FROM author WHERE name_key = :key;", &[(":key", &key)], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    match existing {
+        Some(id) => Ok(id),
+        None => {
+            conn.execute_named(
+                "INSERT INTO author (name, name_key) VALUES (:name, :key);",
+                &[(":name", &name), (":key", &key)],
+            )?;
+            Ok(conn.last_insert_rowid() as u32)
+        }
+    }
+}
+
+/// The book `author` text for a list of credits, the inverse of `parse_credits`.
+pub fn credits_text(credits: &[Credit]) -> String {
+    let names: Vec<String> = credits
+        .iter()
+        .map(|credit| match credit.role.as_str() {
+            "author" => credit.name.clone(),
+            role => format!("{} ({})", credit.name, role),
+        })
+        .collect();
+    names.join("; ")
+}
+
+/**
+Splits a book's `author` text into names and the role each is credited
+with. A role is given in parentheses after the name, as in
+"Richard Pevear (translator)", and is "author" otherwise.
+*/
+pub fn parse_credits(text: &str) -> Vec<(String, &'static str)> {
+    let mut credits: Vec<(String, &'static str)> = Vec::new();
+    for part in split_names(text) {
+        let (name, role) = strip_role(part);
+        let name = display_name(name);
+        if name_key(&name).is_empty() {
+            continue;
+        }
+        let duplicate = credits
+            .iter()
+            .any(|(other, other_role)| *other_role == role && name_key(other) == name_key(&name));
+        if !duplicate {
+            credits.push((name, role));
+        }
+    }
+    credits
+}
+
+/// The key names are matched on, so that spelling variants of a name meet.
+pub fn name_key(name: &str) -> String {
+    fold(&display_name(name))
+}
+
+/// Turns "Last, First" into "First Last" and collapses whitespace.
+fn display_name(name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut parts = name.splitn(2, ',');
+    let (last, first) = match (parts.next(), parts.next()) {
+        (Some(last), Some(first)) if !is_suffix(first) => (last.trim(), first.trim()),
+        _ => return name,
+    };
+    if first.is_empty() {
+        last.to_string()
+    } else {
+        format!("{} {}", first, last)
+    }
+}
+
+fn split_names(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    for part in text.split([';', '&']) {
+        for part in split_on_and(part) {
+            if looks_inverted(part) {
+                names.push(part);
+            } else {
+                names.extend(part.split(','));
+            }
+        }
+    }
+    names
+}
+
+// Splits on the word "and" in any case. ASCII lowercasing keeps byte offsets the same.
+fn split_on_and(text: &str) -> Vec<&str> {
+    let lower = text.to_ascii_lowercase();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    while let Some(found) = lower[start..].find(" and ") {
+        parts.push(&text[start..start + found]);
+        start += found + " and ".len();
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/**
+Guesses whether a comma in `text` separates two names or inverts one,
+as in "Tolkien, J.R.R.". It's one name if there is a single comma and
+either side is a single word, the right side holds an initial, or the
+right side is a suffix like "Jr.".
+*/
+fn looks_inverted(text: &str) -> bool {
+    let mut parts = text.split(',');
+    let (left, right) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(left), Some(right), None) => (left, right),
+        _ => return false,
+    };
+    let words = |side: &str| side.split_whitespace().count();
+    let has_initial = right
+        .split_whitespace()
+        .any(|word| word.trim_end_matches('.').chars().count() == 1 || word.matches('.').count() > 1);
+    words(left) == 1 || words(right) == 1 || has_initial || is_suffix(right)
+}
+
+fn is_suffix(text: &str) -> bool {
+    let word = text.trim().trim_end_matches('.').to_lowercase();
+    NAME_SUFFIXES.contains(&word.as_str())
+}
+
+fn strip_role(text: &str) -> (&str, &'static str) {
+    let text = text.trim();
+    if let Some(open) = text.rfind('(') {
+        if text.ends_with(')') {
+            let role = match text[open + 1..text.len() - 1].trim().to_lowercase().as_str() {
+                "author" => Some("author"),
+                "translator" | "trans." | "tr." => Some("translator"),
+                "editor" | "ed." | "eds." | "editors" => Some("editor"),
+                "illustrator" | "ill." | "illus." => Some("illustrator"),
+                _ => None,
+            };
+            if let Some(role) = role {
+                return (text[..open].trim(), role);
+            }
+        }
+    }
+    (text, "author")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::common::test_connection;
+
+    fn names(text: &str) -> Vec<(String, &'static str)> {
+        parse_credits(text)
+    }
+
+    #[test]
+    fn author_text_is_split_into_credits() {
+        assert_eq!(names("Tolkien, J.R.R."), vec![("J.R.R. Tolkien".to_string(), "author")]);
+        assert_eq!(
+            names("Neil Gaiman, Terry Pratchett"),
+            vec![("Neil Gaiman".to_string(), "author"), ("Terry Pratchett".to_string(), "author")]
+        );
+        assert_eq!(
+            names("Fyodor Dostoevsky; Richard Pevear (translator) and Larissa Volokhonsky (trans.)"),
+            vec![
+                ("Fyodor Dostoevsky".to_string(), "author"),
+                ("Richard Pevear".to_string(), "translator"),
+                ("Larissa Volokhonsky".to_string(), "translator"),
+            ]
+        );
+        assert_eq!(names("Le Guin, Ursula K."), vec![("Ursula K. Le Guin".to_string(), "author")]);
+        assert_eq!(names("Martin Luther King, Jr."), vec![("Martin Luther King, Jr.".to_string(), "author")]);
+        assert_eq!(name_key("Tolkien, J.R.R."), name_key("J. R. R. Tolkien"));
+    }
+
+    #[test]
+    fn books_and_authors_stay_in_step() {
+        let conn = test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('The Hobbit', 'Tolkien, J.R.R.', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('The Silmarillion', 'J. R. R. Tolkien', 'paper');",
+        )
+        .unwrap();
+        link_book(&conn, 1, "Tolkien, J.R.R.").unwrap();
+        link_book(&conn, 2, "J. R. R. Tolkien").unwrap();
+
+        let credits = query_credits(&conn, 1).unwrap();
+        assert_eq!(credits, query_credits(&conn, 2).unwrap());
+        let tolkien = credits[0].author;
+        assert_eq!(query_books_by_author_id(&conn, tolkien).unwrap().len(), 2);
+
+        let requests = vec![
+            CreditRequest { author: Some(tolkien), name: None, role: default_role() },
+            CreditRequest { author: None, name: Some("Alan Lee".to_string()), role: "illustrator".to_string() },
+        ];
+        set_credits(&conn, 1, requests, Some("ana")).unwrap();
+        let author_of = |id| serde_json::to_value(query_book_by_id(&conn, id).unwrap()).unwrap()["author"].clone();
+        assert_eq!(author_of(1), "J.R.R. Tolkien; Alan Lee (illustrator)");
+
+        let mut renamed = query_author_by_id(&conn, tolkien).unwrap();
+        renamed.name = "John Ronald Reuel Tolkien".to_string();
+        update_author_in_db(&conn, renamed, Some("ben")).unwrap();
+        assert_eq!(author_of(2), "John Ronald Reuel Tolkien");
+
+        // Both rewrites of the author text are in the books' history
+        let latest = |id| {
+            let history = history::query_history(&conn, Entity::Book, id, &PageParams::default()).unwrap();
+            serde_json::to_value(&history.items[0]).unwrap()
+        };
+        assert_eq!(latest(1)["actor"], "ben");
+        assert_eq!(latest(1)["before"]["author"], "J.R.R. Tolkien; Alan Lee (illustrator)");
+        assert_eq!(latest(2)["changes"][0]["from"], "J. R. R. Tolkien");
+        assert_eq!(latest(2)["changes"][0]["to"], "John Ronald Reuel Tolkien");
+
+        assert!(matches!(delete_author_by_id(&conn, tolkien), Err(ApiError::Conflict(_))));
+        conn.execute_batch("DELETE FROM book;").unwrap();
+        assert_eq!(delete_author_by_id(&conn, tolkien).unwrap(), 1);
+    }
+}
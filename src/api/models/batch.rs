@@ -0,0 +1,311 @@
+/*!
+
+# batch
+
+Many writes to books and readings in one request. A batch is an
+ordered list of operations that run one after the other in a single
+transaction: either every operation succeeds and the batch is
+committed, or the first one to fail stops it and nothing is written.
+
+Each operation says what it does (`op`: create, update or delete) to
+which `type` of record (book or reading), and takes:
+
+- for a create, the new `record`, and optionally a `ref` naming it so
+  later operations can use its id
+- for an update, the `id` and a `record` holding a JSON merge patch of
+  the fields to change, as for PATCH /book/id/:id
+- for a delete, the `id` and, for a book, what to do `on_readings`
+  (see `book::OnReadings`), restrict by default
+
+Updates and deletes can give the `version` the record should be at,
+which works like an If-Match header. Wherever a later operation needs
+an id, `{"$ref": "name"}` stands for the id of the record created by
+the operation with that `ref`:
+
+```json
+[
+  {"op": "create", "type": "book", "ref": "dune", "record": {"title": "Dune", "author": "Frank Herbert", "medium": "paper"}},
+  {"op": "create", "type": "reading", "record": {"book": {"$ref": "dune"}, "start_date": "2020-01-01"}}
+]
+```
+
+!*/
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use super::book::{create_book, delete_book_by_id, patch_book, Book, OnReadings};
+use super::history::Entity;
+use super::patch::Patch;
+use super::reading::{create_reading, delete_reading_by_id, patch_reading, Reading};
+use super::version::{self, Precondition};
+use crate::api::error::ApiError;
+
+/// The most operations one batch can hold.
+pub const MAX_OPERATIONS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Create,
+    Update,
+    Delete,
+}
+
+/// One operation of a batch, as the client sent it.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Operation {
+    op: Op,
+    #[serde(rename = "type")]
+    entity: Entity,
+    #[serde(default, rename = "ref")]
+    name: Option<String>,
+    #[serde(default)]
+    id: Option<Value>,
+    #[serde(default)]
+    record: Option<Value>,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    on_readings: Option<String>,
+}
+
+/// What an operation did. Creates and updates come with the record as it was stored.
+#[derive(Serialize, Debug)]
+pub struct OperationResult {
+    op: Op,
+    #[serde(rename = "type")]
+    entity: Entity,
+    id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<Value>,
+}
+
+/// The error that stopped a batch, with the index of the operation that
+/// failed, or no index if the batch was turned down as a whole.
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: Option<usize>,
+    pub error: ApiError,
+}
+
+/**
+Runs the operations in order and returns what each of them did. The
+first operation to fail stops the batch, and callers have to run it in
+a transaction they only commit if the whole batch succeeded.
+*/
+pub fn run_batch(
+    conn: &Connection,
+    operations: Vec<Operation>,
+    actor: Option<&str>,
+) -> Result<Vec<OperationResult>, BatchError> {
+    if operations.len() > MAX_OPERATIONS {
+        return Err(BatchError {
+            index: None,
+            error: ApiError::InvalidBody(format!("a batch can have at most {} operations", MAX_OPERATIONS)),
+        });
+    }
+
+    let mut refs = HashMap::new();
+    let mut results = Vec::new();
+    for (index, operation) in operations.into_iter().enumerate() {
+        let result = run_operation(conn, operation, &mut refs, actor).map_err(|error| BatchError {
+            index: Some(index),
+            error,
+        })?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+fn run_operation(
+    conn: &Connection,
+    mut operation: Operation,
+    refs: &mut HashMap<String, u32>,
+    actor: Option<&str>,
+) -> Result<OperationResult, ApiError> {
+    if let Some(id) = &mut operation.id {
+        resolve_refs(id, refs)?;
+    }
+    if let Some(record) = &mut operation.record {
+        resolve_refs(record, refs)?;
+    }
+    if let Some(name) = &operation.name {
+        if operation.op != Op::Create {
+            return Err(ApiError::invalid_field("ref", "only creates can have a ref"));
+        }
+        if refs.contains_key(name) {
+            return Err(ApiError::invalid_field("ref", format!("an earlier operation already has the ref {}", name)));
+        }
+    }
+
+    let (id, record) = match operation.op {
+        Op::Create => {
+            let record = operation
+                .record
+                .ok_or_else(|| ApiError::invalid_field("record", "a create needs the record to create"))?;
+            let (id, record) = match operation.entity {
+                Entity::Book => {
+                    let book: Book = serde_json::from_value(record)?;
+                    let book = create_book(conn, book, actor)?;
+                    (book.id().unwrap_or_default(), serde_json::to_value(&book)?)
+                }
+                Entity::Reading => {
+                    let mut reading: Reading = serde_json::from_value(record)?;
+                    reading.normalize_dates()?;
+                    let reading = create_reading(conn, reading, actor)?;
+                    (reading.id().unwrap_or_default(), serde_json::to_value(&reading)?)
+                }
+            };
+            if let Some(name) = operation.name {
+                refs.insert(name, id);
+            }
+            (id, Some(record))
+        }
+        Op::Update => {
+            let id = record_id(operation.id)?;
+            check_version(conn, operation.entity, id, operation.version)?;
+            let patch = match operation.record {
+                Some(record) if record.is_object() => Patch::Merge(record),
+                _ => {
+                    return Err(ApiError::invalid_field(
+                        "record",
+                        "an update needs a record holding the fields to change",
+                    ))
+                }
+            };
+            let record = match operation.entity {
+                Entity::Book => serde_json::to_value(&patch_book(conn, id, &patch, actor)?)?,
+                Entity::Reading => serde_json::to_value(&patch_reading(conn, id, &patch, actor)?)?,
+            };
+            (id, Some(record))
+        }
+        Op::Delete => {
+            let id = record_id(operation.id)?;
+            check_version(conn, operation.entity, id, operation.version)?;
+            let rows_changed = match operation.entity {
+                Entity::Book => {
+                    let on_readings = match operation.on_readings.as_deref() {
+                        None => OnReadings::Restrict,
+                        Some(param) => OnReadings::from_param(param).ok_or_else(|| {
+                            ApiError::invalid_field("on_readings", "on_readings must be one of: restrict, cascade, detach")
+                        })?,
+                    };
+                    delete_book_by_id(conn, id, on_readings, actor)?
+                }
+                Entity::Reading => delete_reading_by_id(conn, id, actor)?,
+            };
+            // Deleting a record that isn't there, or is already in the trash, fails the batch
+            if rows_changed == 0 {
+                return Err(ApiError::NotFound(operation.entity.as_str()));
+            }
+            (id, None)
+        }
+    };
+
+    Ok(OperationResult {
+        op: operation.op,
+        entity: operation.entity,
+        id,
+        record,
+    })
+}
+
+fn record_id(id: Option<Value>) -> Result<u32, ApiError> {
+    id.as_ref()
+        .and_then(Value::as_u64)
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| ApiError::invalid_field("id", "the operation needs the id of its record, a number or a $ref"))
+}
+
+fn check_version(conn: &Connection, entity: Entity, id: u32, version: Option<u32>) -> Result<(), ApiError> {
+    match version {
+        Some(version) => version::check(conn, entity.as_str(), id, &Precondition::OneOf(vec![version])),
+        None => Ok(()),
+    }
+}
+
+// Replaces every {"$ref": "name"} in the value with the id created under that name
+fn resolve_refs(value: &mut Value, refs: &HashMap<String, u32>) -> Result<(), ApiError> {
+    match value {
+        Value::Object(fields) if fields.len() == 1 && fields.contains_key("$ref") => {
+            let id = match &fields["$ref"] {
+                Value::String(name) => *refs.get(name).ok_or_else(|| {
+                    ApiError::invalid_field("$ref", format!("no earlier operation has the ref {}", name))
+                })?,
+                _ => return Err(ApiError::invalid_field("$ref", "a $ref is the ref of an earlier create")),
+            };
+            *value = Value::from(id);
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                resolve_refs(field, refs)?;
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                resolve_refs(item, refs)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::common::test_connection;
+    use rusqlite::NO_PARAMS;
+
+    fn operations(json: &str) -> Vec<Operation> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn later_operations_use_the_ids_of_earlier_creates() {
+        let mut conn = test_connection();
+        let tx = conn.transaction().unwrap();
+        let batch = operations(
+            r#"[
+                {"op": "create", "type": "book", "ref": "dune", "record": {"title": "Dune", "author": "Frank Herbert", "medium": "paper"}},
+                {"op": "create", "type": "reading", "ref": "first", "record": {"book": {"$ref": "dune"}, "start_date": "01/02/2020"}},
+                {"op": "update", "type": "book", "id": {"$ref": "dune"}, "version": 1, "record": {"rating": 5}},
+                {"op": "delete", "type": "reading", "id": {"$ref": "first"}}
+            ]"#,
+        );
+        let results = run_batch(&tx, batch, Some("ana")).unwrap();
+        assert_eq!(results[1].record.as_ref().unwrap()["start_date"], "2020-01-02");
+        assert_eq!(results[2].record.as_ref().unwrap()["rating"], 5);
+        assert_eq!((results[3].op, results[3].id), (Op::Delete, 1));
+        tx.commit().unwrap();
+
+        let tx = conn.transaction().unwrap();
+        let batch = operations(
+            r#"[
+                {"op": "create", "type": "book", "record": {"title": "Emma", "author": "Jane Austen", "medium": "paper"}},
+                {"op": "create", "type": "reading", "record": {"book": {"$ref": "emma"}, "start_date": "2020-01-01"}}
+            ]"#,
+        );
+        let failed = run_batch(&tx, batch, None).unwrap_err();
+        assert_eq!((failed.index, failed.error.field()), (Some(1), Some("$ref")));
+        drop(tx);
+
+        let tx = conn.transaction().unwrap();
+        let batch = operations(r#"[{"op": "delete", "type": "reading", "id": 1}]"#);
+        let failed = run_batch(&tx, batch, None).unwrap_err();
+        assert!(matches!((failed.index, failed.error), (Some(0), ApiError::NotFound("reading"))));
+        let too_many = (0..=MAX_OPERATIONS).map(|_| r#"{"op": "delete", "type": "book", "id": 1}"#);
+        let batch = operations(&format!("[{}]", too_many.collect::<Vec<&str>>().join(",")));
+        let failed = run_batch(&tx, batch, None).unwrap_err();
+        assert!(matches!((failed.index, failed.error), (None, ApiError::InvalidBody(_))));
+        drop(tx);
+
+        let books: u32 = conn.query_row("SELECT count(*) FROM book;", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(books, 1);
+    }
+}
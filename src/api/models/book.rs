@@ -1,8 +1,19 @@
+use rusqlite::{Connection, OptionalExtension, Row, NO_PARAMS};
 use serde::{Deserialize, Serialize};
 
+use super::author;
 use super::common;
+use super::common::{Page, PageParams, SortDirection};
+use super::fields::{EntityFields, Field, FieldType};
+use super::isbn::Isbn;
+use super::filter::{self, Filter};
+use super::history::{self, Action, Entity};
+use super::patch::{self, Patch};
+use super::reading::query_reading_by_id;
+use super::search::{self, FtsIndex, FtsQuery, SearchHit};
+use crate::api::error::ApiError;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Book {
     // id is optional because it is missing in book creation
     id: Option<u32>,
@@ -13,9 +24,130 @@ pub struct Book {
     medium: String,
     rating: Option<u32>,
     notes: Option<String>,
+    // Stored as the bare ISBN-13 whichever form it was given in, see `models::isbn`
+    #[serde(default)]
+    isbn: Option<String>,
+    #[serde(default)]
+    publisher: Option<String>,
+    #[serde(default)]
+    year: Option<i32>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    edition: Option<String>,
+    // Set by the database and bumped on every write, see `models::version`
+    #[serde(default)]
+    version: u32,
 }
 
-pub fn update_book_in_db(book: Book) -> Result<usize, rusqlite::Error> {
+/// The columns `Book::from_row` expects, in order. Qualified with the
+/// table name so the list also works in queries that join on book.
+pub const BOOK_COLUMNS: &str =
+    "book.id, book.title, book.author, book.pages, book.genre, book.medium, book.rating, book.notes, \
+     book.isbn, book.publisher, book.year, book.language, book.edition, book.version";
+
+/// The full-text index over books, with titles weighted highest.
+pub const BOOK_INDEX: FtsIndex = FtsIndex {
+    table: "book",
+    fts_table: "book_fts",
+    record_columns: BOOK_COLUMNS,
+    fields: &[("title", 10.0), ("author", 5.0), ("notes", 1.0)],
+};
+
+const BOOK_TAGS: &str = "SELECT 1 FROM book_tag JOIN tag ON tag.id = book_tag.tag WHERE book_tag.book = book.id";
+const BOOK_SHELVES: &str =
+    "SELECT 1 FROM shelf_book JOIN shelf ON shelf.id = shelf_book.shelf WHERE shelf_book.book = book.id";
+
+/// The fields of a book that searches can filter on and /book/all can sort by.
+pub const BOOK_FIELDS: EntityFields = EntityFields {
+    table: "book",
+    record_columns: BOOK_COLUMNS,
+    fields: &[
+        Field { name: "id", column: "book.id", kind: FieldType::Integer, filterable: true, sortable: true, via: None },
+        Field { name: "title", column: "book.title", kind: FieldType::Text, filterable: true, sortable: true, via: None },
+        Field { name: "author", column: "book.author", kind: FieldType::Text, filterable: true, sortable: true, via: None },
+        Field { name: "pages", column: "book.pages", kind: FieldType::Integer, filterable: true, sortable: true, via: None },
+        Field { name: "genre", column: "book.genre", kind: FieldType::Text, filterable: true, sortable: false, via: None },
+        Field { name: "medium", column: "book.medium", kind: FieldType::Text, filterable: true, sortable: false, via: None },
+        Field { name: "rating", column: "book.rating", kind: FieldType::Integer, filterable: true, sortable: true, via: None },
+        Field { name: "notes", column: "book.notes", kind: FieldType::Text, filterable: true, sortable: false, via: None },
+        Field { name: "isbn", column: "book.isbn", kind: FieldType::Isbn, filterable: true, sortable: false, via: None },
+        Field { name: "publisher", column: "book.publisher", kind: FieldType::Text, filterable: true, sortable: true, via: None },
+        Field { name: "year", column: "book.year", kind: FieldType::Integer, filterable: true, sortable: true, via: None },
+        Field { name: "language", column: "book.language", kind: FieldType::Text, filterable: true, sortable: false, via: None },
+        Field { name: "edition", column: "book.edition", kind: FieldType::Text, filterable: true, sortable: false, via: None },
+        Field { name: "tag", column: "tag.name", kind: FieldType::Text, filterable: true, sortable: false, via: Some(BOOK_TAGS) },
+        Field { name: "shelf", column: "shelf.name", kind: FieldType::Text, filterable: true, sortable: false, via: Some(BOOK_SHELVES) },
+    ],
+};
+
+impl Book {
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn from_row(row: &Row) -> Result<Book, rusqlite::Error> {
+        Ok(Book {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            author: row.get(2)?,
+            pages: row.get(3)?,
+            genre: row.get(4)?,
+            medium: row.get(5)?,
+            rating: row.get(6)?,
+            notes: row.get(7)?,
+            isbn: row.get(8)?,
+            publisher: row.get(9)?,
+            year: row.get(10)?,
+            language: row.get(11)?,
+            edition: row.get(12)?,
+            version: row.get(13)?,
+        })
+    }
+
+    /**
+    Checks the book's ISBN and stores it as the bare ISBN-13. An empty
+    ISBN is taken to mean the book has none.
+    */
+    fn validated(mut self) -> Result<Book, ApiError> {
+        self.isbn = match self.isbn.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(text) => Some(
+                Isbn::parse(text)
+                    .map_err(|message| ApiError::invalid_field("isbn", message))?
+                    .as_str()
+                    .to_string(),
+            ),
+        };
+        Ok(self)
+    }
+
+    /// The column a field is stored in and the field's value, for
+    /// building updates of just the fields that changed.
+    fn column(&self, field: &str) -> Option<(&'static str, &dyn rusqlite::ToSql)> {
+        match field {
+            "title" => Some(("title", &self.title)),
+            "author" => Some(("author", &self.author)),
+            "pages" => Some(("pages", &self.pages)),
+            "genre" => Some(("genre", &self.genre)),
+            "medium" => Some(("medium", &self.medium)),
+            "rating" => Some(("rating", &self.rating)),
+            "notes" => Some(("notes", &self.notes)),
+            "isbn" => Some(("isbn", &self.isbn)),
+            "publisher" => Some(("publisher", &self.publisher)),
+            "year" => Some(("year", &self.year)),
+            "language" => Some(("language", &self.language)),
+            "edition" => Some(("edition", &self.edition)),
+            _ => None,
+        }
+    }
+}
+
+pub fn update_book_in_db(conn: &Connection, book: Book, actor: Option<&str>) -> Result<usize, ApiError> {
     /*
     I'm taking a new approach with this method, instead of taking partial payloads,
     I'm going to take an entire book object and serialize it. The realization here
@@ -24,7 +156,14 @@ pub fn update_book_in_db(book: Book) -> Result<usize, rusqlite::Error> {
     front end and then just send the whole thing. Let SQLite figure out half of the
     values didn't change. 
      */
-    let conn = common::get_database_connection()?;
+    let book = book.validated()?;
+    check_isbn_is_free(conn, &book)?;
+    // Kept for the book's history, a book that isn't there isn't updated either
+    let before = match book.id.map(|id| query_book_by_id(conn, id)) {
+        Some(Ok(before)) => before,
+        Some(Err(ApiError::NotFound(_))) | None => return Ok(0),
+        Some(Err(err)) => return Err(err),
+    };
     let mut stmt = conn.prepare(
 "UPDATE book SET title = :title,
 author = :author,
@@ -32,8 +171,14 @@ pages = :pages,
 genre = :genre,
 medium = :medium,
 rating = :rating,
-notes = :notes
-WHERE id = :id; 
+notes = :notes,
+isbn = :isbn,
+publisher = :publisher,
+year = :year,
+language = :language,
+edition = :edition,
+version = version + 1
+WHERE id = :id AND deleted_at IS NULL; 
 ")?;
     println!("{:#?}", book);
     let params: &[(&str, &dyn rusqlite::ToSql)] = &[
@@ -45,8 +190,86 @@ WHERE id = :id;
         (":medium", &book.medium),
         (":rating", &book.rating),
         (":notes", &book.notes),
+        (":isbn", &book.isbn),
+        (":publisher", &book.publisher),
+        (":year", &book.year),
+        (":language", &book.language),
+        (":edition", &book.edition),
     ];
-    stmt.execute_named(params)
+    let rows_changed = stmt.execute_named(params)?;
+    if let (1, Some(id)) = (rows_changed, book.id) {
+        author::link_book(conn, id, &book.author)?;
+        let after = query_book_by_id(conn, id)?;
+        history::record(conn, Entity::Book, id, Action::Update, Some(&before), Some(&after), actor)?;
+    }
+    Ok(rows_changed)
+}
+
+/**
+
+Applies `patch` to the book with the given id and returns the book as
+it is now stored. Only the columns whose values the patch changed are
+written, and the id can't be changed. Reading the book and writing it
+back should happen in one transaction, so callers need to provide it.
+
+**/
+pub fn patch_book(conn: &Connection, id: u32, patch: &Patch, actor: Option<&str>) -> Result<Book, ApiError> {
+    let book = query_book_by_id(conn, id)?;
+    let patched = patch::apply(&book, patch)?.validated()?;
+    if !write_changes(conn, id, &book, &patched)? {
+        return Ok(book);
+    }
+
+    let patched = query_book_by_id(conn, id)?;
+    history::record(conn, Entity::Book, id, Action::Update, Some(&book), Some(&patched), actor)?;
+    Ok(patched)
+}
+
+/**
+
+Puts the book with the given id back the way it was after the given
+revision of its history (see `models::history`) and returns it as it
+is now stored. The book can't be in the trash, and only the fields
+that differ are written, like a patch. Callers should run this in a
+transaction.
+
+**/
+pub fn revert_book(conn: &Connection, id: u32, revision: u32, actor: Option<&str>) -> Result<Book, ApiError> {
+    let book = query_book_by_id(conn, id)?;
+    let mut reverted: Book = history::snapshot_at(conn, Entity::Book, id, revision)?;
+    reverted.id = book.id;
+    reverted.version = book.version;
+    if !write_changes(conn, id, &book, &reverted.validated()?)? {
+        return Ok(book);
+    }
+
+    let reverted = query_book_by_id(conn, id)?;
+    history::record(conn, Entity::Book, id, Action::Revert, Some(&book), Some(&reverted), actor)?;
+    Ok(reverted)
+}
+
+// Writes the fields that differ between the stored book and `changed`, returning whether there were any
+fn write_changes(conn: &Connection, id: u32, book: &Book, changed: &Book) -> Result<bool, ApiError> {
+    let fields = patch::changed_fields(book, changed)?;
+    if fields.is_empty() {
+        return Ok(false);
+    }
+
+    let mut columns = Vec::new();
+    for field in &fields {
+        match changed.column(field) {
+            Some(column) => columns.push(column),
+            None => return Err(ApiError::invalid_field(field, format!("{} can't be changed", field))),
+        }
+    }
+    if fields.iter().any(|field| field == "isbn") {
+        check_isbn_is_free(conn, changed)?;
+    }
+    patch::update_columns(conn, "book", id, &columns)?;
+    if fields.iter().any(|field| field == "author") {
+        author::link_book(conn, id, &changed.author)?;
+    }
+    Ok(true)
 }
 
 /**
@@ -57,75 +280,301 @@ no duplicate ids in the table. However, if that was to somehow occur,
 this function would only return the first row that was found.
 
 **/
-pub fn query_book_by_id(id: u32) -> Result<Book, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
-    let mut stmt = conn.prepare("SELECT * FROM book WHERE id = :id;")?;
-    let row = stmt.query_row_named(&[(":id", &id)], |row| {
-        Ok(Book {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            author: row.get(2)?,
-            pages: row.get(3)?,
-            genre: row.get(4)?,
-            medium: row.get(5)?,
-            rating: row.get(6)?,
-            notes: row.get(7)?,
-        })
-    })?;
-    Ok(row)
+pub fn query_book_by_id(conn: &Connection, id: u32) -> Result<Book, ApiError> {
+    let sql = format!("SELECT {} FROM book WHERE id = :id AND deleted_at IS NULL;", BOOK_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_row_named(&[(":id", &id)], Book::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("book"))
+}
+
+/// Returns the book with the given ISBN, in either form.
+pub fn query_book_by_isbn(conn: &Connection, isbn: &Isbn) -> Result<Book, ApiError> {
+    let sql = format!("SELECT {} FROM book WHERE isbn = :isbn AND deleted_at IS NULL;", BOOK_COLUMNS);
+    conn.query_row_named(&sql, &[(":isbn", &isbn.as_str())], Book::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("book"))
+}
+
+/**
+Fails with a `Conflict` naming the other book if another book already
+has this book's ISBN. Books in the trash don't count. The unique index
+on the column backs this up, the check is here for the clearer error.
+*/
+pub fn check_isbn_is_free(conn: &Connection, book: &Book) -> Result<(), ApiError> {
+    let isbn = match &book.isbn {
+        Some(isbn) => isbn,
+        None => return Ok(()),
+    };
+    let other: Option<u32> = conn
+        .query_row_named(
+            "SELECT id FROM book WHERE isbn = :isbn AND id IS NOT :id AND deleted_at IS NULL;",
+            &[(":isbn", isbn), (":id", &book.id)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match other {
+        Some(other) => Err(ApiError::Conflict(format!(
+            "book {} already has the ISBN {}",
+            other, isbn
+        ))),
+        None => Ok(()),
+    }
+}
+
+/**
+
+Returns one page of books ordered by `sort`, along with the total
+number of books so that clients can work out how many pages there are.
+Books with no value in the sort column (no rating, say) always come
+last, and ties are broken by id so paging through is stable.
+
+**/
+pub fn query_all_books(
+    conn: &Connection,
+    page: &PageParams,
+    sort: &Field,
+    direction: SortDirection,
+) -> Result<Page<Book>, ApiError> {
+    let total: u32 = conn.query_row(
+        "SELECT count(*) FROM book WHERE deleted_at IS NULL;",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    let sql = format!(
+        "SELECT {} FROM book WHERE deleted_at IS NULL
+ORDER BY {} {} NULLS LAST, book.id ASC LIMIT :limit OFFSET :offset;",
+        BOOK_COLUMNS,
+        sort.order_expression(),
+        direction.as_sql()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] =
+        &[(":limit", &page.limit), (":offset", &page.offset)];
+    let items = stmt
+        .query_map_named(params, Book::from_row)?
+        .collect::<Result<Vec<Book>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/**
+
+Returns every book whose title contains `title`, ignoring case.
+
+**/
+pub fn query_books_by_title(conn: &Connection, title: &str) -> Result<Vec<Book>, ApiError> {
+    query_books_containing(conn, "title", title)
+}
+
+/**
+
+Returns every book whose author contains `author`, ignoring case, so
+that "le guin" finds "Ursula K. Le Guin".
+
+**/
+pub fn query_books_by_author(conn: &Connection, author: &str) -> Result<Vec<Book>, ApiError> {
+    query_books_containing(conn, "author", author)
+}
+
+// `column` is always one of the literals above, never user input
+fn query_books_containing(
+    conn: &Connection,
+    column: &str,
+    term: &str,
+) -> Result<Vec<Book>, ApiError> {
+    let sql = format!(
+        "SELECT {} FROM book WHERE {} LIKE :pattern ESCAPE '\\' AND deleted_at IS NULL
+ORDER BY title COLLATE NOCASE, id;",
+        BOOK_COLUMNS, column
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let pattern = common::contains_pattern(term);
+    let books = stmt
+        .query_map_named(&[(":pattern", &pattern)], Book::from_row)?
+        .collect::<Result<Vec<Book>, rusqlite::Error>>()?;
+    Ok(books)
+}
+
+/**
+
+Returns one page of the books matching a full-text search of their
+titles, authors and notes, best matches first. See `models::search`
+for the query syntax.
+
+**/
+pub fn search_books(
+    conn: &Connection,
+    query: &FtsQuery,
+    page: &PageParams,
+) -> Result<Page<SearchHit<Book>>, ApiError> {
+    search::search(conn, &BOOK_INDEX, query, page, Book::from_row)
+}
+
+/**
+
+Returns one page of the books matching a filter, ordered by id. See
+`models::filter` for the filter language.
+
+**/
+pub fn query_books_matching(
+    conn: &Connection,
+    filter: &Filter,
+    page: &PageParams,
+) -> Result<Page<Book>, ApiError> {
+    filter::query_filtered(conn, &BOOK_FIELDS, filter, page, Book::from_row)
 }
+
 // Might be good to add an optional limit query param?
 pub fn query_books_by_filter(
-    filter_col: String, filter_query: String) -> Result<Vec<Book>, rusqlite::Error> {
+    conn: &Connection, filter_col: String, filter_query: String) -> Result<Vec<Book>, ApiError> {
 
-    if !common::column_name_is_valid(filter_col.as_ref()) {
-        return Err(rusqlite::Error::InvalidColumnName(filter_col));
-    }
+    let field = BOOK_FIELDS.filterable("filterBy", &filter_col)?;
+    let filter_query = field
+        .bind_text(&filter_query)
+        .map_err(|message| ApiError::invalid_parameter("query", message))?;
 
-    let conn = common::get_database_connection()?;
-    let partial_stmt = format!("SELECT * FROM book where {} = :filter_query;", filter_col);
+    let partial_stmt = format!(
+        "SELECT {} FROM book where {} AND deleted_at IS NULL;",
+        BOOK_COLUMNS,
+        field.condition("= :filter_query")
+    );
     let mut stmt = conn.prepare(partial_stmt.as_ref())?;
     let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":filter_query", &filter_query)];
 
     let mut rows = stmt.query_named(params)?;
     let mut books: Vec<Book> = Vec::new();
     while let Some(row) = rows.next()? {
-        let book = Book {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            author: row.get(2)?,
-            pages: row.get(3)?,
-            genre: row.get(4)?,
-            medium: row.get(5)?,
-            rating: row.get(6)?,
-            notes: row.get(7)?,
-        };
-        books.push(book);
+        books.push(Book::from_row(row)?);
     };
     Ok(books)
 }
 
+/// What deleting a book does with the readings of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnReadings {
+    /// The book isn't deleted while it has readings.
+    Restrict,
+    /// The readings go to the trash along with the book, and come back with it.
+    Cascade,
+    /// The readings are kept without a book.
+    Detach,
+}
+
+impl OnReadings {
+    pub fn from_param(param: &str) -> Option<OnReadings> {
+        match param {
+            "restrict" => Some(OnReadings::Restrict),
+            "cascade" => Some(OnReadings::Cascade),
+            "detach" => Some(OnReadings::Detach),
+            _ => None,
+        }
+    }
+}
+
+/**
+
+Given, an integer id, this function attempts to move the book with the
+given id to the trash (see `models::trash`), dealing with the readings
+of the book as `on_readings` says. With `OnReadings::Restrict`, a book
+that has readings is a `Conflict` naming how many. The function then
+returns a `Result` that is either the number of books deleted (on
+success), or an ApiError (if there is a problem). Callers should run
+this in a transaction.
+
+**/
+pub fn delete_book_by_id(
+    conn: &Connection,
+    id: u32,
+    on_readings: OnReadings,
+    actor: Option<&str>,
+) -> Result<usize, ApiError> {
+    let book = match query_book_by_id(conn, id) {
+        Ok(book) => book,
+        Err(ApiError::NotFound(_)) => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let deleted_at = common::now_timestamp();
+    let mut stmt = conn.prepare("SELECT id FROM reading WHERE book = :id AND deleted_at IS NULL ORDER BY id;")?;
+    let reading_ids = stmt
+        .query_map_named(&[(":id", &id)], |row| row.get(0))?
+        .collect::<Result<Vec<u32>, rusqlite::Error>>()?;
+    let mut readings = Vec::new();
+    for reading in reading_ids {
+        readings.push(query_reading_by_id(conn, reading)?);
+    }
+
+    if !readings.is_empty() {
+        match on_readings {
+            OnReadings::Restrict => {
+                return Err(ApiError::Conflict(format!(
+                    "the book still has {} reading(s), delete them with on_readings=cascade \
+                     or keep them with on_readings=detach",
+                    readings.len()
+                )))
+            }
+            OnReadings::Cascade => {
+                conn.execute_named(
+                    "UPDATE reading SET deleted_at = :deleted_at, deleted_with_book = 1
+WHERE book = :id AND deleted_at IS NULL;",
+                    &[(":id", &id as &dyn rusqlite::ToSql), (":deleted_at", &deleted_at)],
+                )?;
+                for reading in &readings {
+                    let id = reading.id().unwrap_or_default();
+                    history::record(conn, Entity::Reading, id, Action::Delete, Some(reading), None, actor)?;
+                }
+            }
+            OnReadings::Detach => {
+                conn.execute_named(
+                    "UPDATE reading SET book = NULL, version = version + 1
+WHERE book = :id AND deleted_at IS NULL;",
+                    &[(":id", &id)],
+                )?;
+                for reading in &readings {
+                    let id = reading.id().unwrap_or_default();
+                    let detached = query_reading_by_id(conn, id)?;
+                    history::record(conn, Entity::Reading, id, Action::Update, Some(reading), Some(&detached), actor)?;
+                }
+            }
+        }
+    }
+    let deleted = conn.execute_named(
+        "UPDATE book SET deleted_at = :deleted_at WHERE id = :id AND deleted_at IS NULL;",
+        &[(":id", &id as &dyn rusqlite::ToSql), (":deleted_at", &deleted_at)],
+    )?;
+    history::record(conn, Entity::Book, id, Action::Delete, Some(&book), None, actor)?;
+    Ok(deleted)
+}
+
 /**
 
-Given, an integer id, this function attempts to delete the book record
-from the database with the given id. The function then returns a
-`Result` that is either the number of rows changed (on success), or a
-rusqlite:Error (if there is a problem).
+Inserts the book and returns it as it was stored, including the id
+sqlite assigned to it. Any id already set on the book is ignored.
 
 **/
-pub fn delete_book_by_id(id: u32) -> Result<usize, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
-    let mut stmt = conn.prepare("DELETE FROM book WHERE id = :id;")?;
-    // execute_named returns either Ok(usize) or Err(rusqlite::Error)
-    // which is exactly what I want, so it can be returned as is.
-    stmt.execute_named(&[(":id", &id)])
+pub fn create_book(conn: &Connection, mut book: Book, actor: Option<&str>) -> Result<Book, ApiError> {
+    book.id = None;
+    let book = book.validated()?;
+    check_isbn_is_free(conn, &book)?;
+    let author = book.author.clone();
+    write_book_to_db(conn, book)?;
+    let id = conn.last_insert_rowid() as u32;
+    author::link_book(conn, id, &author)?;
+    let created = query_book_by_id(conn, id)?;
+    history::record(conn, Entity::Book, id, Action::Create, None, Some(&created), actor)?;
+    Ok(created)
 }
 
-pub fn write_book_to_db(book: Book) -> Result<usize, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
+pub fn write_book_to_db(conn: &Connection, book: Book) -> Result<usize, ApiError> {
     let mut stmt = conn.prepare(
-        "INSERT INTO book (title, author, pages, genre, medium, rating, notes) 
-VALUES (:title, :author, :pages, :genre, :medium, :rating, :notes)",
+        "INSERT INTO book (title, author, pages, genre, medium, rating, notes, isbn, publisher, year, language, edition) 
+VALUES (:title, :author, :pages, :genre, :medium, :rating, :notes, :isbn, :publisher, :year, :language, :edition)",
     )?;
     println!("{:#?}", book);
     let params: &[(&str, &dyn rusqlite::ToSql)] = &[
@@ -136,8 +585,13 @@ VALUES (:title, :author, :pages, :genre, :medium, :rating, :notes)",
         (":medium", &book.medium),
         (":rating", &book.rating),
         (":notes", &book.notes),
+        (":isbn", &book.isbn),
+        (":publisher", &book.publisher),
+        (":year", &book.year),
+        (":language", &book.language),
+        (":edition", &book.edition),
     ];
-    stmt.execute_named(params)
+    Ok(stmt.execute_named(params)?)
 }
 
 #[cfg(test)]
@@ -172,7 +626,7 @@ mod tests {
             serde_json::from_str(valid_json);
         match should_be_valid {
             Ok(book) => println!("'normal' json: {:?}", book),
-            Err(e) => panic!("There should not be any errors!"),
+            Err(_) => panic!("There should not be any errors!"),
         }
 
         let missing_fields: Result<Book, serde_json::error::Error> =
@@ -195,15 +649,72 @@ mod tests {
 
     #[test]
     fn query_books_from_db() {
+        let conn = common::test_connection();
+        for id in 1..45 {
+            let book = Book {
+                id: None,
+                title: format!("Book number {}", id),
+                author: "JDSeiler-Test".to_string(),
+                pages: Some(id),
+                genre: None,
+                medium: "ebook".to_string(),
+                rating: None,
+                notes: None,
+                isbn: None,
+                publisher: None,
+                year: None,
+                language: None,
+                edition: None,
+                version: 0,
+            };
+            write_book_to_db(&conn, book).unwrap();
+        }
+
         for id in 1..45 {
-            let result = query_book_by_id(id);
+            let result = query_book_by_id(&conn, id);
             match result {
-                Ok(book) => {}
+                Ok(_) => {}
                 Err(e) => panic!("A query on the database for id {} failed: {:?}", id, e),
             }
         }
     }
 
+    #[test]
+    fn paging_through_all_books() {
+        let conn = common::test_connection();
+        for (title, rating) in &[("Emma", Some(3)), ("dune", None), ("Beloved", Some(5))] {
+            let book = Book {
+                id: None,
+                title: title.to_string(),
+                author: "JDSeiler-Test".to_string(),
+                pages: None,
+                genre: None,
+                medium: "paper".to_string(),
+                rating: *rating,
+                notes: None,
+                isbn: None,
+                publisher: None,
+                year: None,
+                language: None,
+                edition: None,
+                version: 0,
+            };
+            write_book_to_db(&conn, book).unwrap();
+        }
+
+        let first_two = PageParams { limit: 2, offset: 0 };
+        let page = query_all_books(&conn, &first_two, BOOK_FIELDS.sortable("title").unwrap(), SortDirection::Asc).unwrap();
+        assert_eq!(page.total, 3);
+        let titles: Vec<&str> = page.items.iter().map(|b| b.title.as_str()).collect();
+        assert_eq!(titles, vec!["Beloved", "dune"]);
+
+        // Unrated books sort last in either direction
+        let everything = PageParams::default();
+        let page = query_all_books(&conn, &everything, BOOK_FIELDS.sortable("rating").unwrap(), SortDirection::Desc).unwrap();
+        let titles: Vec<&str> = page.items.iter().map(|b| b.title.as_str()).collect();
+        assert_eq!(titles, vec!["Beloved", "Emma", "dune"]);
+    }
+
     #[test]
     fn writing_a_book_to_db() {
         let new_book: Book = Book {
@@ -215,11 +726,98 @@ mod tests {
             medium: "paper".to_string(),
             rating: Some(5),
             notes: None,
+            isbn: None,
+            publisher: None,
+            year: None,
+            language: None,
+            edition: None,
+            version: 0,
         };
-        let changes = write_book_to_db(new_book);
+        let conn = common::test_connection();
+        let changes = write_book_to_db(&conn, new_book);
         match changes {
             Ok(rows) => assert_eq!(rows, 1),
             Err(e) => panic!("Insert failed with error: {:#?}", e),
         }
     }
+
+    #[test]
+    fn created_books_come_back_with_an_id() {
+        let conn = common::test_connection();
+        let new_book: Book = serde_json::from_str(
+            r#"{"title": "Dune", "author": "Frank Herbert", "medium": "paper"}"#,
+        )
+        .unwrap();
+        let created = create_book(&conn, new_book, None).unwrap();
+        assert_eq!(created.id(), Some(1));
+        assert_eq!(created.title, "Dune");
+    }
+
+    #[test]
+    fn patching_a_book_keeps_the_fields_left_out() {
+        let conn = common::test_connection();
+        let book: Book = serde_json::from_str(
+            r#"{"title": "Dune", "author": "Frank Herbert", "medium": "paper", "pages": 412}"#,
+        )
+        .unwrap();
+        let id = create_book(&conn, book, None).unwrap().id().unwrap();
+
+        let patch = Patch::from_body(None, br#"{"rating": 5}"#).unwrap();
+        let patched = patch_book(&conn, id, &patch, None).unwrap();
+        assert_eq!(patched.rating, Some(5));
+        assert_eq!(patched.pages, Some(412));
+
+        let patch = Patch::from_body(None, br#"{"id": 99}"#).unwrap();
+        assert!(matches!(patch_book(&conn, id, &patch, None), Err(ApiError::InvalidField { .. })));
+        assert!(matches!(patch_book(&conn, 99, &patch, None), Err(ApiError::NotFound("book"))));
+    }
+
+    #[test]
+    fn an_isbn_is_only_added_once() {
+        let conn = common::test_connection();
+        let book = |isbn: &str| -> Book {
+            serde_json::from_value(serde_json::json!(
+                {"title": "Dune", "author": "Frank Herbert", "medium": "paper", "isbn": isbn}
+            ))
+            .unwrap()
+        };
+        let created = create_book(&conn, book("0-441-17271-7"), None).unwrap();
+        assert_eq!(created.isbn.as_deref(), Some("9780441172719"));
+        assert!(matches!(create_book(&conn, book("978-0441172719"), None), Err(ApiError::Conflict(_))));
+        assert!(matches!(create_book(&conn, book("0-441-17271-8"), None), Err(ApiError::InvalidField { .. })));
+
+        let isbn = Isbn::parse("0441172717").unwrap();
+        assert_eq!(query_book_by_isbn(&conn, &isbn).unwrap().id(), created.id());
+        let patch = Patch::from_body(None, br#"{"isbn": ""}"#).unwrap();
+        assert_eq!(patch_book(&conn, 1, &patch, None).unwrap().isbn, None);
+    }
+
+    #[test]
+    fn deleting_a_book_deals_with_its_readings() {
+        let conn = common::test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Emma', 'Jane Austen', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Persuasion', 'Jane Austen', 'paper');
+             INSERT INTO reading (book, start_date) VALUES (1, '2020-01-01');
+             INSERT INTO reading (book, start_date) VALUES (1, '2021-01-01');
+             INSERT INTO reading (book, start_date) VALUES (2, '2020-06-01');",
+        )
+        .unwrap();
+        let readings = |sql: &str| -> u32 { conn.query_row(sql, NO_PARAMS, |row| row.get(0)).unwrap() };
+
+        match delete_book_by_id(&conn, 1, OnReadings::Restrict, None) {
+            Err(ApiError::Conflict(message)) => assert!(message.contains("2 reading(s)")),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(conn.execute_batch("DELETE FROM book WHERE id = 1;").is_err());
+
+        assert_eq!(delete_book_by_id(&conn, 1, OnReadings::Detach, None).unwrap(), 1);
+        assert_eq!(readings("SELECT count(*) FROM reading WHERE book IS NULL;"), 2);
+        assert_eq!(delete_book_by_id(&conn, 2, OnReadings::Cascade, None).unwrap(), 1);
+        assert_eq!(readings("SELECT count(*) FROM reading WHERE deleted_at IS NULL;"), 2);
+
+        assert!(conn
+            .execute_batch("INSERT INTO reading (book, start_date) VALUES (9, '2020-01-01');")
+            .is_err());
+    }
 }
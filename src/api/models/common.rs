@@ -1,33 +1,111 @@
-use rusqlite::Connection;
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+
+/// How dates are stored, ISO 8601 so that they sort and compare as text.
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+/// The month/day/year format dates were originally written in, still
+/// accepted from clients and converted on the way in.
+const LEGACY_DATE_FORMAT: &str = "%m/%d/%Y";
+/// How times are stored, in UTC, so that they too sort as text.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// The number of records in a page when the client doesn't ask for a size.
+pub const DEFAULT_PAGE_LIMIT: u32 = 25;
+/// The largest page a client is allowed to ask for.
+pub const MAX_PAGE_LIMIT: u32 = 100;
+
+/// Which slice of a listing the client asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageParams {
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl Default for PageParams {
+    fn default() -> Self {
+        PageParams {
+            limit: DEFAULT_PAGE_LIMIT,
+            offset: 0,
+        }
+    }
+}
+
+/// One page of a listing, plus the total size of the listing.
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn from_param(param: &str) -> Option<SortDirection> {
+        match param {
+            "asc" => Some(SortDirection::Asc),
+            "desc" => Some(SortDirection::Desc),
+            _ => None,
+        }
+    }
+
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+/// The current time in `TIMESTAMP_FORMAT`.
+pub fn now_timestamp() -> String {
+    Utc::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Parses a date in either the stored format or the legacy month/day/year format.
+pub fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT)
+        .or_else(|_| NaiveDate::parse_from_str(date, LEGACY_DATE_FORMAT))
+        .ok()
+}
+
+/// Rewrites a date from the client in the format dates are stored in.
+pub fn normalize_date(date: &str) -> Option<String> {
+    parse_date(date).map(|date| date.format(DATE_FORMAT).to_string())
+}
 
 /**
-Returns a `Result` that is either a usable connection to the database
-or a `rusqlite::Error`. The path to the database is currently
-hardcoded into the function as a relative path. Starting alexandria-db
-from a directory besides the project root results in this relative path
-being incorrect and this function returning an error.
+Turns a search term into a LIKE pattern matching any value that
+contains it. The term's own `%`, `_` and `\` are escaped, so queries
+using the pattern need `ESCAPE '\'`.
 */
-pub fn get_database_connection() -> Result<Connection, rusqlite::Error> {
-    let test_db_path = "./src/db_storage/dummy.db";
-    let maybe_conn = Connection::open(&test_db_path);
-    maybe_conn
+pub fn contains_pattern(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len() + 2);
+    pattern.push('%');
+    for c in term.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
 }
 
 /**
-Returns true if the provided column name exactly matches any
-of the allowed columns, and false otherwise.
+Returns a fresh in-memory database with every migration applied, for
+tests that need to run real queries without touching a database file.
+Foreign keys are enforced, as they are on the server's connections.
 */
-pub fn column_name_is_valid(col: &str) -> bool {
-    match col {
-        "title"      |
-        "author"     |
-        "pages"      |
-        "genre"      |
-        "medium"     |
-        "rating"     |
-        "book"       |
-        "start_date" |
-        "end_date" => true,
-        _ => false
-    }
+#[cfg(test)]
+pub fn test_connection() -> rusqlite::Connection {
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+    crate::migrations::migrate_up(&mut conn).unwrap();
+    conn
 }
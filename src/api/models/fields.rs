@@ -0,0 +1,195 @@
+/*!
+
+# fields
+
+What each record type exposes to queries: its fields, the column each
+one is stored in, the type of value it holds, and whether clients can
+filter or sort on it. The search routes check `filterBy` and `filter`
+against this, `/book/all` checks `sort`, and values are bound with the
+field's type, so `pages = 300` compares numbers instead of the text
+"300". A field missing here can't end up in a statement.
+
+!*/
+
+use rusqlite::types::Value as SqlValue;
+
+use super::common;
+use super::filter::Value;
+use super::isbn::Isbn;
+use crate::api::error::ApiError;
+
+/// The kind of value a field holds, which decides how values compared
+/// against it are bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Integer,
+    Text,
+    /// Text in the stored YYYY-MM-DD format, which either accepted date
+    /// format is converted to before comparing.
+    Date,
+    /// An ISBN, which either form is converted to the stored ISBN-13 of before comparing.
+    Isbn,
+}
+
+#[derive(Debug)]
+pub struct Field {
+    /// The name clients use.
+    pub name: &'static str,
+    /// The table-qualified column the field is stored in.
+    pub column: &'static str,
+    pub kind: FieldType,
+    pub filterable: bool,
+    pub sortable: bool,
+    /**
+    For a field stored in another table, like a book's tags, the
+    subquery selecting the record's rows of that table. Conditions on
+    the field then hold if they hold for any of those rows, and the
+    field counts as null when there are none.
+    */
+    pub via: Option<&'static str>,
+}
+
+/// The fields of one record type.
+#[derive(Debug)]
+pub struct EntityFields {
+    /// The record table, which has a `deleted_at` column for the trash.
+    pub table: &'static str,
+    /// The columns the record type's `from_row` expects, in order.
+    pub record_columns: &'static str,
+    pub fields: &'static [Field],
+}
+
+impl EntityFields {
+    /**
+    Returns the field called `name` if it can be filtered on, or a 400
+    error for the query parameter `param` listing the fields that can.
+    */
+    pub fn filterable(&self, param: &'static str, name: &str) -> Result<&Field, ApiError> {
+        self.fields
+            .iter()
+            .find(|field| field.filterable && field.name == name)
+            .ok_or_else(|| {
+                ApiError::invalid_parameter(
+                    param,
+                    format!(
+                        "{} can't be filtered on, the fields are: {}",
+                        name,
+                        self.names(|field| field.filterable)
+                    ),
+                )
+            })
+    }
+
+    /// Returns the field called `name` if it can be sorted by, or a 400
+    /// error for the `sort` parameter listing the fields that can.
+    pub fn sortable(&self, name: &str) -> Result<&Field, ApiError> {
+        self.fields
+            .iter()
+            .find(|field| field.sortable && field.name == name)
+            .ok_or_else(|| {
+                ApiError::invalid_parameter(
+                    "sort",
+                    format!("sort must be one of: {}", self.names(|field| field.sortable)),
+                )
+            })
+    }
+
+    fn names(&self, include: fn(&Field) -> bool) -> String {
+        let names: Vec<&str> = self.fields.iter().filter(|field| include(field)).map(|field| field.name).collect();
+        names.join(", ")
+    }
+}
+
+impl Field {
+    /**
+    Converts a value compared against this field to the type the field
+    holds. Text that isn't a number can't be compared with a numeric
+    field, and the message saying so is returned as the error.
+    */
+    pub fn bind(&self, value: Value) -> Result<SqlValue, String> {
+        match (self.kind, value) {
+            (FieldType::Integer, Value::Integer(number)) => Ok(SqlValue::Integer(number)),
+            (FieldType::Integer, Value::Real(number)) => Ok(SqlValue::Real(number)),
+            (FieldType::Integer, Value::Text(text)) => match text.trim().parse() {
+                Ok(number) => Ok(SqlValue::Integer(number)),
+                Err(_) => Err(format!("{} holds numbers, not {:?}", self.name, text)),
+            },
+            (FieldType::Text, Value::Integer(number)) => Ok(SqlValue::Text(number.to_string())),
+            (FieldType::Text, Value::Real(number)) => Ok(SqlValue::Text(number.to_string())),
+            (FieldType::Text, Value::Text(text)) => Ok(SqlValue::Text(text)),
+            (FieldType::Date, Value::Text(date)) => {
+                Ok(SqlValue::Text(common::normalize_date(&date).unwrap_or(date)))
+            }
+            (FieldType::Date, _) => Err(format!("{} holds dates like 2020-01-31", self.name)),
+            (FieldType::Isbn, Value::Text(text)) => match Isbn::parse(&text) {
+                Ok(isbn) => Ok(SqlValue::Text(isbn.as_str().to_string())),
+                Err(_) => Ok(SqlValue::Text(text)),
+            },
+            (FieldType::Isbn, Value::Integer(number)) => self.bind(Value::Text(number.to_string())),
+            (FieldType::Isbn, Value::Real(_)) => Err(format!("{} holds ISBNs like 978-0-306-40615-7", self.name)),
+        }
+    }
+
+    /// Like `bind`, for a value that arrived as plain text.
+    pub fn bind_text(&self, text: &str) -> Result<SqlValue, String> {
+        self.bind(Value::Text(text.to_string()))
+    }
+
+    /// The SQL for `test`, like `= ?`, applied to the field.
+    pub fn condition(&self, test: &str) -> String {
+        match self.via {
+            None => format!("{} {}", self.column, test),
+            Some(rows) => format!("EXISTS ({} AND {} {})", rows, self.column, test),
+        }
+    }
+
+    /// The SQL testing whether the field is null, or with `negated` whether it isn't.
+    pub fn null_condition(&self, negated: bool) -> String {
+        match (self.via, negated) {
+            (None, false) => format!("{} IS NULL", self.column),
+            (None, true) => format!("{} IS NOT NULL", self.column),
+            (Some(rows), false) => format!("NOT EXISTS ({})", rows),
+            (Some(rows), true) => format!("EXISTS ({})", rows),
+        }
+    }
+
+    /// The expression to order by, ignoring case for text.
+    pub fn order_expression(&self) -> String {
+        match self.kind {
+            FieldType::Text => format!("{} COLLATE NOCASE", self.column),
+            FieldType::Integer | FieldType::Date | FieldType::Isbn => self.column.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::book::BOOK_FIELDS;
+    use crate::api::models::reading::READING_FIELDS;
+
+    #[test]
+    fn each_table_has_its_own_fields() {
+        assert!(BOOK_FIELDS.filterable("filterBy", "notes").is_ok());
+        assert!(READING_FIELDS.filterable("filterBy", "start_date").is_ok());
+        let error = BOOK_FIELDS.filterable("filterBy", "start_date").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "start_date can't be filtered on, the fields are: id, title, author, pages, genre, medium, rating, notes, isbn, publisher, year, language, edition, tag, shelf"
+        );
+        assert!(BOOK_FIELDS.sortable("genre").is_err());
+    }
+
+    #[test]
+    fn values_are_bound_with_the_fields_type() {
+        let pages = BOOK_FIELDS.filterable("filter", "pages").unwrap();
+        assert_eq!(pages.bind_text("300"), Ok(SqlValue::Integer(300)));
+        assert!(pages.bind_text("many").is_err());
+
+        let title = BOOK_FIELDS.filterable("filter", "title").unwrap();
+        assert_eq!(title.bind(Value::Integer(1984)), Ok(SqlValue::Text("1984".to_string())));
+
+        let start = READING_FIELDS.filterable("filter", "start_date").unwrap();
+        assert_eq!(start.bind_text("01/02/2020"), Ok(SqlValue::Text("2020-01-02".to_string())));
+    }
+}
@@ -0,0 +1,648 @@
+/*!
+
+# filter
+
+A small query language for picking out records by their fields, used
+by the `filter` parameter of the search routes:
+
+```text
+rating >= 4 AND genre IN (Fantasy, "Science Fiction") AND pages < 400
+(author CONTAINS tolkien OR author CONTAINS "le guin") AND NOT medium = audio
+rating IS NULL OR title LIKE 'the %'
+start_date BETWEEN 2020-01-01 AND 2020-12-31
+```
+
+- comparisons: `=`, `!=` (or `<>`), `<`, `<=`, `>`, `>=`
+- `IN (a, b, ...)` and `NOT IN (...)`
+- `IS NULL` and `IS NOT NULL`
+- `BETWEEN low AND high`, inclusive
+- `LIKE pattern` with SQL's `%` and `_` wildcards, and `CONTAINS term`,
+  which matches the term anywhere with no wildcards of its own.
+  Both ignore case.
+- `AND`, `OR`, `NOT` and parentheses. `AND` binds tighter than `OR`.
+
+Books can also be filtered on `tag` and `shelf`, the names of their
+tags and the shelves they sit on. Those hold if any of the book's tags
+or shelves match, so `tag = fantasy AND NOT tag = read` finds unread
+fantasy, and `tag IS NULL` finds books with no tags at all.
+
+Keywords aren't case sensitive. Values are numbers, quoted strings (in
+single or double quotes) or bare words made of letters, digits and
+`-`, `_`, `.`, `/` and `:`, which covers dates. Anything with spaces
+needs quotes.
+
+The text is parsed into a `Filter` tree first and only then turned into
+SQL, with every value bound as a parameter with the type of its field
+and every field checked against the ones the record type allows (see
+`models::fields`), so nothing the client writes ever ends up in the
+statement itself.
+
+!*/
+
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, Row};
+use std::fmt;
+
+use super::common;
+use super::common::{Page, PageParams};
+use super::fields::{EntityFields, Field};
+use crate::api::error::ApiError;
+
+/// How deeply parentheses and `NOT`s may nest.
+const MAX_DEPTH: usize = 16;
+/// How many conditions a filter may hold in total.
+const MAX_CONDITIONS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Compare { field: String, op: CompareOp, value: Value },
+    In { field: String, values: Vec<Value>, negated: bool },
+    IsNull { field: String, negated: bool },
+    Between { field: String, low: Value, high: Value },
+    Like { field: String, pattern: String, negated: bool },
+    Contains { field: String, term: String },
+}
+
+/// The SQL for a filter, to go after `WHERE`, and the values it binds in order.
+#[derive(Debug)]
+pub struct CompiledFilter {
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+}
+
+impl Filter {
+    /// Parses the text of a filter, failing with an error pointing at the problem.
+    pub fn parse(text: &str) -> Result<Filter, ApiError> {
+        let tokens = tokenize(text).map_err(|err| err.into_api_error())?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+            conditions: 0,
+        };
+        let filter = parser.parse_or().map_err(|err| err.into_api_error())?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(token) => Err(ParseError::new(
+                format!("unexpected {}", token.kind),
+                token.offset,
+            )
+            .into_api_error()),
+        }
+    }
+
+    /// Turns the filter into SQL for the given record type.
+    pub fn compile(&self, schema: &EntityFields) -> Result<CompiledFilter, ApiError> {
+        let mut compiled = CompiledFilter {
+            sql: String::new(),
+            params: Vec::new(),
+        };
+        self.write_sql(schema, &mut compiled)?;
+        Ok(compiled)
+    }
+
+    fn write_sql(&self, schema: &EntityFields, out: &mut CompiledFilter) -> Result<(), ApiError> {
+        match self {
+            Filter::And(filters) | Filter::Or(filters) => {
+                let joiner = if let Filter::And(_) = self { " AND " } else { " OR " };
+                out.sql.push('(');
+                for (i, filter) in filters.iter().enumerate() {
+                    if i > 0 {
+                        out.sql.push_str(joiner);
+                    }
+                    filter.write_sql(schema, out)?;
+                }
+                out.sql.push(')');
+            }
+            Filter::Not(filter) => {
+                out.sql.push_str("NOT ");
+                filter.write_sql(schema, out)?;
+            }
+            Filter::Compare { field, op, value } => {
+                let field = schema.filterable("filter", field)?;
+                out.sql.push_str(&field.condition(&format!("{} ?", op)));
+                bind(out, field, value.clone())?;
+            }
+            Filter::In { field, values, negated } => {
+                let field = schema.filterable("filter", field)?;
+                let placeholders = vec!["?"; values.len()].join(", ");
+                let not = if *negated { "NOT " } else { "" };
+                out.sql.push_str(&field.condition(&format!("{}IN ({})", not, placeholders)));
+                for value in values {
+                    bind(out, field, value.clone())?;
+                }
+            }
+            Filter::IsNull { field, negated } => {
+                let field = schema.filterable("filter", field)?;
+                out.sql.push_str(&field.null_condition(*negated));
+            }
+            Filter::Between { field, low, high } => {
+                let field = schema.filterable("filter", field)?;
+                out.sql.push_str(&field.condition("BETWEEN ? AND ?"));
+                bind(out, field, low.clone())?;
+                bind(out, field, high.clone())?;
+            }
+            Filter::Like { field, pattern, negated } => {
+                let field = schema.filterable("filter", field)?;
+                let not = if *negated { "NOT " } else { "" };
+                out.sql.push_str(&field.condition(&format!("{}LIKE ?", not)));
+                out.params.push(SqlValue::Text(pattern.clone()));
+            }
+            Filter::Contains { field, term } => {
+                let field = schema.filterable("filter", field)?;
+                out.sql.push_str(&field.condition("LIKE ? ESCAPE '\\'"));
+                out.params.push(SqlValue::Text(common::contains_pattern(term)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+Returns one page of the records of `schema`'s table that match the
+filter, ordered by id, along with the total number of matches. Records
+in the trash never match.
+*/
+pub fn query_filtered<T>(
+    conn: &Connection,
+    schema: &EntityFields,
+    filter: &Filter,
+    page: &PageParams,
+    from_row: fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Result<Page<T>, ApiError> {
+    let compiled = filter.compile(schema)?;
+
+    let condition = format!("{}.deleted_at IS NULL AND ({})", schema.table, compiled.sql);
+    let count_sql = format!("SELECT count(*) FROM {} WHERE {};", schema.table, condition);
+    let total: u32 = conn.query_row(&count_sql, &compiled.params, |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT {} FROM {} WHERE {} ORDER BY {}.id LIMIT ? OFFSET ?;",
+        schema.record_columns, schema.table, condition, schema.table
+    );
+    let mut params = compiled.params;
+    params.push(SqlValue::Integer(page.limit.into()));
+    params.push(SqlValue::Integer(page.offset.into()));
+    let mut stmt = conn.prepare(&sql)?;
+    let items = stmt
+        .query_map(&params, from_row)?
+        .collect::<Result<Vec<T>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+// Values are bound with the field's type, so `pages = '300'` compares numbers
+fn bind(out: &mut CompiledFilter, field: &Field, value: Value) -> Result<(), ApiError> {
+    let value = field
+        .bind(value)
+        .map_err(|message| ApiError::invalid_parameter("filter", message))?;
+    out.params.push(value);
+    Ok(())
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            CompareOp::Eq => "=",
+            CompareOp::NotEq => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::LtEq => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::GtEq => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Quoted(String),
+    Op(CompareOp),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Word(word) => write!(f, "{:?}", word),
+            TokenKind::Quoted(text) => write!(f, "string {:?}", text),
+            TokenKind::Op(op) => write!(f, "{:?}", op.to_string()),
+            TokenKind::LeftParen => write!(f, "\"(\""),
+            TokenKind::RightParen => write!(f, "\")\""),
+            TokenKind::Comma => write!(f, "\",\""),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    /// Where the token starts in the filter text, in characters.
+    offset: usize,
+}
+
+#[derive(Debug)]
+struct ParseError {
+    message: String,
+    offset: usize,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, offset: usize) -> ParseError {
+        ParseError {
+            message: message.into(),
+            offset,
+        }
+    }
+
+    fn into_api_error(self) -> ApiError {
+        ApiError::invalid_parameter(
+            "filter",
+            format!("{} at position {} of the filter", self.message, self.offset),
+        )
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | ':')
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            ',' => TokenKind::Comma,
+            '=' => TokenKind::Op(CompareOp::Eq),
+            '!' if chars.get(i + 1) == Some(&'=') => {
+                i += 1;
+                TokenKind::Op(CompareOp::NotEq)
+            }
+            '<' | '>' => {
+                let next = chars.get(i + 1).copied();
+                let op = match (c, next) {
+                    ('<', Some('>')) => CompareOp::NotEq,
+                    ('<', Some('=')) => CompareOp::LtEq,
+                    ('>', Some('=')) => CompareOp::GtEq,
+                    ('<', _) => CompareOp::Lt,
+                    _ => CompareOp::Gt,
+                };
+                if matches!(op, CompareOp::NotEq | CompareOp::LtEq | CompareOp::GtEq) {
+                    i += 1;
+                }
+                TokenKind::Op(op)
+            }
+            '\'' | '"' => {
+                // A doubled quote inside a string stands for the quote itself
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(ParseError::new("unterminated string", start)),
+                        Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                            text.push(c);
+                            i += 2;
+                        }
+                        Some(&q) if q == c => break,
+                        Some(&other) => {
+                            text.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                TokenKind::Quoted(text)
+            }
+            c if is_word_char(c) => {
+                while i + 1 < chars.len() && is_word_char(chars[i + 1]) {
+                    i += 1;
+                }
+                TokenKind::Word(chars[start..=i].iter().collect())
+            }
+            other => return Err(ParseError::new(format!("unexpected character {:?}", other), start)),
+        };
+        tokens.push(Token { kind, offset: start });
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    conditions: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        let end = self.tokens.last().map(|token| token.offset + 1).unwrap_or(0);
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| ParseError::new("unexpected end of filter", end))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is the given keyword.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Word(word), .. }) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, expected: TokenKind) -> Result<(), ParseError> {
+        let token = self.next()?;
+        if token.kind == expected {
+            Ok(())
+        } else {
+            Err(ParseError::new(
+                format!("expected {} but found {}", expected, token.kind),
+                token.offset,
+            ))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.keyword(keyword) {
+            return Ok(());
+        }
+        let token = self.next()?;
+        Err(ParseError::new(
+            format!("expected {} but found {}", keyword, token.kind),
+            token.offset,
+        ))
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, ParseError> {
+        let mut filters = vec![self.parse_and()?];
+        while self.keyword("OR") {
+            filters.push(self.parse_and()?);
+        }
+        Ok(if filters.len() == 1 { filters.remove(0) } else { Filter::Or(filters) })
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, ParseError> {
+        let mut filters = vec![self.parse_unary()?];
+        while self.keyword("AND") {
+            filters.push(self.parse_unary()?);
+        }
+        Ok(if filters.len() == 1 { filters.remove(0) } else { Filter::And(filters) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter, ParseError> {
+        let offset = self.peek().map(|token| token.offset).unwrap_or(0);
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ParseError::new("the filter is nested too deeply", offset));
+        }
+
+        let filter = if self.keyword("NOT") {
+            Filter::Not(Box::new(self.parse_unary()?))
+        } else if self.peek().map(|token| &token.kind) == Some(&TokenKind::LeftParen) {
+            self.next()?;
+            let filter = self.parse_or()?;
+            self.expect(TokenKind::RightParen)?;
+            filter
+        } else {
+            self.conditions += 1;
+            if self.conditions > MAX_CONDITIONS {
+                return Err(ParseError::new(
+                    format!("a filter can have at most {} conditions", MAX_CONDITIONS),
+                    offset,
+                ));
+            }
+            self.parse_condition()?
+        };
+
+        self.depth -= 1;
+        Ok(filter)
+    }
+
+    fn parse_condition(&mut self) -> Result<Filter, ParseError> {
+        let token = self.next()?;
+        let field = match token.kind {
+            TokenKind::Word(word) if !is_keyword(&word) => word.to_ascii_lowercase(),
+            other => {
+                return Err(ParseError::new(
+                    format!("expected a field name but found {}", other),
+                    token.offset,
+                ))
+            }
+        };
+
+        if let Some(Token { kind: TokenKind::Op(op), .. }) = self.peek().cloned() {
+            self.next()?;
+            let value = self.parse_value()?;
+            return Ok(Filter::Compare { field, op, value });
+        }
+
+        if self.keyword("IS") {
+            let negated = self.keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(Filter::IsNull { field, negated });
+        }
+        if self.keyword("BETWEEN") {
+            let low = self.parse_value()?;
+            self.expect_keyword("AND")?;
+            let high = self.parse_value()?;
+            return Ok(Filter::Between { field, low, high });
+        }
+        if self.keyword("CONTAINS") {
+            let term = self.parse_text()?;
+            return Ok(Filter::Contains { field, term });
+        }
+
+        let negated = self.keyword("NOT");
+        if self.keyword("IN") {
+            self.expect(TokenKind::LeftParen)?;
+            let mut values = vec![self.parse_value()?];
+            while self.peek().map(|token| &token.kind) == Some(&TokenKind::Comma) {
+                self.next()?;
+                values.push(self.parse_value()?);
+            }
+            self.expect(TokenKind::RightParen)?;
+            return Ok(Filter::In { field, values, negated });
+        }
+        if self.keyword("LIKE") {
+            let pattern = self.parse_text()?;
+            return Ok(Filter::Like { field, pattern, negated });
+        }
+
+        let end = self.tokens.last().map(|token| token.offset + 1).unwrap_or(0);
+        let (found, offset) = match self.peek() {
+            Some(token) => (token.kind.to_string(), token.offset),
+            None => (String::from("the end of the filter"), end),
+        };
+        Err(ParseError::new(
+            format!("expected a comparison after {} but found {}", field, found),
+            offset,
+        ))
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Quoted(text) => Ok(Value::Text(text)),
+            TokenKind::Word(word) if !is_keyword(&word) => Ok(if let Ok(number) = word.parse() {
+                Value::Integer(number)
+            } else if let Ok(number) = word.parse() {
+                Value::Real(number)
+            } else {
+                Value::Text(word)
+            }),
+            other => Err(ParseError::new(
+                format!("expected a value but found {}", other),
+                token.offset,
+            )),
+        }
+    }
+
+    fn parse_text(&mut self) -> Result<String, ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Quoted(text) => Ok(text),
+            TokenKind::Word(word) if !is_keyword(&word) => Ok(word),
+            other => Err(ParseError::new(
+                format!("expected some text but found {}", other),
+                token.offset,
+            )),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["AND", "OR", "NOT", "IN", "IS", "NULL", "LIKE", "CONTAINS", "BETWEEN"]
+        .iter()
+        .any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::models::fields::FieldType;
+
+    const fn field_of(name: &'static str, column: &'static str, kind: FieldType) -> Field {
+        Field { name, column, kind, filterable: true, sortable: false, via: None }
+    }
+
+    const BOOKS: EntityFields = EntityFields {
+        table: "book",
+        record_columns: "book.id",
+        fields: &[
+            field_of("title", "book.title", FieldType::Text),
+            field_of("author", "book.author", FieldType::Text),
+            field_of("genre", "book.genre", FieldType::Text),
+            field_of("pages", "book.pages", FieldType::Integer),
+            field_of("rating", "book.rating", FieldType::Integer),
+        ],
+    };
+
+    fn field(name: &str) -> String {
+        name.to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let filter = Filter::parse("rating >= 4 and genre in (Fantasy, 'Science Fiction') or pages<400").unwrap();
+        assert_eq!(
+            filter,
+            Filter::Or(vec![
+                Filter::And(vec![
+                    Filter::Compare { field: field("rating"), op: CompareOp::GtEq, value: Value::Integer(4) },
+                    Filter::In {
+                        field: field("genre"),
+                        values: vec![Value::Text(field("Fantasy")), Value::Text(field("Science Fiction"))],
+                        negated: false,
+                    },
+                ]),
+                Filter::Compare { field: field("pages"), op: CompareOp::Lt, value: Value::Integer(400) },
+            ])
+        );
+    }
+
+    #[test]
+    fn filters_compile_to_parameterized_sql() {
+        let filter = Filter::parse(
+            r#"NOT (rating IS NULL OR title CONTAINS "50%") AND author NOT LIKE 'J%' AND pages BETWEEN 100 AND 2.5e2"#,
+        )
+        .unwrap();
+        let compiled = filter.compile(&BOOKS).unwrap();
+        assert_eq!(
+            compiled.sql,
+            "(NOT (book.rating IS NULL OR book.title LIKE ? ESCAPE '\\') AND book.author NOT LIKE ? \
+             AND book.pages BETWEEN ? AND ?)"
+        );
+        assert_eq!(
+            compiled.params,
+            vec![
+                SqlValue::Text(field("%50\\%%")),
+                SqlValue::Text(field("J%")),
+                SqlValue::Integer(100),
+                SqlValue::Real(250.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_position() {
+        let message = |text: &str| match Filter::parse(text).and_then(|filter| filter.compile(&BOOKS)) {
+            Err(err) => err.to_string(),
+            Ok(compiled) => panic!("{} should not compile: {:?}", text, compiled),
+        };
+        assert_eq!(message("rating >="), "unexpected end of filter at position 8 of the filter");
+        assert_eq!(message("rating = 4 pages"), "unexpected \"pages\" at position 11 of the filter");
+        assert_eq!(message("title = 'dune"), "unterminated string at position 8 of the filter");
+        assert!(message("medium = paper").starts_with("medium can't be filtered on"));
+        assert!(message("title = x; DROP TABLE book").starts_with("unexpected character ';'"));
+        assert!(message(&"(".repeat(40)).contains("nested too deeply"));
+    }
+}
@@ -0,0 +1,278 @@
+/*!
+
+# fuzzy
+
+Typo tolerant matching of book titles and authors, so that
+"dostoyevsky" still finds "Fyodor Dostoevsky" and "bronte" finds
+"Charlotte Brontë".
+
+Both sides are folded first: lowercased, stripped of accents and
+punctuation, with letters like `ß` and `ø` spelled out. The score of a
+candidate is then the best of two similarities, each between 0 and 1:
+trigram similarity (how many three letter chunks the two share),
+which forgives dropped and added letters, and one minus the edit
+distance over the longer length, which forgives swapped letters. A
+query shorter than the candidate is compared against every run of as
+many words, so a surname alone matches a full name.
+
+Readings are matched through the book they refer to. Scoring happens
+in Rust over every book, which is fine at the size of a personal
+library but doesn't use an index.
+
+!*/
+
+use rusqlite::{Connection, Row};
+use serde::Serialize;
+use std::collections::HashSet;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use super::book::{Book, BOOK_COLUMNS};
+use super::reading::{Reading, READING_COLUMNS};
+use crate::api::error::ApiError;
+
+/// The score a candidate needs unless the client asks for another.
+pub const DEFAULT_MIN_SCORE: f64 = 0.5;
+/// The most matches a fuzzy search returns.
+pub const MAX_MATCHES: usize = 50;
+
+/// Which of a book's fields a fuzzy search compares against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FuzzyField {
+    Title,
+    Author,
+    /// Whichever of title and author matches better.
+    Any,
+}
+
+impl FuzzyField {
+    pub fn from_param(param: &str) -> Option<FuzzyField> {
+        match param {
+            "title" => Some(FuzzyField::Title),
+            "author" => Some(FuzzyField::Author),
+            "any" => Some(FuzzyField::Any),
+            _ => None,
+        }
+    }
+}
+
+/// A record that matched a fuzzy search, serialized as the record's own
+/// fields plus `score`, where 1 is an exact match after folding.
+#[derive(Serialize, Debug)]
+pub struct FuzzyMatch<T> {
+    #[serde(flatten)]
+    pub record: T,
+    pub score: f64,
+}
+
+/**
+Returns the books whose `field` resembles `term` with a score of at
+least `min_score`, best first and at most `MAX_MATCHES` of them.
+*/
+pub fn fuzzy_books(
+    conn: &Connection,
+    field: FuzzyField,
+    term: &str,
+    min_score: f64,
+) -> Result<Vec<FuzzyMatch<Book>>, ApiError> {
+    let sql = format!(
+        "SELECT {}, book.title, book.author FROM book WHERE deleted_at IS NULL;",
+        BOOK_COLUMNS
+    );
+    fuzzy_match(conn, &sql, field, term, min_score, Book::from_row)
+}
+
+/**
+Returns the readings of the books whose `field` resembles `term`, each
+scored by how well its book matches. Otherwise like `fuzzy_books`.
+*/
+pub fn fuzzy_readings(
+    conn: &Connection,
+    field: FuzzyField,
+    term: &str,
+    min_score: f64,
+) -> Result<Vec<FuzzyMatch<Reading>>, ApiError> {
+    let sql = format!(
+        "SELECT {}, book.title, book.author FROM reading JOIN book ON book.id = reading.book
+WHERE reading.deleted_at IS NULL AND book.deleted_at IS NULL
+ORDER BY reading.start_date, reading.id;",
+        READING_COLUMNS
+    );
+    fuzzy_match(conn, &sql, field, term, min_score, Reading::from_row)
+}
+
+// `sql` selects the record's columns followed by the book's title and author
+fn fuzzy_match<T>(
+    conn: &Connection,
+    sql: &str,
+    field: FuzzyField,
+    term: &str,
+    min_score: f64,
+    from_row: fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Result<Vec<FuzzyMatch<T>>, ApiError> {
+    let query = fold(term);
+    if query.is_empty() {
+        return Err(ApiError::invalid_parameter("term", "the search term must hold a letter or digit"));
+    }
+
+    let mut stmt = conn.prepare(sql)?;
+    let title_column = stmt.column_count() - 2;
+    let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+    let mut matches = Vec::new();
+    while let Some(row) = rows.next()? {
+        let title: String = row.get(title_column)?;
+        let author: String = row.get(title_column + 1)?;
+        let score = match field {
+            FuzzyField::Title => similarity(&query, &fold(&title)),
+            FuzzyField::Author => similarity(&query, &fold(&author)),
+            FuzzyField::Any => similarity(&query, &fold(&title)).max(similarity(&query, &fold(&author))),
+        };
+        if score >= min_score {
+            matches.push(FuzzyMatch {
+                record: from_row(row)?,
+                score,
+            });
+        }
+    }
+
+    // A stable sort, so equally good matches stay in the statement's order
+    matches.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    matches.truncate(MAX_MATCHES);
+    Ok(matches)
+}
+
+/**
+Lowercases `text`, strips accents, spells out letters that don't
+decompose into a base letter and an accent, and turns everything that
+isn't a letter or digit into single spaces.
+*/
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'œ' => folded.push_str("oe"),
+            'þ' => folded.push_str("th"),
+            'ø' => folded.push('o'),
+            'ł' => folded.push('l'),
+            'đ' | 'ð' => folded.push('d'),
+            c if c.is_alphanumeric() => folded.push(c),
+            _ => folded.push(' '),
+        }
+    }
+    folded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/**
+How much the folded `candidate` resembles the folded `query`, from 0
+to 1. Compares the whole candidate as well as each run of as many words
+as the query has, and keeps the best.
+*/
+pub fn similarity(query: &str, candidate: &str) -> f64 {
+    let mut best = pair_similarity(query, candidate);
+    let query_words = query.split(' ').count();
+    let words: Vec<&str> = candidate.split(' ').collect();
+    if words.len() > query_words {
+        for window in words.windows(query_words) {
+            best = best.max(pair_similarity(query, &window.join(" ")));
+        }
+    }
+    best
+}
+
+fn pair_similarity(a: &str, b: &str) -> f64 {
+    trigram_similarity(a, b).max(edit_similarity(a, b))
+}
+
+/// Shared trigrams over all trigrams, with each word padded the way
+/// Postgres' pg_trgm does so short words and word starts still count.
+fn trigram_similarity(a: &str, b: &str) -> f64 {
+    let a = trigrams(a);
+    let b = trigrams(b);
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let shared = a.intersection(&b).count();
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    let mut trigrams = HashSet::new();
+    for word in text.split(' ').filter(|word| !word.is_empty()) {
+        let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(" ".chars()).collect();
+        for window in padded.windows(3) {
+            trigrams.insert([window[0], window[1], window[2]]);
+        }
+    }
+    trigrams
+}
+
+/// One minus the Levenshtein distance over the length of the longer string.
+fn edit_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == b_char { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folding_drops_case_accents_and_punctuation() {
+        assert_eq!(fold("Charlotte Brontë"), "charlotte bronte");
+        assert_eq!(fold("  Gabriel García-Márquez! "), "gabriel garcia marquez");
+        assert_eq!(fold("Straße, Søren"), "strasse soren");
+    }
+
+    #[test]
+    fn misspellings_still_score_well() {
+        let author = fold("Fyodor Dostoevsky");
+        assert!(similarity(&fold("Dostoyevsky"), &author) > 0.8);
+        assert!(similarity(&fold("fyodor dostoyevski"), &author) > 0.8);
+        assert_eq!(similarity(&fold("dostoevsky"), &author), 1.0);
+        assert!(similarity(&fold("tolstoy"), &author) < DEFAULT_MIN_SCORE);
+    }
+
+    #[test]
+    fn fuzzy_books_ranks_the_closest_first() {
+        let conn = crate::api::models::common::test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Crime and Punishment', 'Fyodor Dostoevsky', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Jane Eyre', 'Charlotte Brontë', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Crime Novel', 'Someone', 'paper');",
+        )
+        .unwrap();
+
+        let found = fuzzy_books(&conn, FuzzyField::Author, "dostoyevsky", DEFAULT_MIN_SCORE).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].record.id(), Some(1));
+
+        let found = fuzzy_books(&conn, FuzzyField::Any, "bronte", DEFAULT_MIN_SCORE).unwrap();
+        assert_eq!(found[0].record.id(), Some(2));
+
+        let found = fuzzy_books(&conn, FuzzyField::Title, "crime and punishmnet", 0.3).unwrap();
+        assert_eq!(found.iter().map(|m| m.record.id()).collect::<Vec<_>>(), vec![Some(1), Some(3)]);
+
+        conn.execute_batch("INSERT INTO reading (book, start_date) VALUES (2, '2020-01-01');").unwrap();
+        let found = fuzzy_readings(&conn, FuzzyField::Author, "charlote bronte", DEFAULT_MIN_SCORE).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(fuzzy_books(&conn, FuzzyField::Title, "?!", DEFAULT_MIN_SCORE).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+/*!
+
+# history
+
+Every write to a book or a reading is recorded as a revision in the
+append-only `history` table: what was done (`create`, `update`,
+`delete`, `restore` or `revert`), who did it, when, and the record as
+JSON before and after. Revisions are numbered per record from 1, and
+outlive the record itself, so a book purged from the trash keeps its
+history.
+
+Who made a change is whatever the client said in the `X-Actor` header,
+there are no accounts to check it against. Fields that change without
+the record being edited, the `version` and a reading's progress (see
+`models::progress`), are left out of the snapshots, and an update that
+changes nothing else isn't recorded.
+
+A record can be reverted to how it was after any of its revisions,
+which is recorded as a revision of its own. Going back to a deletion
+is what the trash is for, see `models::trash`.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::common::{self, Page, PageParams};
+use crate::api::error::ApiError;
+
+/// Fields the snapshots leave out, since they change without the record being edited.
+const DERIVED_FIELDS: &[&str] = &["version", "current_page", "percent_complete", "estimated_finish"];
+
+/// The kinds of records with a history.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Entity {
+    Book,
+    Reading,
+}
+
+impl Entity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Entity::Book => "book",
+            Entity::Reading => "reading",
+        }
+    }
+}
+
+/// What a revision did to its record.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Revert,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Restore => "restore",
+            Action::Revert => "revert",
+        }
+    }
+}
+
+/// A field whose value a revision changed. A field the record didn't have before or after is null.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FieldChange {
+    field: String,
+    from: Value,
+    to: Value,
+}
+
+/// One revision of a record, with the fields it changed worked out from the snapshots.
+#[derive(Serialize, Debug)]
+pub struct Revision {
+    revision: u32,
+    action: String,
+    actor: Option<String>,
+    changed_at: String,
+    changes: Vec<FieldChange>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl Revision {
+    fn from_row(row: &Row) -> Result<Revision, rusqlite::Error> {
+        let before = parse_snapshot(row.get(4)?)?;
+        let after = parse_snapshot(row.get(5)?)?;
+        Ok(Revision {
+            revision: row.get(0)?,
+            action: row.get(1)?,
+            actor: row.get(2)?,
+            changed_at: row.get(3)?,
+            changes: changes(before.as_ref(), after.as_ref()),
+            before,
+            after,
+        })
+    }
+}
+
+/**
+Adds a revision to the history of the record, given as it was before
+and after the write. Either side is None when there was no record, as
+before a creation. Callers should run this in the transaction that
+made the write, so the two are committed together.
+*/
+pub fn record<T: Serialize>(
+    conn: &Connection,
+    entity: Entity,
+    id: u32,
+    action: Action,
+    before: Option<&T>,
+    after: Option<&T>,
+    actor: Option<&str>,
+) -> Result<(), ApiError> {
+    let before = before.map(snapshot).transpose()?;
+    let after = after.map(snapshot).transpose()?;
+    if action == Action::Update && before == after {
+        return Ok(());
+    }
+    let actor = actor.map(str::trim).filter(|actor| !actor.is_empty());
+
+    conn.execute_named(
+        "INSERT INTO history (entity, entity_id, revision, action, actor, changed_at, before, after)
+VALUES (:entity, :id, (
+    SELECT coalesce(max(revision), 0) + 1 FROM history WHERE entity = :entity AND entity_id = :id
+), :action, :actor, :changed_at, :before, :after);",
+        &[
+            (":entity", &entity.as_str() as &dyn rusqlite::ToSql),
+            (":id", &id),
+            (":action", &action.as_str()),
+            (":actor", &actor),
+            (":changed_at", &common::now_timestamp()),
+            (":before", &before.map(|before| before.to_string())),
+            (":after", &after.map(|after| after.to_string())),
+        ],
+    )?;
+    Ok(())
+}
+
+/**
+Returns one page of the record's history, latest revision first, along
+with the number of revisions. Records in the trash or purged from it
+still have a history, but a record that never existed is a `NotFound`.
+*/
+pub fn query_history(conn: &Connection, entity: Entity, id: u32, page: &PageParams) -> Result<Page<Revision>, ApiError> {
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":entity", &entity.as_str()), (":id", &id)];
+    let total: u32 = conn.query_row_named(
+        "SELECT count(*) FROM history WHERE entity = :entity AND entity_id = :id;",
+        params,
+        |row| row.get(0),
+    )?;
+    if total == 0 {
+        // Records written before the history existed have none
+        let sql = format!("SELECT id FROM {} WHERE id = :id;", entity.as_str());
+        conn.query_row_named(&sql, &[(":id", &id)], |row| row.get::<_, u32>(0))
+            .optional()?
+            .ok_or(ApiError::NotFound(entity.as_str()))?;
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT revision, action, actor, changed_at, before, after FROM history
+WHERE entity = :entity AND entity_id = :id ORDER BY revision DESC LIMIT :limit OFFSET :offset;",
+    )?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[
+        (":entity", &entity.as_str()),
+        (":id", &id),
+        (":limit", &page.limit),
+        (":offset", &page.offset),
+    ];
+    let items = stmt
+        .query_map_named(params, Revision::from_row)?
+        .collect::<Result<Vec<Revision>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/**
+Returns the record as it was after the given revision, for reverting
+to it. Its derived fields are left at their defaults. A revision that
+deleted the record is a `Conflict`, since there is nothing to go back
+to but the trash.
+*/
+pub fn snapshot_at<T: DeserializeOwned>(conn: &Connection, entity: Entity, id: u32, revision: u32) -> Result<T, ApiError> {
+    let after: Option<String> = conn
+        .query_row_named(
+            "SELECT after FROM history WHERE entity = :entity AND entity_id = :id AND revision = :revision;",
+            &[
+                (":entity", &entity.as_str() as &dyn rusqlite::ToSql),
+                (":id", &id),
+                (":revision", &revision),
+            ],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or(ApiError::NotFound("revision"))?;
+    match after {
+        Some(after) => Ok(serde_json::from_str(&after)?),
+        None => Err(ApiError::Conflict(format!(
+            "revision {} deleted the {}, restore it from the trash instead",
+            revision,
+            entity.as_str()
+        ))),
+    }
+}
+
+// The record as a JSON object without its derived fields
+fn snapshot<T: Serialize>(record: &T) -> Result<Value, ApiError> {
+    let mut value = serde_json::to_value(record)?;
+    if let Value::Object(fields) = &mut value {
+        for field in DERIVED_FIELDS {
+            fields.remove(*field);
+        }
+    }
+    Ok(value)
+}
+
+fn parse_snapshot(text: Option<String>) -> Result<Option<Value>, rusqlite::Error> {
+    text.map(|text| serde_json::from_str(&text))
+        .transpose()
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+// The fields whose values differ between two snapshots, in the order of their names
+fn changes(before: Option<&Value>, after: Option<&Value>) -> Vec<FieldChange> {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .map(|field| FieldChange {
+            field: field.clone(),
+            from: before.get(field).cloned().unwrap_or(Value::Null),
+            to: after.get(field).cloned().unwrap_or(Value::Null),
+        })
+        .filter(|change| change.from != change.to)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::book::{self, Book};
+    use crate::api::models::patch::Patch;
+
+    #[test]
+    fn changes_are_recorded_and_can_be_reverted() {
+        let conn = common::test_connection();
+        let new_book: Book =
+            serde_json::from_str(r#"{"title": "Dune", "author": "Frank Herbert", "medium": "paper"}"#).unwrap();
+        book::create_book(&conn, new_book, Some("ana")).unwrap();
+        let patch = Patch::from_body(None, br#"{"title": "Dune Messiah", "pages": 256}"#).unwrap();
+        book::patch_book(&conn, 1, &patch, None).unwrap();
+        // Changing nothing leaves no trace
+        book::patch_book(&conn, 1, &patch, None).unwrap();
+
+        let history = query_history(&conn, Entity::Book, 1, &PageParams::default()).unwrap();
+        assert_eq!(history.total, 2);
+        let update = &history.items[0];
+        assert_eq!((update.revision, update.action.as_str(), update.actor.as_deref()), (2, "update", None));
+        let fields: Vec<&str> = update.changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(fields, vec!["pages", "title"]);
+        assert_eq!(update.changes[1].from, "Dune");
+        assert_eq!(history.items[1].actor.as_deref(), Some("ana"));
+
+        let reverted = book::revert_book(&conn, 1, 1, Some("ben")).unwrap();
+        assert_eq!(serde_json::to_value(&reverted).unwrap()["title"], "Dune");
+        let latest = &query_history(&conn, Entity::Book, 1, &PageParams::default()).unwrap().items[0];
+        assert_eq!((latest.revision, latest.action.as_str()), (3, "revert"));
+
+        book::delete_book_by_id(&conn, 1, book::OnReadings::Restrict, None).unwrap();
+        assert!(matches!(book::revert_book(&conn, 1, 1, None), Err(ApiError::NotFound("book"))));
+        assert!(matches!(snapshot_at::<Book>(&conn, Entity::Book, 1, 4), Err(ApiError::Conflict(_))));
+        assert!(matches!(snapshot_at::<Book>(&conn, Entity::Book, 1, 9), Err(ApiError::NotFound("revision"))));
+        assert!(matches!(query_history(&conn, Entity::Book, 2, &PageParams::default()), Err(ApiError::NotFound("book"))));
+
+        assert!(conn.execute_batch("UPDATE history SET actor = 'eve';").is_err());
+        assert!(conn.execute_batch("DELETE FROM history;").is_err());
+    }
+}
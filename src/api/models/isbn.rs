@@ -0,0 +1,149 @@
+/*!
+
+# isbn
+
+International Standard Book Numbers. An ISBN comes in two forms: the
+older ten character ISBN-10, whose last character is a check digit
+from 0 to 9 or X, and the thirteen digit ISBN-13, which is the ISBN-10
+prefixed with 978 and given a new check digit. ISBN-13s starting with
+979 have no ISBN-10.
+
+Books store their ISBN as the bare ISBN-13, so a book added with
+"0-306-40615-2" and one added with "978-0-306-40615-7" are recognized
+as the same edition. Hyphens, spaces and a leading "ISBN" are ignored
+when reading one.
+
+!*/
+
+use serde::Serialize;
+
+/// A valid ISBN, held as its ISBN-13.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Isbn(String);
+
+/// Both forms of an ISBN, for the /isbn route.
+#[derive(Serialize, Debug)]
+pub struct IsbnForms {
+    pub isbn13: String,
+    pub isbn10: Option<String>,
+}
+
+impl Isbn {
+    /**
+    Reads an ISBN-10 or ISBN-13, checking its check digit. The error
+    says what is wrong with the text.
+    */
+    pub fn parse(text: &str) -> Result<Isbn, String> {
+        let mut digits: String = text.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+        if !digits.is_ascii() {
+            return Err(format!("{} isn't an ISBN, those are made of digits", text));
+        }
+        // A label like "ISBN-13:", which is "ISBN13:" without the hyphen
+        if digits.get(..4).is_some_and(|label| label.eq_ignore_ascii_case("isbn")) {
+            digits.drain(..4);
+            if let Some(label) = ["10:", "13:", ":"].iter().find(|label| digits.starts_with(*label)) {
+                digits.drain(..label.len());
+            }
+        }
+        let digits = digits.to_ascii_uppercase();
+
+        match digits.len() {
+            10 => {
+                let (body, check) = digits.split_at(9);
+                let check_is_valid = check == "X" || check.bytes().all(|b| b.is_ascii_digit());
+                if !body.bytes().all(|b| b.is_ascii_digit()) || !check_is_valid {
+                    return Err(format!("{} isn't an ISBN-10, those are 9 digits followed by a digit or X", text));
+                }
+                let expected = isbn10_check_digit(body);
+                if !check.starts_with(expected) {
+                    return Err(format!("the check digit of ISBN-10 {} should be {}", digits, expected));
+                }
+                let body = format!("978{}", body);
+                let check = isbn13_check_digit(&body);
+                Ok(Isbn(format!("{}{}", body, check)))
+            }
+            13 => {
+                if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(format!("{} isn't an ISBN-13, those are 13 digits", text));
+                }
+                if !digits.starts_with("978") && !digits.starts_with("979") {
+                    return Err(format!("ISBN-13 {} should start with 978 or 979", digits));
+                }
+                let (body, check) = digits.split_at(12);
+                let expected = isbn13_check_digit(body);
+                if !check.starts_with(expected) {
+                    return Err(format!("the check digit of ISBN-13 {} should be {}", digits, expected));
+                }
+                Ok(Isbn(digits))
+            }
+            _ => Err(format!("{} isn't an ISBN, those have 10 or 13 digits", text)),
+        }
+    }
+
+    /// The bare ISBN-13, the form books store.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The ISBN-10, which only ISBNs starting with 978 have.
+    pub fn to_isbn10(&self) -> Option<String> {
+        let body = self.0.strip_prefix("978")?.get(..9)?;
+        Some(format!("{}{}", body, isbn10_check_digit(body)))
+    }
+
+    pub fn forms(&self) -> IsbnForms {
+        IsbnForms {
+            isbn13: self.0.clone(),
+            isbn10: self.to_isbn10(),
+        }
+    }
+}
+
+// The digits are weighted 10 down to 2, and the check digit makes the sum divisible by 11
+fn isbn10_check_digit(body: &str) -> char {
+    let sum: u32 = body
+        .bytes()
+        .zip((2..=10).rev())
+        .map(|(digit, weight)| u32::from(digit - b'0') * weight)
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        digit => (b'0' + digit as u8) as char,
+    }
+}
+
+// The digits are weighted 1, 3, 1, 3, ... and the check digit makes the sum divisible by 10
+fn isbn13_check_digit(body: &str) -> char {
+    let sum: u32 = body
+        .bytes()
+        .zip([1, 3].iter().cycle())
+        .map(|(digit, weight)| u32::from(digit - b'0') * weight)
+        .sum();
+    (b'0' + ((10 - sum % 10) % 10) as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_forms_are_read_and_converted() {
+        let isbn = Isbn::parse("0-306-40615-2").unwrap();
+        assert_eq!(isbn.as_str(), "9780306406157");
+        assert_eq!(isbn.to_isbn10().as_deref(), Some("0306406152"));
+        assert_eq!(Isbn::parse("ISBN 978-0-306-40615-7"), Ok(isbn));
+
+        assert_eq!(Isbn::parse("080442957x").unwrap().as_str(), "9780804429573");
+        assert_eq!(Isbn::parse("9780804429573").unwrap().to_isbn10().as_deref(), Some("080442957X"));
+        assert_eq!(Isbn::parse("979-10-90636-07-1").unwrap().to_isbn10(), None);
+
+        assert_eq!(
+            Isbn::parse("0-306-40615-3").unwrap_err(),
+            "the check digit of ISBN-10 0306406153 should be 2"
+        );
+        assert!(Isbn::parse("9770306406157").is_err());
+        assert!(Isbn::parse("12345").is_err());
+        assert!(Isbn::parse("ISBN-13: 978-0-306-40615-7").is_ok());
+        assert!(Isbn::parse("03064061é").is_err());
+    }
+}
@@ -1,3 +1,19 @@
+pub mod author;
+pub mod batch;
 pub mod book;
 pub mod reading;
 pub mod common;
+pub mod patch;
+pub mod progress;
+pub mod version;
+pub mod search;
+pub mod series;
+pub mod shelf;
+pub mod tag;
+pub mod trash;
+pub mod fields;
+pub mod filter;
+pub mod fuzzy;
+pub mod history;
+pub mod isbn;
+pub mod note;
@@ -0,0 +1,356 @@
+/*!
+
+# note
+
+Long-form notes on a book or a reading, written in Markdown. The short
+`notes` field of books and readings is fine for a line or two, notes
+are for the rest: they are stored apart from the records, so they can
+run to tens of kilobytes (up to the `note_body_limit_kb` setting, see
+`crate::config`) without weighing down every listing, and a book or a
+reading can have any number of them.
+
+Besides its Markdown `body`, each note comes with fields worked out
+from it:
+
+- `title`, the text of the note's first heading, if it has one
+- `excerpt`, the start of the note as plain text, for listings
+- `word_count`, the number of words with the markup left out
+
+Notes of a book or a reading in the trash are hidden along with it,
+and go when it is purged.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use super::book::query_book_by_id;
+use super::common::{self, Page, PageParams};
+use super::history::Entity;
+use super::reading::query_reading_by_id;
+use crate::api::error::ApiError;
+
+/// How many characters of plain text an excerpt holds.
+const EXCERPT_LENGTH: usize = 200;
+
+const NOTE_COLUMNS: &str = "note.id, note.book, note.reading, note.body, note.created_at, note.updated_at, note.version";
+
+/// Leaves out the notes of books and readings in the trash.
+const NOTE_IS_VISIBLE: &str = "(note.book IS NULL OR note.book IN (SELECT id FROM book WHERE deleted_at IS NULL))
+AND (note.reading IS NULL OR note.reading IN (SELECT id FROM reading WHERE deleted_at IS NULL))";
+
+#[derive(Serialize, Debug)]
+pub struct Note {
+    id: u32,
+    book: Option<u32>,
+    reading: Option<u32>,
+    title: Option<String>,
+    excerpt: String,
+    word_count: u32,
+    body: String,
+    created_at: String,
+    updated_at: String,
+    // Bumped on every write, see `models::version`
+    version: u32,
+}
+
+/// The body of a request writing a note.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NoteRequest {
+    pub body: String,
+}
+
+impl Note {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn from_row(row: &Row) -> Result<Note, rusqlite::Error> {
+        let body: String = row.get(3)?;
+        let text = plain_text(&body);
+        Ok(Note {
+            id: row.get(0)?,
+            book: row.get(1)?,
+            reading: row.get(2)?,
+            title: title(&body),
+            excerpt: excerpt(&text),
+            word_count: text.split_whitespace().count() as u32,
+            body,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            version: row.get(6)?,
+        })
+    }
+}
+
+impl NoteRequest {
+    /**
+    Reads a note from a request body: the Markdown itself if the
+    Content-Type is text/markdown or text/plain, or otherwise a JSON
+    object holding it as `body`.
+    */
+    pub fn from_body(content_type: Option<&str>, body: &[u8]) -> Result<NoteRequest, ApiError> {
+        let media_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase());
+        match media_type.as_deref() {
+            Some("text/markdown") | Some("text/plain") => match std::str::from_utf8(body) {
+                Ok(text) => Ok(NoteRequest { body: text.to_string() }),
+                Err(_) => Err(ApiError::InvalidBody(String::from("a note has to be UTF-8 text"))),
+            },
+            _ => Ok(serde_json::from_slice(body)?),
+        }
+    }
+
+    fn validated(self) -> Result<NoteRequest, ApiError> {
+        if self.body.trim().is_empty() {
+            return Err(ApiError::invalid_field("body", "a note can't be empty"));
+        }
+        Ok(self)
+    }
+}
+
+/**
+Returns one page of the notes of the book or reading with the given
+id, oldest first, along with the number of notes it has.
+*/
+pub fn query_notes(conn: &Connection, owner: Entity, id: u32, page: &PageParams) -> Result<Page<Note>, ApiError> {
+    check_owner_exists(conn, owner, id)?;
+    let column = owner.as_str();
+
+    let count_sql = format!("SELECT count(*) FROM note WHERE {} = :id;", column);
+    let total: u32 = conn.query_row_named(&count_sql, &[(":id", &id)], |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT {} FROM note WHERE {} = :id ORDER BY note.created_at, note.id LIMIT :limit OFFSET :offset;",
+        NOTE_COLUMNS, column
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":id", &id), (":limit", &page.limit), (":offset", &page.offset)];
+    let items = stmt
+        .query_map_named(params, Note::from_row)?
+        .collect::<Result<Vec<Note>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+pub fn query_note_by_id(conn: &Connection, id: u32) -> Result<Note, ApiError> {
+    let sql = format!("SELECT {} FROM note WHERE note.id = :id AND {};", NOTE_COLUMNS, NOTE_IS_VISIBLE);
+    conn.query_row_named(&sql, &[(":id", &id)], Note::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("note"))
+}
+
+/// Adds a note to the book or reading with the given id and returns it as stored.
+pub fn create_note(conn: &Connection, owner: Entity, id: u32, request: NoteRequest) -> Result<Note, ApiError> {
+    check_owner_exists(conn, owner, id)?;
+    let request = request.validated()?;
+    let now = common::now_timestamp();
+    let sql = format!(
+        "INSERT INTO note ({}, body, created_at, updated_at) VALUES (:id, :body, :now, :now);",
+        owner.as_str()
+    );
+    conn.execute_named(
+        &sql,
+        &[(":id", &id as &dyn rusqlite::ToSql), (":body", &request.body), (":now", &now)],
+    )?;
+    query_note_by_id(conn, conn.last_insert_rowid() as u32)
+}
+
+/// Replaces the Markdown of the note with the given id and returns the note as stored.
+pub fn update_note(conn: &Connection, id: u32, request: NoteRequest) -> Result<Note, ApiError> {
+    query_note_by_id(conn, id)?;
+    let request = request.validated()?;
+    conn.execute_named(
+        "UPDATE note SET body = :body, updated_at = :now, version = version + 1 WHERE id = :id;",
+        &[
+            (":id", &id as &dyn rusqlite::ToSql),
+            (":body", &request.body),
+            (":now", &common::now_timestamp()),
+        ],
+    )?;
+    query_note_by_id(conn, id)
+}
+
+/// Erases the note with the given id, returning the number of rows removed.
+pub fn delete_note_by_id(conn: &Connection, id: u32) -> Result<usize, ApiError> {
+    let sql = format!("DELETE FROM note WHERE note.id = :id AND {};", NOTE_IS_VISIBLE);
+    Ok(conn.execute_named(&sql, &[(":id", &id)])?)
+}
+
+fn check_owner_exists(conn: &Connection, owner: Entity, id: u32) -> Result<(), ApiError> {
+    match owner {
+        Entity::Book => query_book_by_id(conn, id).map(|_| ()),
+        Entity::Reading => query_reading_by_id(conn, id).map(|_| ()),
+    }
+}
+
+// The text of the first ATX heading ("# Title"), without its markup
+fn title(markdown: &str) -> Option<String> {
+    markdown.lines().find_map(|line| {
+        let text = heading_text(line.trim())?.trim_end_matches('#').trim();
+        Some(inline_text(text)).filter(|title| !title.is_empty())
+    })
+}
+
+// The plain text collapsed onto one line and cut short at a word if it is too long
+fn excerpt(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut excerpt = String::new();
+    for word in words {
+        if excerpt.chars().count() + word.chars().count() + 1 > EXCERPT_LENGTH {
+            excerpt.push('…');
+            break;
+        }
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(word);
+    }
+    excerpt
+}
+
+/// The Markdown read as plain text, with the markup of headings, quotes,
+/// lists, code, emphasis and links taken out.
+pub fn plain_text(markdown: &str) -> String {
+    markdown
+        .lines()
+        .map(str::trim)
+        // Code fences and thematic breaks hold no text of their own
+        .filter(|line| !line.starts_with("```") && !line.starts_with("~~~") && !is_break(line))
+        .map(|line| inline_text(block_text(line)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn heading_text(line: &str) -> Option<&str> {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[hashes..];
+    if (1..=6).contains(&hashes) && (rest.is_empty() || rest.starts_with(' ')) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+fn is_break(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3 && ["-", "*", "_"].iter().any(|mark| marks.chars().all(|c| c.to_string() == *mark))
+}
+
+// The line without the markers of the block it is in
+fn block_text(line: &str) -> &str {
+    let mut line = line;
+    while let Some(rest) = line.strip_prefix('>') {
+        line = rest.trim_start();
+    }
+    if let Some(text) = heading_text(line) {
+        return text.trim_end_matches('#').trim_end();
+    }
+    for marker in &["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            return rest.trim_start();
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 && (line[digits..].starts_with(". ") || line[digits..].starts_with(") ")) {
+        return line[digits + 2..].trim_start();
+    }
+    line
+}
+
+// The text without emphasis and code markers, and links and images reduced to their text
+fn inline_text(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '!' if chars.get(i + 1) == Some(&'[') => {}
+            '[' => {
+                if let Some((label, end)) = link(&chars, i) {
+                    out.push_str(&inline_text(&label));
+                    i = end;
+                    continue;
+                }
+                out.push('[');
+            }
+            '*' | '`' => {}
+            '~' if chars.get(i + 1) == Some(&'~') => i += 1,
+            // Underscores inside words, as in snake_case, aren't emphasis
+            '_' if i > 0
+                && i + 1 < chars.len()
+                && chars[i - 1].is_alphanumeric()
+                && chars[i + 1].is_alphanumeric() =>
+            {
+                out.push('_')
+            }
+            '_' => {}
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+// For a link starting at `start`, "[label](url)", its label and the index just past it
+fn link(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let close = start + chars[start..].iter().position(|c| *c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 1 + chars[close + 1..].iter().position(|c| *c == ')')?;
+    Some((chars[start + 1..close].iter().collect(), end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::book::{delete_book_by_id, OnReadings};
+
+    #[test]
+    fn notes_are_read_as_markdown() {
+        let markdown = "# On *Dune*\n\n> Fear is the **mind-killer**.\n\n- see [the appendix](https://example.com)\n\
+                        1. keep a snake_case word\n\n---\n\n```\nlet spice = 1;\n```";
+        assert_eq!(title(markdown).as_deref(), Some("On Dune"));
+        assert_eq!(
+            plain_text(markdown).split_whitespace().collect::<Vec<&str>>().join(" "),
+            "On Dune Fear is the mind-killer. see the appendix keep a snake_case word let spice = 1;"
+        );
+        assert!(excerpt(&"word ".repeat(100)).ends_with("word…"));
+
+        let conn = common::test_connection();
+        conn.execute_batch("INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');")
+            .unwrap();
+        let request = NoteRequest::from_body(Some("text/markdown; charset=utf-8"), markdown.as_bytes()).unwrap();
+        let note = create_note(&conn, Entity::Book, 1, request).unwrap();
+        assert_eq!((note.word_count, note.book, note.reading), (17, Some(1), None));
+        assert!(create_note(&conn, Entity::Reading, 1, NoteRequest { body: "x".into() }).is_err());
+        assert!(update_note(&conn, 1, NoteRequest { body: " \n".into() }).is_err());
+
+        let long = NoteRequest::from_body(None, format!(r#"{{"body": "{}"}}"#, "spice ".repeat(10_000)).as_bytes());
+        assert_eq!(update_note(&conn, 1, long.unwrap()).unwrap().version, 2);
+        assert_eq!(query_notes(&conn, Entity::Book, 1, &PageParams::default()).unwrap().total, 1);
+
+        delete_book_by_id(&conn, 1, OnReadings::Restrict, None).unwrap();
+        assert!(matches!(query_note_by_id(&conn, 1), Err(ApiError::NotFound("note"))));
+        conn.execute_batch("DELETE FROM book;").unwrap();
+        let notes: u32 = conn.query_row("SELECT count(*) FROM note;", rusqlite::NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(notes, 0);
+    }
+}
@@ -0,0 +1,203 @@
+/*!
+
+# patch
+
+Partial updates for records, for the PATCH routes. Two patch formats
+are understood, picked by the request's Content-Type:
+
+- `application/merge-patch+json` (RFC 7396), also assumed for plain
+  `application/json` or no Content-Type at all. The body is an object
+  holding just the fields to change, with `null` clearing a field.
+- `application/json-patch+json` (RFC 6902). The body is a list of
+  operations like `{"op": "replace", "path": "/rating", "value": 4}`.
+
+Either way the patch is applied to the record's JSON form and the
+result is read back into the record type, so a patch can't produce a
+record the create and update routes wouldn't accept.
+
+!*/
+
+use rusqlite::{Connection, ToSql};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::error::ApiError;
+
+pub const MERGE_PATCH_TYPE: &str = "application/merge-patch+json";
+pub const JSON_PATCH_TYPE: &str = "application/json-patch+json";
+
+#[derive(Debug)]
+pub enum Patch {
+    Merge(Value),
+    Json(json_patch::Patch),
+}
+
+impl Patch {
+    /**
+    Reads a patch body in the format named by `content_type`, ignoring
+    any parameters such as `charset`.
+    */
+    pub fn from_body(content_type: Option<&str>, body: &[u8]) -> Result<Patch, ApiError> {
+        let media_type = content_type.map(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        });
+
+        match media_type.as_deref() {
+            None | Some(MERGE_PATCH_TYPE) | Some("application/json") => {
+                let patch: Value = serde_json::from_slice(body)?;
+                if !patch.is_object() {
+                    return Err(ApiError::InvalidBody(String::from(
+                        "a merge patch must be a JSON object",
+                    )));
+                }
+                Ok(Patch::Merge(patch))
+            }
+            Some(JSON_PATCH_TYPE) => Ok(Patch::Json(serde_json::from_slice(body)?)),
+            Some(other) => Err(ApiError::UnsupportedMediaType(format!(
+                "{} is not a patch format, use {} or {}",
+                other, MERGE_PATCH_TYPE, JSON_PATCH_TYPE
+            ))),
+        }
+    }
+}
+
+/**
+Returns `record` with `patch` applied. Fields the record type doesn't
+have are rejected rather than ignored, so a typo in a field name isn't
+mistaken for a successful update.
+*/
+pub fn apply<T: Serialize + DeserializeOwned>(record: &T, patch: &Patch) -> Result<T, ApiError> {
+    let original = to_object(record)?;
+    let mut patched = Value::Object(original.clone());
+    match patch {
+        Patch::Merge(merge) => json_patch::merge(&mut patched, merge),
+        Patch::Json(operations) => json_patch::patch(&mut patched, operations)
+            .map_err(|err| ApiError::PatchConflict(err.to_string()))?,
+    }
+
+    match &patched {
+        Value::Object(fields) => {
+            if let Some(unknown) = fields.keys().find(|field| !original.contains_key(*field)) {
+                return Err(ApiError::invalid_field(unknown, format!("{} is not a known field", unknown)));
+            }
+        }
+        _ => {
+            return Err(ApiError::InvalidBody(String::from(
+                "the patched record must still be a JSON object",
+            )))
+        }
+    }
+    Ok(serde_json::from_value(patched)?)
+}
+
+/// The names of the fields whose values differ between two versions of a record.
+pub fn changed_fields<T: Serialize>(before: &T, after: &T) -> Result<Vec<String>, ApiError> {
+    let before = to_object(before)?;
+    let after = to_object(after)?;
+    Ok(after
+        .into_iter()
+        .filter(|(field, value)| before.get(field) != Some(value))
+        .map(|(field, _)| field)
+        .collect())
+}
+
+/**
+Writes just the given columns of the row of `table` with the given id,
+and bumps the row's version. The table and column names go into the
+statement as they are, so they must come from the model, never from
+the client.
+*/
+pub fn update_columns(
+    conn: &Connection,
+    table: &str,
+    id: u32,
+    columns: &[(&'static str, &dyn ToSql)],
+) -> Result<usize, ApiError> {
+    let mut assignments: Vec<String> = columns
+        .iter()
+        .map(|(column, _)| format!("{0} = :{0}", column))
+        .collect();
+    assignments.push(String::from("version = version + 1"));
+    let placeholders: Vec<String> = columns.iter().map(|(column, _)| format!(":{}", column)).collect();
+
+    let mut params: Vec<(&str, &dyn ToSql)> = vec![(":id", &id)];
+    for ((_, value), placeholder) in columns.iter().zip(&placeholders) {
+        params.push((placeholder, *value));
+    }
+    let sql = format!("UPDATE {} SET {} WHERE id = :id;", table, assignments.join(", "));
+    Ok(conn.execute_named(&sql, &params)?)
+}
+
+fn to_object<T: Serialize>(record: &T) -> Result<serde_json::Map<String, Value>, ApiError> {
+    match serde_json::to_value(record) {
+        Ok(Value::Object(fields)) => Ok(fields),
+        _ => Err(ApiError::Internal(String::from("record did not serialize to an object"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Record {
+        title: String,
+        rating: Option<u32>,
+        notes: Option<String>,
+    }
+
+    fn record() -> Record {
+        Record {
+            title: String::from("Dune"),
+            rating: Some(3),
+            notes: Some(String::from("Spice")),
+        }
+    }
+
+    #[test]
+    fn merge_patches_only_touch_the_given_fields() {
+        let patch = Patch::from_body(Some(MERGE_PATCH_TYPE), br#"{"rating": 5, "notes": null}"#).unwrap();
+        let patched = apply(&record(), &patch).unwrap();
+        assert_eq!(patched.title, "Dune");
+        assert_eq!(patched.rating, Some(5));
+        assert_eq!(patched.notes, None);
+        assert_eq!(changed_fields(&record(), &patched).unwrap(), vec!["notes", "rating"]);
+    }
+
+    #[test]
+    fn json_patches_apply_their_operations() {
+        let body = br#"[{"op": "test", "path": "/rating", "value": 3},
+                        {"op": "replace", "path": "/title", "value": "Dune Messiah"}]"#;
+        let patch = Patch::from_body(Some("application/json-patch+json; charset=utf-8"), body).unwrap();
+        let patched = apply(&record(), &patch).unwrap();
+        assert_eq!(patched.title, "Dune Messiah");
+
+        let failing = Patch::from_body(
+            Some(JSON_PATCH_TYPE),
+            br#"[{"op": "test", "path": "/rating", "value": 1}]"#,
+        )
+        .unwrap();
+        assert!(matches!(apply(&record(), &failing), Err(ApiError::PatchConflict(_))));
+    }
+
+    #[test]
+    fn bad_patches_are_rejected() {
+        let unknown = Patch::from_body(None, br#"{"ratng": 5}"#).unwrap();
+        assert!(matches!(apply(&record(), &unknown), Err(ApiError::InvalidField { .. })));
+
+        let wrong_type = Patch::from_body(None, br#"{"title": null}"#).unwrap();
+        assert!(matches!(apply(&record(), &wrong_type), Err(ApiError::InvalidBody(_))));
+
+        assert!(matches!(
+            Patch::from_body(Some("text/plain"), b"{}"),
+            Err(ApiError::UnsupportedMediaType(_))
+        ));
+    }
+}
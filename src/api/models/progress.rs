@@ -0,0 +1,326 @@
+/*!
+
+# progress
+
+How far into its book a reading has got. Progress is logged as
+timestamped entries holding either the page reached or, for books
+without page numbers, the percentage, and each reading carries fields
+worked out from its latest entry:
+
+- `current_page`, the page reached, or the percentage of the book's
+  `pages` if the entry was a percentage
+- `percent_complete`, the share of the book's `pages` reached, or the
+  percentage itself
+- `estimated_finish`, the date the reading should end at the pace kept
+  since `start_date`
+
+A finished reading (one with an `end_date`) is complete whatever its
+log says, and has no estimate. Fields that can't be worked out, like
+the percentage of a book whose page count isn't known, are null.
+
+!*/
+
+use chrono::{DateTime, Duration, NaiveDateTime};
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use super::common;
+use super::reading::query_reading_by_id;
+use crate::api::error::ApiError;
+
+/// Estimates further out than this are left out rather than guessed.
+const MAX_ESTIMATE_DAYS: f64 = 365.0 * 100.0;
+
+#[derive(Serialize, Debug)]
+pub struct ProgressEntry {
+    id: u32,
+    reading: u32,
+    logged_at: String,
+    page: Option<u32>,
+    percent: Option<f64>,
+    note: Option<String>,
+}
+
+/// The body of a request logging progress. `logged_at` defaults to now.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProgressRequest {
+    #[serde(default)]
+    pub logged_at: Option<String>,
+    #[serde(default)]
+    pub page: Option<u32>,
+    #[serde(default)]
+    pub percent: Option<f64>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// The fields a reading derives from its progress log.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Progress {
+    #[serde(default)]
+    current_page: Option<u32>,
+    #[serde(default)]
+    percent_complete: Option<f64>,
+    #[serde(default)]
+    estimated_finish: Option<String>,
+}
+
+const ENTRY_COLUMNS: &str = "id, reading, logged_at, page, percent, note";
+
+impl ProgressEntry {
+    fn from_row(row: &Row) -> Result<ProgressEntry, rusqlite::Error> {
+        Ok(ProgressEntry {
+            id: row.get(0)?,
+            reading: row.get(1)?,
+            logged_at: row.get(2)?,
+            page: row.get(3)?,
+            percent: row.get(4)?,
+            note: row.get(5)?,
+        })
+    }
+}
+
+impl Progress {
+    /**
+    Works out the progress of a reading from its row, which holds the
+    reading's latest entry and its book's page count as the columns
+    `READING_COLUMNS` ends with.
+    */
+    pub fn from_row(row: &Row, start_date: &str, end_date: Option<&str>) -> Result<Progress, rusqlite::Error> {
+        let page: Option<u32> = row.get("latest_page")?;
+        let percent: Option<f64> = row.get("latest_percent")?;
+        let logged_at: Option<String> = row.get("latest_logged_at")?;
+        let pages: Option<u32> = row.get::<_, Option<u32>>("book_pages")?.filter(|pages| *pages > 0);
+        Ok(Progress::derive(start_date, end_date, page, percent, logged_at.as_deref(), pages))
+    }
+
+    fn derive(
+        start_date: &str,
+        end_date: Option<&str>,
+        page: Option<u32>,
+        percent: Option<f64>,
+        logged_at: Option<&str>,
+        pages: Option<u32>,
+    ) -> Progress {
+        if end_date.is_some() {
+            return Progress {
+                current_page: pages,
+                percent_complete: Some(100.0),
+                estimated_finish: None,
+            };
+        }
+
+        let (current_page, percent_complete) = match (page, percent) {
+            (Some(page), _) => (
+                Some(page),
+                pages.map(|pages| (f64::from(page) / f64::from(pages) * 100.0).min(100.0)),
+            ),
+            (None, Some(percent)) => (
+                pages.map(|pages| (percent / 100.0 * f64::from(pages)).round() as u32),
+                Some(percent),
+            ),
+            (None, None) => (None, None),
+        };
+
+        let estimated_finish = match (percent_complete, logged_at) {
+            (Some(percent), Some(logged_at)) => estimate_finish(start_date, logged_at, percent),
+            _ => None,
+        };
+        Progress {
+            current_page,
+            percent_complete: percent_complete.map(|percent| (percent * 10.0).round() / 10.0),
+            estimated_finish,
+        }
+    }
+}
+
+// Extends the pace from the start of the reading to `logged_at` until the book is done
+fn estimate_finish(start_date: &str, logged_at: &str, percent: f64) -> Option<String> {
+    let start = common::parse_date(start_date)?.and_hms_opt(0, 0, 0)?;
+    let logged_at = NaiveDateTime::parse_from_str(logged_at, common::TIMESTAMP_FORMAT).ok()?;
+    if percent >= 100.0 {
+        return Some(logged_at.format(common::DATE_FORMAT).to_string());
+    }
+
+    let elapsed_days = (logged_at - start).num_seconds() as f64 / 86400.0;
+    if percent <= 0.0 || elapsed_days <= 0.0 {
+        return None;
+    }
+    let remaining_days = elapsed_days * (100.0 - percent) / percent;
+    if remaining_days > MAX_ESTIMATE_DAYS {
+        return None;
+    }
+    let finish = logged_at.checked_add_signed(Duration::seconds((remaining_days * 86400.0) as i64))?;
+    Some(finish.format(common::DATE_FORMAT).to_string())
+}
+
+/**
+Reads a timestamp from the client: an RFC 3339 date and time with an
+offset, a date and time taken to be UTC, or a bare date, taken to mean
+its start. Returns it in `common::TIMESTAMP_FORMAT`.
+*/
+pub fn normalize_timestamp(text: &str) -> Option<String> {
+    let text = text.trim();
+    let utc = DateTime::parse_from_rfc3339(text)
+        .map(|time| time.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S"))
+        .ok()
+        .or_else(|| common::parse_date(text)?.and_hms_opt(0, 0, 0))?;
+    Some(utc.format(common::TIMESTAMP_FORMAT).to_string())
+}
+
+/// Returns the reading's progress log, oldest entry first.
+pub fn query_progress(conn: &Connection, reading: u32) -> Result<Vec<ProgressEntry>, ApiError> {
+    query_reading_by_id(conn, reading)?;
+    let sql = format!(
+        "SELECT {} FROM reading_progress WHERE reading = :reading ORDER BY logged_at, id;",
+        ENTRY_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let entries = stmt
+        .query_map_named(&[(":reading", &reading)], ProgressEntry::from_row)?
+        .collect::<Result<Vec<ProgressEntry>, rusqlite::Error>>()?;
+    Ok(entries)
+}
+
+/**
+Adds an entry to the reading's progress log and bumps the reading's
+version, since its derived fields change with it. The entry needs
+either a `page`, which can't be past the end of the book, or a
+`percent` from 0 to 100, and can't be logged before the reading
+started. Returns the stored entry.
+*/
+pub fn log_progress(conn: &Connection, reading: u32, request: ProgressRequest) -> Result<ProgressEntry, ApiError> {
+    let found = query_reading_by_id(conn, reading)?;
+
+    match (request.page, request.percent) {
+        (Some(_), Some(_)) | (None, None) => {
+            return Err(ApiError::invalid_field("page", "progress needs either a page or a percent, not both"))
+        }
+        (None, Some(percent)) if !(0.0..=100.0).contains(&percent) => {
+            return Err(ApiError::invalid_field("percent", "percent must be from 0 to 100"))
+        }
+        _ => {}
+    }
+    if let Some(page) = request.page {
+        let pages: Option<u32> = conn
+            .query_row_named("SELECT pages FROM book WHERE id = :id;", &[(":id", &found.book())], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .flatten();
+        if let Some(pages) = pages.filter(|pages| page > *pages) {
+            return Err(ApiError::invalid_field("page", format!("the book only has {} pages", pages)));
+        }
+    }
+
+    let logged_at = match &request.logged_at {
+        Some(text) => normalize_timestamp(text).ok_or_else(|| {
+            ApiError::invalid_field("logged_at", format!("logged_at is not a valid date or time: {}", text))
+        })?,
+        None => common::now_timestamp(),
+    };
+    if logged_at.as_str() < found.start_date() {
+        return Err(ApiError::invalid_field("logged_at", "progress can't be logged before the reading started"));
+    }
+
+    conn.execute_named(
+        "INSERT INTO reading_progress (reading, logged_at, page, percent, note)
+VALUES (:reading, :logged_at, :page, :percent, :note);",
+        &[
+            (":reading", &reading as &dyn rusqlite::ToSql),
+            (":logged_at", &logged_at),
+            (":page", &request.page),
+            (":percent", &request.percent),
+            (":note", &request.note),
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    bump_version(conn, reading)?;
+
+    let sql = format!("SELECT {} FROM reading_progress WHERE id = :id;", ENTRY_COLUMNS);
+    Ok(conn.query_row_named(&sql, &[(":id", &id)], ProgressEntry::from_row)?)
+}
+
+/// Removes an entry from the reading's progress log, returning the number of rows removed.
+pub fn delete_progress_entry(conn: &Connection, reading: u32, entry: u32) -> Result<usize, ApiError> {
+    query_reading_by_id(conn, reading)?;
+    let removed = conn.execute_named(
+        "DELETE FROM reading_progress WHERE id = :id AND reading = :reading;",
+        &[(":id", &entry), (":reading", &reading)],
+    )?;
+    if removed > 0 {
+        bump_version(conn, reading)?;
+    }
+    Ok(removed)
+}
+
+fn bump_version(conn: &Connection, reading: u32) -> Result<(), rusqlite::Error> {
+    conn.execute_named(
+        "UPDATE reading SET version = version + 1 WHERE id = :id;",
+        &[(":id", &reading)],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::common::test_connection;
+
+    fn log(conn: &Connection, logged_at: &str, page: Option<u32>, percent: Option<f64>) -> Result<ProgressEntry, ApiError> {
+        let request = ProgressRequest {
+            logged_at: Some(logged_at.to_string()),
+            page,
+            percent,
+            note: None,
+        };
+        log_progress(conn, 1, request)
+    }
+
+    fn progress(conn: &Connection) -> serde_json::Value {
+        serde_json::to_value(query_reading_by_id(conn, 1).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn progress_is_derived_from_the_latest_entry() {
+        let conn = test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, pages, medium) VALUES ('Dune', 'Frank Herbert', 400, 'paper');
+             INSERT INTO reading (book, start_date) VALUES (1, '2020-01-01');",
+        )
+        .unwrap();
+        assert_eq!(progress(&conn)["current_page"], serde_json::Value::Null);
+
+        log(&conn, "2020-01-05", Some(50), None).unwrap();
+        log(&conn, "2020-01-11T00:00:00+00:00", Some(100), None).unwrap();
+        let reading = progress(&conn);
+        assert_eq!(reading["current_page"], 100);
+        assert_eq!(reading["percent_complete"], 25.0);
+        // A quarter of the book in ten days leaves thirty more
+        assert_eq!(reading["estimated_finish"], "2020-02-10");
+        assert_eq!(reading["version"], 3);
+
+        log(&conn, "2020-01-21 00:00:00", None, Some(62.5)).unwrap();
+        assert_eq!(progress(&conn)["current_page"], 250);
+
+        assert!(log(&conn, "2020-01-22", Some(401), None).is_err());
+        assert!(log(&conn, "2019-12-31", Some(10), None).is_err());
+        assert!(log(&conn, "2020-01-22", Some(10), Some(10.0)).is_err());
+
+        assert_eq!(delete_progress_entry(&conn, 1, 3).unwrap(), 1);
+        let reading = progress(&conn);
+        assert_eq!((reading["current_page"].clone(), reading["version"].clone()), (100.into(), 5.into()));
+        // The progress of a reading in the trash can't be changed
+        conn.execute_batch("UPDATE reading SET deleted_at = '2020-03-01T00:00:00Z';").unwrap();
+        assert!(matches!(delete_progress_entry(&conn, 1, 2), Err(ApiError::NotFound("reading"))));
+        conn.execute_batch("UPDATE reading SET deleted_at = NULL;").unwrap();
+
+        conn.execute_batch("UPDATE reading SET end_date = '2020-02-01';").unwrap();
+        let reading = progress(&conn);
+        assert_eq!(reading["percent_complete"], 100.0);
+        assert_eq!(reading["estimated_finish"], serde_json::Value::Null);
+    }
+}
@@ -1,66 +1,473 @@
+use rusqlite::{Connection, OptionalExtension, Row};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+use super::book::{Book, BOOK_COLUMNS};
 use super::common;
+use super::common::{Page, PageParams};
+use super::fields::{EntityFields, Field, FieldType};
+use super::filter::{self, Filter};
+use super::history::{self, Action, Entity};
+use super::patch::{self, Patch};
+use super::progress::Progress;
+use super::search::{self, FtsIndex, FtsQuery, SearchHit};
+use crate::api::error::ApiError;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Reading {
     id: Option<u32>,
-    book: u32,
+    // None for readings detached from their book, by deleting the book or
+    // by an explicit null, new readings need one
+    book: Option<u32>,
     start_date: String,
     end_date: Option<String>,
     notes: Option<String>,
+    // Set by the database and bumped on every write, see `models::version`
+    #[serde(default)]
+    version: u32,
+    // Worked out from the progress log, see `models::progress`
+    #[serde(flatten, default)]
+    progress: Progress,
 }
 
-pub fn delete_reading_by_id(id: u32) -> Result<usize, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
-    let mut stmt = conn.prepare("DELETE FROM reading WHERE id = :id;")?;
-    stmt.execute_named(&[(":id", &id)])
-}
+/**
+The columns `Reading::from_row` expects, in order: the reading's own,
+then the latest entry of its progress log and its book's page count,
+which its progress is worked out from.
+*/
+pub const READING_COLUMNS: &str =
+    "reading.id, reading.book, reading.start_date, reading.end_date, reading.notes, reading.version, \
+(SELECT page FROM reading_progress WHERE reading_progress.reading = reading.id
+ ORDER BY reading_progress.logged_at DESC, reading_progress.id DESC LIMIT 1) AS latest_page, \
+(SELECT percent FROM reading_progress WHERE reading_progress.reading = reading.id
+ ORDER BY reading_progress.logged_at DESC, reading_progress.id DESC LIMIT 1) AS latest_percent, \
+(SELECT logged_at FROM reading_progress WHERE reading_progress.reading = reading.id
+ ORDER BY reading_progress.logged_at DESC, reading_progress.id DESC LIMIT 1) AS latest_logged_at, \
+(SELECT pages FROM book WHERE book.id = reading.book) AS book_pages";
+
+/// The full-text index over the notes of readings.
+pub const READING_INDEX: FtsIndex = FtsIndex {
+    table: "reading",
+    fts_table: "reading_fts",
+    record_columns: READING_COLUMNS,
+    fields: &[("notes", 1.0)],
+};
+
+/// The fields of a reading that searches can filter on.
+pub const READING_FIELDS: EntityFields = EntityFields {
+    table: "reading",
+    record_columns: READING_COLUMNS,
+    fields: &[
+        Field { name: "id", column: "reading.id", kind: FieldType::Integer, filterable: true, sortable: false, via: None },
+        Field { name: "book", column: "reading.book", kind: FieldType::Integer, filterable: true, sortable: false, via: None },
+        Field { name: "start_date", column: "reading.start_date", kind: FieldType::Date, filterable: true, sortable: false, via: None },
+        Field { name: "end_date", column: "reading.end_date", kind: FieldType::Date, filterable: true, sortable: false, via: None },
+        Field { name: "notes", column: "reading.notes", kind: FieldType::Text, filterable: true, sortable: false, via: None },
+    ],
+};
+
+impl Reading {
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
 
-pub fn query_reading_by_id(id: u32) -> Result<Reading, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
-    let mut stmt = conn.prepare("SELECT * FROM reading WHERE id = :id;")?;
-    let row = stmt.query_row_named(&[(":id", &id)], |row| {
+    pub fn book(&self) -> Option<u32> {
+        self.book
+    }
+
+    pub fn start_date(&self) -> &str {
+        &self.start_date
+    }
+
+    pub fn from_row(row: &Row) -> Result<Reading, rusqlite::Error> {
+        let start_date: String = row.get(2)?;
+        let end_date: Option<String> = row.get(3)?;
         Ok(Reading {
             id: row.get(0)?,
             book: row.get(1)?,
-            start_date: row.get(2)?,
-            end_date: row.get(3)?,
+            progress: Progress::from_row(row, &start_date, end_date.as_deref())?,
+            start_date,
+            end_date,
             notes: row.get(4)?,
+            version: row.get(5)?,
         })
-    })?;
-    Ok(row)
+    }
+
+    /// The column a field is stored in and the field's value, for
+    /// building updates of just the fields that changed.
+    fn column(&self, field: &str) -> Option<(&'static str, &dyn rusqlite::ToSql)> {
+        match field {
+            "book" => Some(("book", &self.book)),
+            "start_date" => Some(("start_date", &self.start_date)),
+            "end_date" => Some(("end_date", &self.end_date)),
+            "notes" => Some(("notes", &self.notes)),
+            _ => None,
+        }
+    }
+
+    /**
+    Rewrites the start and end dates in the format they are stored in.
+    Fails with the offending field if either date can't be parsed.
+    */
+    pub fn normalize_dates(&mut self) -> Result<(), ApiError> {
+        self.start_date = common::normalize_date(&self.start_date).ok_or_else(|| {
+            ApiError::invalid_field(
+                "start_date",
+                format!("start_date is not a valid date: {}", self.start_date),
+            )
+        })?;
+        if let Some(end_date) = &self.end_date {
+            let normalized = common::normalize_date(end_date).ok_or_else(|| {
+                ApiError::invalid_field("end_date", format!("end_date is not a valid date: {}", end_date))
+            })?;
+            self.end_date = Some(normalized);
+        }
+        Ok(())
+    }
+
+    /// Fails with an error on `book` unless it is the id of a book that exists and isn't in the trash.
+    fn check_book_exists(&self, conn: &Connection) -> Result<(), ApiError> {
+        let book = match self.book {
+            Some(book) => book,
+            None => return Ok(()),
+        };
+        let found: Option<u32> = conn
+            .query_row_named(
+                "SELECT id FROM book WHERE id = :id AND deleted_at IS NULL;",
+                &[(":id", &book)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match found {
+            Some(_) => Ok(()),
+            None => Err(ApiError::invalid_field("book", format!("there is no book with id {}", book))),
+        }
+    }
 }
 
-pub fn query_readings_by_filter(
-    filter_col: String, filter_query: String) -> Result<Vec<Reading>, rusqlite::Error> {
+/**
+A reading with the book it refers to inlined in place of the id, for
+`/reading/all?expand=book`. `book` is only None if the reading was
+detached from its book when the book was deleted.
+*/
+#[derive(Serialize, Debug)]
+pub struct ExpandedReading {
+    id: Option<u32>,
+    book: Option<Book>,
+    start_date: String,
+    end_date: Option<String>,
+    notes: Option<String>,
+    version: u32,
+    #[serde(flatten)]
+    progress: Progress,
+}
 
-    if !common::column_name_is_valid(filter_col.as_ref()) {
-        return Err(rusqlite::Error::InvalidColumnName(filter_col));
+/// The optional conditions `/reading/all` can narrow the listing with.
+#[derive(Debug, Default, Clone)]
+pub struct ReadingFilter {
+    /// Only readings started on or after this date.
+    pub started_after: Option<String>,
+    /// Only readings finished on or before this date.
+    pub finished_before: Option<String>,
+    /// Only readings without an end date when true, or only finished ones when false.
+    pub in_progress: Option<bool>,
+}
+
+/// Moves the reading with the given id to the trash, see `models::trash`.
+pub fn delete_reading_by_id(conn: &Connection, id: u32, actor: Option<&str>) -> Result<usize, ApiError> {
+    let reading = match query_reading_by_id(conn, id) {
+        Ok(reading) => reading,
+        Err(ApiError::NotFound(_)) => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let deleted = conn.execute_named(
+        "UPDATE reading SET deleted_at = :deleted_at WHERE id = :id AND deleted_at IS NULL;",
+        &[(":id", &id as &dyn rusqlite::ToSql), (":deleted_at", &common::now_timestamp())],
+    )?;
+    history::record(conn, Entity::Reading, id, Action::Delete, Some(&reading), None, actor)?;
+    Ok(deleted)
+}
+
+/**
+Applies `patch` to the reading with the given id and returns the
+reading as it is now stored. Patched dates are normalized like those
+of new readings, only the columns whose values changed are written,
+and the id can't be changed. Callers should run this in a transaction.
+*/
+pub fn patch_reading(conn: &Connection, id: u32, patch: &Patch, actor: Option<&str>) -> Result<Reading, ApiError> {
+    let reading = query_reading_by_id(conn, id)?;
+    let patched = patch::apply(&reading, patch)?;
+    if !write_changes(conn, id, &reading, patched)? {
+        return Ok(reading);
     }
 
-    let conn = common::get_database_connection()?;
-    let partial_stmt = format!("SELECT * FROM reading where {} = :filter_query;", filter_col);
+    let patched = query_reading_by_id(conn, id)?;
+    history::record(conn, Entity::Reading, id, Action::Update, Some(&reading), Some(&patched), actor)?;
+    Ok(patched)
+}
+
+/**
+Puts the reading with the given id back the way it was after the given
+revision of its history (see `models::history`) and returns it as it
+is now stored. The reading can't be in the trash, and neither can the
+book it had then. Callers should run this in a transaction.
+*/
+pub fn revert_reading(conn: &Connection, id: u32, revision: u32, actor: Option<&str>) -> Result<Reading, ApiError> {
+    let reading = query_reading_by_id(conn, id)?;
+    let mut reverted: Reading = history::snapshot_at(conn, Entity::Reading, id, revision)?;
+    reverted.id = reading.id;
+    reverted.version = reading.version;
+    reverted.progress = reading.progress.clone();
+    if !write_changes(conn, id, &reading, reverted)? {
+        return Ok(reading);
+    }
+
+    let reverted = query_reading_by_id(conn, id)?;
+    history::record(conn, Entity::Reading, id, Action::Revert, Some(&reading), Some(&reverted), actor)?;
+    Ok(reverted)
+}
+
+// Writes the fields that differ between the stored reading and `changed`, returning whether there were any
+fn write_changes(conn: &Connection, id: u32, reading: &Reading, mut changed: Reading) -> Result<bool, ApiError> {
+    changed.normalize_dates()?;
+    changed.check_book_exists(conn)?;
+    let fields = patch::changed_fields(reading, &changed)?;
+    if fields.is_empty() {
+        return Ok(false);
+    }
+
+    let mut columns = Vec::new();
+    for field in &fields {
+        match changed.column(field) {
+            Some(column) => columns.push(column),
+            None => return Err(ApiError::invalid_field(field, format!("{} can't be changed", field))),
+        }
+    }
+    patch::update_columns(conn, "reading", id, &columns)?;
+    Ok(true)
+}
+
+pub fn query_reading_by_id(conn: &Connection, id: u32) -> Result<Reading, ApiError> {
+    let sql = format!("SELECT {} FROM reading WHERE id = :id AND deleted_at IS NULL;", READING_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_row_named(&[(":id", &id)], Reading::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("reading"))
+}
+
+/**
+Returns every reading of a book whose title contains `title`, ignoring case.
+*/
+pub fn query_readings_by_book_title(
+    conn: &Connection,
+    title: &str,
+) -> Result<Vec<Reading>, ApiError> {
+    query_readings_by_book_containing(conn, "title", title)
+}
+
+/**
+Returns every reading of a book whose author contains `author`,
+ignoring case, so "le guin" finds all readings of books by Le Guin.
+*/
+pub fn query_readings_by_book_author(
+    conn: &Connection,
+    author: &str,
+) -> Result<Vec<Reading>, ApiError> {
+    query_readings_by_book_containing(conn, "author", author)
+}
+
+// `column` is always one of the literals above, never user input
+fn query_readings_by_book_containing(
+    conn: &Connection,
+    column: &str,
+    term: &str,
+) -> Result<Vec<Reading>, ApiError> {
+    let sql = format!(
+        "SELECT {} FROM reading JOIN book ON book.id = reading.book
+WHERE book.{} LIKE :pattern ESCAPE '\\' AND reading.deleted_at IS NULL AND book.deleted_at IS NULL
+ORDER BY reading.start_date, reading.id;",
+        READING_COLUMNS, column
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let pattern = common::contains_pattern(term);
+    let readings = stmt
+        .query_map_named(&[(":pattern", &pattern)], Reading::from_row)?
+        .collect::<Result<Vec<Reading>, rusqlite::Error>>()?;
+    Ok(readings)
+}
+
+/**
+Returns one page of readings matching `filter`, ordered by start date
+(ties broken by id), along with the total number of matching readings.
+The dates in the filter must already be in the stored format.
+*/
+pub fn query_all_readings(
+    conn: &Connection,
+    filter: &ReadingFilter,
+    page: &PageParams,
+) -> Result<Page<Reading>, ApiError> {
+    let mut conditions: Vec<&str> = vec!["deleted_at IS NULL"];
+    let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = Vec::new();
+
+    if let Some(started_after) = &filter.started_after {
+        conditions.push("start_date >= :started_after");
+        params.push((":started_after", started_after));
+    }
+    if let Some(finished_before) = &filter.finished_before {
+        conditions.push("end_date <= :finished_before");
+        params.push((":finished_before", finished_before));
+    }
+    match filter.in_progress {
+        Some(true) => conditions.push("end_date IS NULL"),
+        Some(false) => conditions.push("end_date IS NOT NULL"),
+        None => {}
+    }
+
+    let where_clause = format!("WHERE {}", conditions.join(" AND "));
+
+    let count_sql = format!("SELECT count(*) FROM reading {};", where_clause);
+    let total: u32 = conn.query_row_named(&count_sql, &params, |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT {} FROM reading {} ORDER BY start_date ASC, id ASC LIMIT :limit OFFSET :offset;",
+        READING_COLUMNS, where_clause
+    );
+    params.push((":limit", &page.limit));
+    params.push((":offset", &page.offset));
+    let mut stmt = conn.prepare(&sql)?;
+    let items = stmt
+        .query_map_named(&params, Reading::from_row)?
+        .collect::<Result<Vec<Reading>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/**
+Swaps the book id in each reading of the page for the book itself,
+fetching all of the books the page refers to in a single query.
+*/
+pub fn expand_readings(
+    conn: &Connection,
+    page: Page<Reading>,
+) -> Result<Page<ExpandedReading>, ApiError> {
+    let mut book_ids: Vec<u32> = page.items.iter().filter_map(|reading| reading.book).collect();
+    book_ids.sort_unstable();
+    book_ids.dedup();
+
+    let mut books: HashMap<u32, Book> = HashMap::new();
+    if !book_ids.is_empty() {
+        // The ids are integers, so they can go into the statement directly
+        let id_list: Vec<String> = book_ids.iter().map(|id| id.to_string()).collect();
+        let sql = format!(
+            "SELECT {} FROM book WHERE id IN ({});",
+            BOOK_COLUMNS,
+            id_list.join(", ")
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let id: u32 = row.get(0)?;
+            books.insert(id, Book::from_row(row)?);
+        }
+    }
+
+    let items = page
+        .items
+        .into_iter()
+        .map(|reading| ExpandedReading {
+            id: reading.id,
+            book: reading.book.and_then(|book| books.get(&book).cloned()),
+            start_date: reading.start_date,
+            end_date: reading.end_date,
+            notes: reading.notes,
+            version: reading.version,
+            progress: reading.progress,
+        })
+        .collect();
+
+    Ok(Page {
+        items,
+        total: page.total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/**
+Returns one page of the readings whose notes match a full-text search,
+best matches first. See `models::search` for the query syntax.
+*/
+pub fn search_readings(
+    conn: &Connection,
+    query: &FtsQuery,
+    page: &PageParams,
+) -> Result<Page<SearchHit<Reading>>, ApiError> {
+    search::search(conn, &READING_INDEX, query, page, Reading::from_row)
+}
+
+/**
+Returns one page of the readings matching a filter, ordered by id. See
+`models::filter` for the filter language.
+*/
+pub fn query_readings_matching(
+    conn: &Connection,
+    filter: &Filter,
+    page: &PageParams,
+) -> Result<Page<Reading>, ApiError> {
+    filter::query_filtered(conn, &READING_FIELDS, filter, page, Reading::from_row)
+}
+
+pub fn query_readings_by_filter(
+    conn: &Connection, filter_col: String, filter_query: String) -> Result<Vec<Reading>, ApiError> {
+
+    let field = READING_FIELDS.filterable("filterBy", &filter_col)?;
+    let filter_query = field
+        .bind_text(&filter_query)
+        .map_err(|message| ApiError::invalid_parameter("query", message))?;
+
+    let partial_stmt = format!(
+        "SELECT {} FROM reading where {} AND deleted_at IS NULL;",
+        READING_COLUMNS,
+        field.condition("= :filter_query")
+    );
     let mut stmt = conn.prepare(partial_stmt.as_ref())?;
     let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":filter_query", &filter_query)];
 
     let mut rows = stmt.query_named(params)?;
     let mut readings: Vec<Reading> = Vec::new();
     while let Some(row) = rows.next()? {
-        let reading = Reading {
-            id: row.get(0)?,
-            book: row.get(1)?,
-            start_date: row.get(2)?,
-            end_date: row.get(3)?,
-            notes: row.get(4)?,
-        };
-        readings.push(reading);
+        readings.push(Reading::from_row(row)?);
     };
     Ok(readings)
 }
 
-pub fn write_reading_to_db(reading: Reading) -> Result<usize, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
+/**
+Inserts the reading and returns it as it was stored, including the id
+sqlite assigned to it. Any id already set on the reading is ignored.
+The reading's book has to exist.
+*/
+pub fn create_reading(conn: &Connection, reading: Reading, actor: Option<&str>) -> Result<Reading, ApiError> {
+    if reading.book.is_none() {
+        return Err(ApiError::invalid_field("book", "a new reading needs the id of its book"));
+    }
+    write_reading_to_db(conn, reading)?;
+    let id = conn.last_insert_rowid() as u32;
+    let created = query_reading_by_id(conn, id)?;
+    history::record(conn, Entity::Reading, id, Action::Create, None, Some(&created), actor)?;
+    Ok(created)
+}
+
+pub fn write_reading_to_db(conn: &Connection, reading: Reading) -> Result<usize, ApiError> {
+    reading.check_book_exists(conn)?;
     let mut stmt = conn.prepare(
         "INSERT INTO reading 
 (book, start_date, end_date, notes) VALUES 
@@ -73,18 +480,25 @@ pub fn write_reading_to_db(reading: Reading) -> Result<usize, rusqlite::Error> {
         (":end_date", &reading.end_date),
         (":notes", &reading.notes),
     ];
-    stmt.execute_named(params)
+    Ok(stmt.execute_named(params)?)
 }
 
-pub fn update_reading_in_db(reading: Reading) -> Result<usize, rusqlite::Error> {
-    let conn = common::get_database_connection()?;
+pub fn update_reading_in_db(conn: &Connection, reading: Reading, actor: Option<&str>) -> Result<usize, ApiError> {
+    reading.check_book_exists(conn)?;
+    // Kept for the reading's history, a reading that isn't there isn't updated either
+    let before = match reading.id.map(|id| query_reading_by_id(conn, id)) {
+        Some(Ok(before)) => before,
+        Some(Err(ApiError::NotFound(_))) | None => return Ok(0),
+        Some(Err(err)) => return Err(err),
+    };
     let mut stmt = conn.prepare(
         "UPDATE reading SET 
 book = :book,
 start_date = :start_date,
 end_date = :end_date,
-notes = :notes
-WHERE id = :id;"
+notes = :notes,
+version = version + 1
+WHERE id = :id AND deleted_at IS NULL;"
     )?;
 
     println!("{:#?}", reading);
@@ -95,5 +509,71 @@ WHERE id = :id;"
         (":end_date", &reading.end_date),
         (":notes", &reading.notes),
     ];
-    stmt.execute_named(params)
+    let rows_changed = stmt.execute_named(params)?;
+    if let (1, Some(id)) = (rows_changed, reading.id) {
+        let after = query_reading_by_id(conn, id)?;
+        history::record(conn, Entity::Reading, id, Action::Update, Some(&before), Some(&after), actor)?;
+    }
+    Ok(rows_changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filtering_all_readings_by_date_and_progress() {
+        let conn = common::test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');
+             INSERT INTO reading (book, start_date, end_date) VALUES (1, '2019-12-01', '2020-01-10');
+             INSERT INTO reading (book, start_date, end_date) VALUES (1, '2020-02-01', '2020-03-01');
+             INSERT INTO reading (book, start_date, end_date) VALUES (1, '2020-06-01', NULL);",
+        )
+        .unwrap();
+        let page = PageParams::default();
+
+        let filter = ReadingFilter {
+            started_after: Some("2020-01-01".to_string()),
+            ..ReadingFilter::default()
+        };
+        assert_eq!(query_all_readings(&conn, &filter, &page).unwrap().total, 2);
+
+        let filter = ReadingFilter {
+            finished_before: Some("2020-02-15".to_string()),
+            ..ReadingFilter::default()
+        };
+        assert_eq!(query_all_readings(&conn, &filter, &page).unwrap().total, 1);
+
+        let filter = ReadingFilter {
+            in_progress: Some(true),
+            ..ReadingFilter::default()
+        };
+        let in_progress = query_all_readings(&conn, &filter, &page).unwrap();
+        assert_eq!(in_progress.items[0].start_date, "2020-06-01");
+
+        let expanded = expand_readings(&conn, in_progress).unwrap();
+        assert!(expanded.items[0].book.is_some());
+    }
+
+    #[test]
+    fn readings_are_found_through_their_book() {
+        let conn = common::test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('The Dispossessed', 'Ursula K. Le Guin', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('100% Guin', 'Someone Else', 'paper');
+             INSERT INTO reading (book, start_date) VALUES (1, '2020-01-01');
+             INSERT INTO reading (book, start_date) VALUES (2, '2020-02-01');",
+        )
+        .unwrap();
+
+        let by_author = query_readings_by_book_author(&conn, "le guin").unwrap();
+        assert_eq!(by_author.len(), 1);
+        assert_eq!(by_author[0].book, Some(1));
+
+        // % in the search term is matched literally, not as a wildcard
+        let by_title = query_readings_by_book_title(&conn, "100%").unwrap();
+        assert_eq!(by_title.len(), 1);
+        assert_eq!(by_title[0].book, Some(2));
+    }
 }
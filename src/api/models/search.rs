@@ -0,0 +1,285 @@
+/*!
+
+# search
+
+Full-text search over books and readings, backed by the FTS5 tables
+`book_fts` and `reading_fts` that triggers keep in sync with the
+records (see migration 4). The query is handed to FTS5 as it is, so
+clients get its whole query syntax:
+
+- `dune` matches any field containing the word, `dun*` any word starting with it
+- `"children of dune"` matches the phrase
+- `dune AND NOT messiah`, `herbert OR asimov`, and parentheses
+- `title:dune` or `notes:(ending OR finale)` to search a single field
+
+Results are ranked with bm25, where each field's weight scales how much
+a match in that field counts, and come with a snippet of the best
+matching field with the matched words wrapped in `<mark>` tags. The
+rest of the snippet is HTML-escaped, so it can be put into a page as it
+is even when the record's text holds markup of its own.
+
+!*/
+
+use rusqlite::{Connection, ErrorCode, Row};
+use serde::Serialize;
+
+use super::common::{Page, PageParams};
+use crate::api::error::ApiError;
+
+/// Mark the start and end of each matched word in the snippet FTS5
+/// returns. Characters from the private use area, so they can't be
+/// mistaken for markup when the snippet is escaped.
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+/// What the matched words are wrapped in once the snippet is escaped.
+const HIGHLIGHT_START: &str = "<mark>";
+const HIGHLIGHT_END: &str = "</mark>";
+/// The most words a snippet holds.
+const SNIPPET_WORDS: u32 = 16;
+
+/// A full-text index over one of the record tables.
+pub struct FtsIndex {
+    /// The record table, which is also the index's content table. Its
+    /// rows in the trash, those with a `deleted_at`, are never found.
+    pub table: &'static str,
+    /// The FTS5 table.
+    pub fts_table: &'static str,
+    /// The columns `from_row` expects, in order.
+    pub record_columns: &'static str,
+    /// The indexed fields in the order the FTS5 table declares them,
+    /// with the weight each one gets unless the client asks otherwise.
+    pub fields: &'static [(&'static str, f64)],
+}
+
+/// A parsed `q` and `weights` pair, ready to run against an index.
+#[derive(Debug, Clone)]
+pub struct FtsQuery {
+    text: String,
+    weights: Vec<f64>,
+}
+
+impl FtsQuery {
+    /**
+    Builds a query for `index`. `weights` optionally overrides the
+    default field weights and looks like `title:4,notes:0.5`, with any
+    field left out keeping its default. A weight of 0 leaves the field
+    out of the ranking, though matches in it are still found.
+    */
+    pub fn new(index: &FtsIndex, text: &str, weights: Option<&str>) -> Result<FtsQuery, ApiError> {
+        if text.trim().is_empty() {
+            return Err(ApiError::invalid_parameter("q", "q must not be empty"));
+        }
+
+        let mut field_weights: Vec<f64> = index.fields.iter().map(|(_, weight)| *weight).collect();
+        for pair in weights.unwrap_or_default().split(',').filter(|pair| !pair.is_empty()) {
+            let bad_weight = || {
+                let fields: Vec<&str> = index.fields.iter().map(|(field, _)| *field).collect();
+                ApiError::invalid_parameter(
+                    "weights",
+                    format!(
+                        "weights must look like field:weight with a weight of 0 or more, the fields are: {}",
+                        fields.join(", ")
+                    ),
+                )
+            };
+            let mut parts = pair.splitn(2, ':');
+            let field = parts.next().unwrap_or_default().trim();
+            let weight: f64 = parts
+                .next()
+                .and_then(|weight| weight.trim().parse().ok())
+                .filter(|weight: &f64| weight.is_finite() && *weight >= 0.0)
+                .ok_or_else(bad_weight)?;
+            let position = index
+                .fields
+                .iter()
+                .position(|(name, _)| *name == field)
+                .ok_or_else(bad_weight)?;
+            field_weights[position] = weight;
+        }
+
+        Ok(FtsQuery {
+            text: text.to_string(),
+            weights: field_weights,
+        })
+    }
+}
+
+/// A record found by a full-text search, serialized as the record's
+/// own fields plus `snippet` and `score`.
+#[derive(Serialize, Debug)]
+pub struct SearchHit<T> {
+    #[serde(flatten)]
+    pub record: T,
+    /// The best matching part of the record, with the matches highlighted.
+    pub snippet: String,
+    /// How well the record matches, higher is better.
+    pub score: f64,
+}
+
+/**
+Returns one page of the records in `index` matching `query`, best
+matches first, along with the total number of matches.
+*/
+pub fn search<T>(
+    conn: &Connection,
+    index: &FtsIndex,
+    query: &FtsQuery,
+    page: &PageParams,
+    from_row: fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Result<Page<SearchHit<T>>, ApiError> {
+    run_search(conn, index, query, page, from_row).map_err(|err| match err {
+        // Mistakes in the query only show up when it runs, as a generic
+        // SQLITE_ERROR ("fts5: syntax error near ...", "unterminated string",
+        // "no such column: pages"). The rest of the statement is ours.
+        rusqlite::Error::SqliteFailure(ref failure, Some(ref message))
+            if failure.code == ErrorCode::Unknown =>
+        {
+            ApiError::invalid_parameter("q", format!("q is not a valid search: {}", message))
+        }
+        other => ApiError::from(other),
+    })
+}
+
+fn run_search<T>(
+    conn: &Connection,
+    index: &FtsIndex,
+    query: &FtsQuery,
+    page: &PageParams,
+    from_row: fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Result<Page<SearchHit<T>>, rusqlite::Error> {
+    let count_sql = format!(
+        "SELECT count(*) FROM {fts} JOIN {table} ON {table}.id = {fts}.rowid
+WHERE {fts} MATCH :query AND {table}.deleted_at IS NULL;",
+        fts = index.fts_table,
+        table = index.table,
+    );
+    let total: u32 = conn.query_row_named(&count_sql, &[(":query", &query.text)], |row| row.get(0))?;
+
+    // The weights are numbers we parsed, so they can go into the statement directly
+    let weights: Vec<String> = query.weights.iter().map(|weight| weight.to_string()).collect();
+    let sql = format!(
+        "SELECT {columns},
+    snippet({fts}, -1, '{start}', '{end}', '…', {words}) AS snippet,
+    bm25({fts}, {weights}) AS rank
+FROM {fts} JOIN {table} ON {table}.id = {fts}.rowid
+WHERE {fts} MATCH :query AND {table}.deleted_at IS NULL
+ORDER BY rank, {table}.id
+LIMIT :limit OFFSET :offset;",
+        columns = index.record_columns,
+        fts = index.fts_table,
+        table = index.table,
+        start = MATCH_START,
+        end = MATCH_END,
+        words = SNIPPET_WORDS,
+        weights = weights.join(", "),
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[
+        (":query", &query.text),
+        (":limit", &page.limit),
+        (":offset", &page.offset),
+    ];
+    let items = stmt
+        .query_map_named(params, |row| {
+            let rank: f64 = row.get("rank")?;
+            Ok(SearchHit {
+                record: from_row(row)?,
+                snippet: highlight(&row.get::<_, String>("snippet")?),
+                // bm25 ranks better matches lower
+                score: -rank,
+            })
+        })?
+        .collect::<Result<Vec<SearchHit<T>>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+// The snippet as HTML, escaped, with the matches between the markers wrapped in <mark>
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str(HIGHLIGHT_START),
+            MATCH_END => html.push_str(HIGHLIGHT_END),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::book::{search_books, BOOK_INDEX};
+    use crate::api::models::common::test_connection;
+
+    fn titles(conn: &Connection, q: &str) -> Vec<String> {
+        let query = FtsQuery::new(&BOOK_INDEX, q, None).unwrap();
+        let page = search_books(conn, &query, &PageParams::default()).unwrap();
+        page.items
+            .iter()
+            .map(|hit| serde_json::to_value(hit).unwrap()["title"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn the_index_follows_the_book_table() {
+        let conn = test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');
+             INSERT INTO book (title, author, medium, notes) VALUES ('Dune Messiah', 'Frank Herbert', 'paper', 'Darker than Dune');
+             INSERT INTO book (title, author, medium) VALUES ('Emma', 'Jane Austen', 'ebook');",
+        )
+        .unwrap();
+
+        assert_eq!(titles(&conn, "dune"), vec!["Dune", "Dune Messiah"]);
+        assert_eq!(titles(&conn, "mess*"), vec!["Dune Messiah"]);
+        assert_eq!(titles(&conn, "\"jane austen\" OR darker").len(), 2);
+        assert_eq!(titles(&conn, "notes:dune"), vec!["Dune Messiah"]);
+
+        conn.execute_batch("UPDATE book SET title = 'Persuasion' WHERE id = 3; DELETE FROM book WHERE id = 1;")
+            .unwrap();
+        assert!(titles(&conn, "emma").is_empty());
+        assert_eq!(titles(&conn, "persuasion"), vec!["Persuasion"]);
+        assert_eq!(titles(&conn, "dune"), vec!["Dune Messiah"]);
+    }
+
+    #[test]
+    fn snippets_highlight_and_bad_queries_are_rejected() {
+        let conn = test_connection();
+        conn.execute_batch("INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');")
+            .unwrap();
+        let query = FtsQuery::new(&BOOK_INDEX, "herbert", Some("author:2")).unwrap();
+        let page = search_books(&conn, &query, &PageParams::default()).unwrap();
+        assert_eq!(page.items[0].snippet, "Frank <mark>Herbert</mark>");
+
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium, notes)
+             VALUES ('Emma', 'Jane Austen', 'paper', '<script>alert(1)</script> Austen & co');",
+        )
+        .unwrap();
+        let query = FtsQuery::new(&BOOK_INDEX, "notes:script", None).unwrap();
+        let page = search_books(&conn, &query, &PageParams::default()).unwrap();
+        assert_eq!(
+            page.items[0].snippet,
+            "&lt;<mark>script</mark>&gt;alert(1)&lt;/<mark>script</mark>&gt; Austen &amp; co"
+        );
+
+        let unbalanced = FtsQuery::new(&BOOK_INDEX, "\"dune", None).unwrap();
+        let result = search_books(&conn, &unbalanced, &PageParams::default());
+        assert!(matches!(result, Err(ApiError::InvalidParameter { .. })), "{:?}", result);
+        assert!(FtsQuery::new(&BOOK_INDEX, "dune", Some("pages:2")).is_err());
+        assert!(FtsQuery::new(&BOOK_INDEX, "dune", Some("notes:-1")).is_err());
+        assert!(FtsQuery::new(&BOOK_INDEX, "dune", Some("notes:0")).is_ok());
+    }
+}
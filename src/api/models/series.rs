@@ -0,0 +1,315 @@
+/*!
+
+# series
+
+Series of books and the order to read them in. A book joins a series
+at a position, which can be fractional so that a novella set between
+volumes 2 and 3 can sit at 2.5, or left out for books that belong to a
+series without a place in it. Books without a position come after the
+numbered ones.
+
+A book counts as read once it has any reading, finished or not, so the
+next unread book of a series is the first one in order that nobody has
+started.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension, Row, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+
+use super::book::{query_book_by_id, Book, BOOK_COLUMNS};
+use super::common::{Page, PageParams};
+use crate::api::error::ApiError;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Series {
+    // id is optional because it is missing in series creation
+    id: Option<u32>,
+    name: String,
+    #[serde(default)]
+    notes: Option<String>,
+    // Set by the database and bumped on every write, see `models::version`
+    #[serde(default)]
+    version: u32,
+}
+
+/// The columns `Series::from_row` expects, in order.
+pub const SERIES_COLUMNS: &str = "series.id, series.name, series.notes, series.version";
+
+/// A book in a series, serialized as the book plus its `position`.
+#[derive(Serialize, Debug)]
+pub struct SeriesEntry {
+    #[serde(flatten)]
+    pub book: Book,
+    pub position: Option<f64>,
+}
+
+/// The body of a request putting a book in a series.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Membership {
+    #[serde(default)]
+    position: Option<f64>,
+}
+
+/// The next unread book of a series.
+#[derive(Serialize, Debug)]
+pub struct NextInSeries {
+    pub series: Series,
+    pub next: SeriesEntry,
+}
+
+impl Series {
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn from_row(row: &Row) -> Result<Series, rusqlite::Error> {
+        Ok(Series {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            notes: row.get(2)?,
+            version: row.get(3)?,
+        })
+    }
+
+    fn validated(self) -> Result<Series, ApiError> {
+        if self.name.trim().is_empty() {
+            return Err(ApiError::invalid_field("name", "name must not be empty"));
+        }
+        Ok(self)
+    }
+}
+
+// The entry's columns come after the book's, see `entry_from_row`
+const ORDER_BY_POSITION: &str = "ORDER BY book_series.position NULLS LAST, book.title COLLATE NOCASE, book.id";
+
+fn entry_from_row(row: &Row) -> Result<SeriesEntry, rusqlite::Error> {
+    Ok(SeriesEntry {
+        book: Book::from_row(row)?,
+        position: row.get("position")?,
+    })
+}
+
+/// Inserts the series and returns it as it was stored.
+pub fn create_series(conn: &Connection, series: Series) -> Result<Series, ApiError> {
+    let series = series.validated()?;
+    conn.execute_named(
+        "INSERT INTO series (name, notes) VALUES (:name, :notes);",
+        &[(":name", &series.name), (":notes", &series.notes)],
+    )?;
+    query_series_by_id(conn, conn.last_insert_rowid() as u32)
+}
+
+pub fn query_series_by_id(conn: &Connection, id: u32) -> Result<Series, ApiError> {
+    let sql = format!("SELECT {} FROM series WHERE id = :id;", SERIES_COLUMNS);
+    conn.query_row_named(&sql, &[(":id", &id)], Series::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("series"))
+}
+
+/// Returns one page of series ordered by name, along with the total number of series.
+pub fn query_all_series(conn: &Connection, page: &PageParams) -> Result<Page<Series>, ApiError> {
+    let total: u32 = conn.query_row("SELECT count(*) FROM series;", NO_PARAMS, |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT {} FROM series ORDER BY name COLLATE NOCASE, id LIMIT :limit OFFSET :offset;",
+        SERIES_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":limit", &page.limit), (":offset", &page.offset)];
+    let items = stmt
+        .query_map_named(params, Series::from_row)?
+        .collect::<Result<Vec<Series>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/// Replaces the series with the id given in `series`.
+pub fn update_series_in_db(conn: &Connection, series: Series) -> Result<usize, ApiError> {
+    let series = series.validated()?;
+    Ok(conn.execute_named(
+        "UPDATE series SET name = :name, notes = :notes, version = version + 1 WHERE id = :id;",
+        &[(":id", &series.id), (":name", &series.name), (":notes", &series.notes)],
+    )?)
+}
+
+/// Deletes the series with the given id. Its books stay, they just leave the series.
+pub fn delete_series_by_id(conn: &Connection, id: u32) -> Result<usize, ApiError> {
+    Ok(conn.execute_named("DELETE FROM series WHERE id = :id;", &[(":id", &id)])?)
+}
+
+/// Returns the books of the series in reading order.
+pub fn query_series_books(conn: &Connection, id: u32) -> Result<Vec<SeriesEntry>, ApiError> {
+    query_series_by_id(conn, id)?;
+    let sql = format!(
+        "SELECT {}, book_series.position FROM book_series JOIN book ON book.id = book_series.book
+WHERE book_series.series = :series AND book.deleted_at IS NULL
+{};",
+        BOOK_COLUMNS, ORDER_BY_POSITION
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let entries = stmt
+        .query_map_named(&[(":series", &id)], entry_from_row)?
+        .collect::<Result<Vec<SeriesEntry>, rusqlite::Error>>()?;
+    Ok(entries)
+}
+
+/**
+Puts the book in the series at the given position, or moves it there if
+it's already in the series, and bumps the series' version. Returns the
+book's entry.
+*/
+pub fn put_book_in_series(
+    conn: &Connection,
+    series: u32,
+    book: u32,
+    membership: &Membership,
+) -> Result<SeriesEntry, ApiError> {
+    if let Some(position) = membership.position {
+        if !position.is_finite() || position < 0.0 {
+            return Err(ApiError::invalid_field("position", "position must be a number of at least 0"));
+        }
+    }
+    query_series_by_id(conn, series)?;
+    let book = query_book_by_id(conn, book)?;
+
+    conn.execute_named(
+        "INSERT INTO book_series (book, series, position) VALUES (:book, :series, :position)
+ON CONFLICT (series, book) DO UPDATE SET position = excluded.position;",
+        &[
+            (":book", &book.id() as &dyn rusqlite::ToSql),
+            (":series", &series),
+            (":position", &membership.position),
+        ],
+    )?;
+    bump_version(conn, series)?;
+    Ok(SeriesEntry {
+        book,
+        position: membership.position,
+    })
+}
+
+/// Takes the book out of the series and returns the number of rows removed.
+pub fn remove_book_from_series(conn: &Connection, series: u32, book: u32) -> Result<usize, ApiError> {
+    let removed = conn.execute_named(
+        "DELETE FROM book_series WHERE series = :series AND book = :book;",
+        &[(":series", &series), (":book", &book)],
+    )?;
+    if removed > 0 {
+        bump_version(conn, series)?;
+    }
+    Ok(removed)
+}
+
+fn bump_version(conn: &Connection, series: u32) -> Result<(), rusqlite::Error> {
+    conn.execute_named(
+        "UPDATE series SET version = version + 1 WHERE id = :id;",
+        &[(":id", &series)],
+    )?;
+    Ok(())
+}
+
+/**
+Returns the first book of the series, in reading order, that has no
+reading, or None if every book has been started.
+*/
+pub fn next_unread(conn: &Connection, series: u32) -> Result<Option<SeriesEntry>, ApiError> {
+    query_series_by_id(conn, series)?;
+    let sql = format!(
+        "SELECT {}, book_series.position FROM book_series JOIN book ON book.id = book_series.book
+WHERE book_series.series = :series AND book.deleted_at IS NULL
+AND NOT EXISTS (SELECT 1 FROM reading WHERE reading.book = book.id AND reading.deleted_at IS NULL)
+{}
+LIMIT 1;",
+        BOOK_COLUMNS, ORDER_BY_POSITION
+    );
+    Ok(conn
+        .query_row_named(&sql, &[(":series", &series)], entry_from_row)
+        .optional()?)
+}
+
+/**
+Returns the next unread book of every series that has been started,
+that is, every series with at least one book that has a reading and at
+least one that hasn't. Series are ordered by name.
+*/
+pub fn next_unread_in_started_series(conn: &Connection) -> Result<Vec<NextInSeries>, ApiError> {
+    let sql = format!(
+        "SELECT {} FROM series WHERE EXISTS (
+    SELECT 1 FROM book_series JOIN reading ON reading.book = book_series.book
+    WHERE book_series.series = series.id AND reading.deleted_at IS NULL
+)
+ORDER BY series.name COLLATE NOCASE, series.id;",
+        SERIES_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let started = stmt
+        .query_map(NO_PARAMS, Series::from_row)?
+        .collect::<Result<Vec<Series>, rusqlite::Error>>()?;
+
+    let mut next = Vec::new();
+    for series in started {
+        if let Some(entry) = next_unread(conn, series.id.unwrap_or_default())? {
+            next.push(NextInSeries { series, next: entry });
+        }
+    }
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::common::test_connection;
+
+    fn titles(entries: &[SeriesEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| serde_json::to_value(&entry.book).unwrap()["title"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn series_are_read_in_order() {
+        let conn = test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Dune Messiah', 'Frank Herbert', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Tales of Dune', 'Frank Herbert', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Children of Dune', 'Frank Herbert', 'paper');
+             INSERT INTO series (name) VALUES ('Dune');",
+        )
+        .unwrap();
+        for (book, position) in &[(1, Some(2.0)), (2, Some(1.0)), (3, None), (4, Some(3.0))] {
+            put_book_in_series(&conn, 1, *book, &Membership { position: *position }).unwrap();
+        }
+        put_book_in_series(&conn, 1, 3, &Membership { position: Some(2.5) }).unwrap();
+
+        let books = query_series_books(&conn, 1).unwrap();
+        assert_eq!(titles(&books), vec!["Dune", "Dune Messiah", "Tales of Dune", "Children of Dune"]);
+        assert_eq!(query_series_by_id(&conn, 1).unwrap().version(), 6);
+
+        assert!(next_unread_in_started_series(&conn).unwrap().is_empty());
+        conn.execute_batch(
+            "INSERT INTO reading (book, start_date, end_date) VALUES (2, '2020-01-01', '2020-02-01');
+             INSERT INTO reading (book, start_date) VALUES (1, '2020-03-01');",
+        )
+        .unwrap();
+        let next = next_unread(&conn, 1).unwrap().unwrap();
+        assert_eq!(next.position, Some(2.5));
+        assert_eq!(next_unread_in_started_series(&conn).unwrap().len(), 1);
+
+        remove_book_from_series(&conn, 1, 3).unwrap();
+        conn.execute_batch("DELETE FROM book WHERE id = 4;").unwrap();
+        assert!(next_unread(&conn, 1).unwrap().is_none());
+    }
+}
@@ -0,0 +1,252 @@
+/*!
+
+# shelf
+
+Shelves are named, ordered lists of books, like "to read" or "lent
+out", each with an optional description. A book can sit on any number
+of shelves. Books are added to the end of a shelf, and replacing the
+whole list of a shelf's books is how they get reordered.
+
+Every change to a shelf's books bumps the shelf's version, so clients
+can use If-Match to avoid overwriting each other's reordering.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension, Row, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+
+use super::book::{Book, BOOK_COLUMNS};
+use super::common::{Page, PageParams};
+use crate::api::error::ApiError;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Shelf {
+    // id is optional because it is missing in shelf creation
+    id: Option<u32>,
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    // Set by the database and bumped on every write, see `models::version`
+    #[serde(default)]
+    version: u32,
+}
+
+/// The columns `Shelf::from_row` expects, in order.
+pub const SHELF_COLUMNS: &str = "shelf.id, shelf.name, shelf.description, shelf.version";
+
+/// The body of a request adding books to a shelf and taking others off it.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ShelfChanges {
+    #[serde(default)]
+    pub add: Vec<u32>,
+    #[serde(default)]
+    pub remove: Vec<u32>,
+}
+
+impl Shelf {
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn from_row(row: &Row) -> Result<Shelf, rusqlite::Error> {
+        Ok(Shelf {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            version: row.get(3)?,
+        })
+    }
+
+    fn validated(self) -> Result<Shelf, ApiError> {
+        if self.name.trim().is_empty() {
+            return Err(ApiError::invalid_field("name", "name must not be empty"));
+        }
+        Ok(self)
+    }
+}
+
+/// Inserts the shelf and returns it as it was stored.
+pub fn create_shelf(conn: &Connection, shelf: Shelf) -> Result<Shelf, ApiError> {
+    let shelf = shelf.validated()?;
+    conn.execute_named(
+        "INSERT INTO shelf (name, description) VALUES (:name, :description);",
+        &[(":name", &shelf.name), (":description", &shelf.description)],
+    )?;
+    query_shelf_by_id(conn, conn.last_insert_rowid() as u32)
+}
+
+pub fn query_shelf_by_id(conn: &Connection, id: u32) -> Result<Shelf, ApiError> {
+    let sql = format!("SELECT {} FROM shelf WHERE id = :id;", SHELF_COLUMNS);
+    conn.query_row_named(&sql, &[(":id", &id)], Shelf::from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("shelf"))
+}
+
+/// Returns one page of shelves ordered by name, along with the total number of shelves.
+pub fn query_all_shelves(conn: &Connection, page: &PageParams) -> Result<Page<Shelf>, ApiError> {
+    let total: u32 = conn.query_row("SELECT count(*) FROM shelf;", NO_PARAMS, |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT {} FROM shelf ORDER BY name, id LIMIT :limit OFFSET :offset;",
+        SHELF_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":limit", &page.limit), (":offset", &page.offset)];
+    let items = stmt
+        .query_map_named(params, Shelf::from_row)?
+        .collect::<Result<Vec<Shelf>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/// Replaces the shelf with the id given in `shelf`, leaving its books as they are.
+pub fn update_shelf_in_db(conn: &Connection, shelf: Shelf) -> Result<usize, ApiError> {
+    let shelf = shelf.validated()?;
+    Ok(conn.execute_named(
+        "UPDATE shelf SET name = :name, description = :description, version = version + 1 WHERE id = :id;",
+        &[(":id", &shelf.id), (":name", &shelf.name), (":description", &shelf.description)],
+    )?)
+}
+
+/// Deletes the shelf with the given id. Its books stay, they just leave the shelf.
+pub fn delete_shelf_by_id(conn: &Connection, id: u32) -> Result<usize, ApiError> {
+    Ok(conn.execute_named("DELETE FROM shelf WHERE id = :id;", &[(":id", &id)])?)
+}
+
+/// Returns the books on the shelf in order.
+pub fn query_shelf_books(conn: &Connection, id: u32) -> Result<Vec<Book>, ApiError> {
+    query_shelf_by_id(conn, id)?;
+    let sql = format!(
+        "SELECT {} FROM shelf_book JOIN book ON book.id = shelf_book.book
+WHERE shelf_book.shelf = :shelf AND book.deleted_at IS NULL
+ORDER BY shelf_book.position, book.id;",
+        BOOK_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let books = stmt
+        .query_map_named(&[(":shelf", &id)], Book::from_row)?
+        .collect::<Result<Vec<Book>, rusqlite::Error>>()?;
+    Ok(books)
+}
+
+/**
+Takes the books in `changes.remove` off the shelf, then adds the books
+in `changes.add` to its end in the order given. Books already on the
+shelf keep their place. Every book to add has to exist, otherwise
+nothing changes and the error names the missing one. Returns the books
+on the shelf afterwards.
+*/
+pub fn change_shelf_books(conn: &Connection, id: u32, changes: &ShelfChanges) -> Result<Vec<Book>, ApiError> {
+    query_shelf_by_id(conn, id)?;
+    check_books_exist(conn, "add", &changes.add)?;
+
+    let mut changed = 0;
+    for book in &changes.remove {
+        changed += conn.execute_named(
+            "DELETE FROM shelf_book WHERE shelf = :shelf AND book = :book;",
+            &[(":shelf", &id), (":book", book)],
+        )?;
+    }
+    for book in &changes.add {
+        changed += conn.execute_named(
+            "INSERT INTO shelf_book (shelf, book, position)
+SELECT :shelf, :book, coalesce(max(position) + 1, 0) FROM shelf_book WHERE shelf = :shelf
+ON CONFLICT (shelf, book) DO NOTHING;",
+            &[(":shelf", &id), (":book", book)],
+        )?;
+    }
+    if changed > 0 {
+        bump_version(conn, id)?;
+    }
+    query_shelf_books(conn, id)
+}
+
+/**
+Makes the shelf hold exactly the given books in the given order, which
+is also how books on a shelf are reordered. A book listed twice keeps
+its first place. Returns the books on the shelf afterwards.
+*/
+pub fn replace_shelf_books(conn: &Connection, id: u32, books: &[u32]) -> Result<Vec<Book>, ApiError> {
+    query_shelf_by_id(conn, id)?;
+    check_books_exist(conn, "", books)?;
+
+    conn.execute_named("DELETE FROM shelf_book WHERE shelf = :shelf;", &[(":shelf", &id)])?;
+    let mut insert = conn.prepare(
+        "INSERT INTO shelf_book (shelf, book, position) VALUES (:shelf, :book, :position)
+ON CONFLICT (shelf, book) DO NOTHING;",
+    )?;
+    for (position, book) in books.iter().enumerate() {
+        insert.execute_named(&[
+            (":shelf", &id as &dyn rusqlite::ToSql),
+            (":book", book),
+            (":position", &(position as u32)),
+        ])?;
+    }
+    bump_version(conn, id)?;
+    query_shelf_books(conn, id)
+}
+
+// Fails with an error naming the first id in `books` that isn't a book, as `field[i]`
+fn check_books_exist(conn: &Connection, field: &str, books: &[u32]) -> Result<(), ApiError> {
+    let mut stmt = conn.prepare("SELECT id FROM book WHERE id = :id AND deleted_at IS NULL;")?;
+    for (i, book) in books.iter().enumerate() {
+        let found: Option<u32> = stmt.query_row_named(&[(":id", book)], |row| row.get(0)).optional()?;
+        if found.is_none() {
+            return Err(ApiError::invalid_field(
+                &format!("{}[{}]", field, i),
+                format!("there is no book with id {}", book),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn bump_version(conn: &Connection, shelf: u32) -> Result<(), rusqlite::Error> {
+    conn.execute_named(
+        "UPDATE shelf SET version = version + 1 WHERE id = :id;",
+        &[(":id", &shelf)],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::common::test_connection;
+
+    fn ids(books: &[Book]) -> Vec<u32> {
+        books.iter().map(|book| book.id().unwrap()).collect()
+    }
+
+    #[test]
+    fn books_are_added_and_removed_in_bulk() {
+        let conn = test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Emma', 'Jane Austen', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Persuasion', 'Jane Austen', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Mansfield Park', 'Jane Austen', 'paper');
+             INSERT INTO shelf (name) VALUES ('To read');",
+        )
+        .unwrap();
+
+        let add = |add: Vec<u32>, remove: Vec<u32>| ShelfChanges { add, remove };
+        assert_eq!(ids(&change_shelf_books(&conn, 1, &add(vec![3, 1], vec![])).unwrap()), vec![3, 1]);
+        assert_eq!(ids(&change_shelf_books(&conn, 1, &add(vec![2, 3], vec![1])).unwrap()), vec![3, 2]);
+        assert!(change_shelf_books(&conn, 1, &add(vec![2, 9], vec![])).is_err());
+        assert_eq!(query_shelf_by_id(&conn, 1).unwrap().version(), 3);
+
+        assert_eq!(ids(&replace_shelf_books(&conn, 1, &[1, 2, 1, 3]).unwrap()), vec![1, 2, 3]);
+        conn.execute_batch("DELETE FROM book WHERE id = 2;").unwrap();
+        assert_eq!(ids(&query_shelf_books(&conn, 1).unwrap()), vec![1, 3]);
+    }
+}
@@ -0,0 +1,172 @@
+/*!
+
+# tag
+
+Tags are free-form labels on books, any number per book, taking over
+from the single `genre` text. Tag names ignore case, so "Fantasy" and
+"fantasy" are one tag, spelled the way it was first added. Tags exist
+as long as something uses them: setting a book's tags adds the names
+that are new, and deleting a tag takes it off every book.
+
+A book's tags count as part of the book for its version: every change
+to them bumps it, so clients can use If-Match to avoid overwriting each
+other's tag edits.
+
+!*/
+
+use rusqlite::{Connection, Row, NO_PARAMS};
+use serde::Serialize;
+
+use super::book::query_book_by_id;
+use super::common::{Page, PageParams};
+use crate::api::error::ApiError;
+
+/// A tag with the number of books carrying it.
+#[derive(Serialize, Debug)]
+pub struct Tag {
+    id: u32,
+    name: String,
+    books: u32,
+}
+
+impl Tag {
+    fn from_row(row: &Row) -> Result<Tag, rusqlite::Error> {
+        Ok(Tag {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            books: row.get(2)?,
+        })
+    }
+}
+
+/// Returns one page of tags ordered by name, along with the total number of tags.
+pub fn query_all_tags(conn: &Connection, page: &PageParams) -> Result<Page<Tag>, ApiError> {
+    let total: u32 = conn.query_row("SELECT count(*) FROM tag;", NO_PARAMS, |row| row.get(0))?;
+
+    let mut stmt = conn.prepare(
+        "SELECT tag.id, tag.name, (
+    SELECT count(*) FROM book_tag JOIN book ON book.id = book_tag.book
+    WHERE book_tag.tag = tag.id AND book.deleted_at IS NULL
+)
+FROM tag ORDER BY tag.name, tag.id LIMIT :limit OFFSET :offset;",
+    )?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] = &[(":limit", &page.limit), (":offset", &page.offset)];
+    let items = stmt
+        .query_map_named(params, Tag::from_row)?
+        .collect::<Result<Vec<Tag>, rusqlite::Error>>()?;
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/// Deletes the tag with the given id, taking it off every book.
+pub fn delete_tag_by_id(conn: &Connection, id: u32) -> Result<usize, ApiError> {
+    conn.execute_named(
+        "UPDATE book SET version = version + 1 WHERE id IN (SELECT book FROM book_tag WHERE tag = :id);",
+        &[(":id", &id)],
+    )?;
+    Ok(conn.execute_named("DELETE FROM tag WHERE id = :id;", &[(":id", &id)])?)
+}
+
+/// Returns the names of the book's tags in alphabetical order.
+pub fn query_book_tags(conn: &Connection, book: u32) -> Result<Vec<String>, ApiError> {
+    query_book_by_id(conn, book)?;
+    Ok(tags_of(conn, book)?)
+}
+
+/**
+Replaces the book's tags with the given names, adding the tags that
+don't exist yet, and returns the book's tags as `query_book_tags` does.
+Callers should check the book's version first, since it is bumped if
+the tags change.
+*/
+pub fn set_book_tags(conn: &Connection, book: u32, names: Vec<String>) -> Result<Vec<String>, ApiError> {
+    query_book_by_id(conn, book)?;
+    let mut cleaned = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let name = clean_name(name);
+        if name.is_empty() {
+            return Err(ApiError::invalid_field(&format!("[{}]", i), "tag names must not be empty"));
+        }
+        cleaned.push(name);
+    }
+
+    let before = tags_of(conn, book)?;
+    conn.execute_named("DELETE FROM book_tag WHERE book = :book;", &[(":book", &book)])?;
+    add_tags(conn, book, &cleaned)?;
+    let after = tags_of(conn, book)?;
+    if after != before {
+        conn.execute_named("UPDATE book SET version = version + 1 WHERE id = :id;", &[(":id", &book)])?;
+    }
+    Ok(after)
+}
+
+/// Tags the book with each of the names, adding the tags that don't exist yet.
+fn add_tags(conn: &Connection, book: u32, names: &[String]) -> Result<(), rusqlite::Error> {
+    let mut insert_tag = conn.prepare("INSERT INTO tag (name) VALUES (:name) ON CONFLICT (name) DO NOTHING;")?;
+    let mut link = conn.prepare(
+        "INSERT INTO book_tag (book, tag) SELECT :book, id FROM tag WHERE name = :name
+ON CONFLICT (book, tag) DO NOTHING;",
+    )?;
+    for name in names {
+        insert_tag.execute_named(&[(":name", name)])?;
+        link.execute_named(&[(":book", &book as &dyn rusqlite::ToSql), (":name", name)])?;
+    }
+    Ok(())
+}
+
+fn tags_of(conn: &Connection, book: u32) -> Result<Vec<String>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT tag.name FROM book_tag JOIN tag ON tag.id = book_tag.tag
+WHERE book_tag.book = :book ORDER BY tag.name;",
+    )?;
+    let names = stmt
+        .query_map_named(&[(":book", &book)], |row| row.get(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    Ok(names)
+}
+
+// Trims the name and collapses runs of whitespace inside it
+fn clean_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::book;
+    use crate::api::models::common::test_connection;
+    use crate::api::models::filter::Filter;
+
+    #[test]
+    fn genres_become_tags() {
+        let conn = test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('The Hobbit', 'J.R.R. Tolkien', 'paper');",
+        )
+        .unwrap();
+        let tags = set_book_tags(&conn, 1, vec!["fantasy".into(), " Fantasy ".into(), "to  read".into()]).unwrap();
+        assert_eq!(tags, vec!["fantasy", "to read"]);
+        assert!(set_book_tags(&conn, 1, vec!["  ".into()]).is_err());
+        // Only a change to the tags changes the book's version
+        let version = || query_book_by_id(&conn, 1).unwrap().version();
+        assert_eq!(version(), 2);
+        set_book_tags(&conn, 1, vec!["to read".into(), "FANTASY".into()]).unwrap();
+        assert_eq!(version(), 2);
+
+        let matching = |text: &str| {
+            let filter = Filter::parse(text).unwrap();
+            book::query_books_matching(&conn, &filter, &PageParams::default()).unwrap().total
+        };
+        assert_eq!(matching("tag = FANTASY AND NOT tag = read"), 1);
+        assert_eq!(matching("tag IS NULL OR shelf = 'to read'"), 0);
+
+        delete_tag_by_id(&conn, 1).unwrap();
+        assert_eq!(query_book_tags(&conn, 1).unwrap(), vec!["to read"]);
+        assert_eq!(version(), 3);
+    }
+}
@@ -0,0 +1,274 @@
+/*!
+
+# trash
+
+Deleting a book or a reading doesn't erase it, it moves the record to
+the trash by setting its `deleted_at` to the time of the deletion.
+Every other query leaves records in the trash out, so to the rest of
+the API they are gone, but they can be listed and restored until they
+are purged.
+
+A book deleted along with its readings (`on_readings=cascade`) sends
+them to the trash too, marked with `deleted_with_book`, and restoring
+the book brings them back with it. A reading can't be restored while its book is still in
+the trash.
+
+Records older than the retention (`trash_retention_days`, see
+`crate::config`) are purged for good by a job the server runs in the
+background, and purging a book purges the readings still pointing at
+it.
+
+!*/
+
+use chrono::{Duration, Utc};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+use super::book::{check_isbn_is_free, query_book_by_id, Book, BOOK_COLUMNS};
+use super::common::{self, Page, PageParams};
+use super::history::{self, Action, Entity};
+use super::reading::{query_reading_by_id, Reading, READING_COLUMNS};
+use crate::api::error::ApiError;
+
+/// The kinds of records that go to the trash when deleted.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    Book,
+    Reading,
+}
+
+impl TrashKind {
+    pub fn from_param(param: &str) -> Option<TrashKind> {
+        match param {
+            "book" => Some(TrashKind::Book),
+            "reading" => Some(TrashKind::Reading),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            TrashKind::Book => "book",
+            TrashKind::Reading => "reading",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum TrashedRecord {
+    Book(Book),
+    Reading(Reading),
+}
+
+/// A record in the trash, with when it was deleted.
+#[derive(Serialize, Debug)]
+pub struct TrashItem {
+    #[serde(rename = "type")]
+    kind: TrashKind,
+    deleted_at: String,
+    record: TrashedRecord,
+}
+
+/// How many records a purge erased.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Purged {
+    pub books: usize,
+    pub readings: usize,
+}
+
+/**
+Returns one page of the trash, most recently deleted first, along with
+the number of records in it. `kind` narrows it down to books or readings.
+*/
+pub fn query_trash(conn: &Connection, kind: Option<TrashKind>, page: &PageParams) -> Result<Page<TrashItem>, ApiError> {
+    let kind = kind.map(TrashKind::as_str);
+    let trash = "(
+    SELECT 'book' AS kind, id, deleted_at FROM book WHERE deleted_at IS NOT NULL
+    UNION ALL
+    SELECT 'reading' AS kind, id, deleted_at FROM reading WHERE deleted_at IS NOT NULL
+) WHERE :kind IS NULL OR kind = :kind";
+
+    let count_sql = format!("SELECT count(*) FROM {};", trash);
+    let total: u32 = conn.query_row_named(&count_sql, &[(":kind", &kind)], |row| row.get(0))?;
+
+    let sql = format!(
+        "SELECT kind, id FROM {} ORDER BY deleted_at DESC, kind, id LIMIT :limit OFFSET :offset;",
+        trash
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let params: &[(&str, &dyn rusqlite::ToSql)] =
+        &[(":kind", &kind), (":limit", &page.limit), (":offset", &page.offset)];
+    let entries = stmt
+        .query_map_named(params, |row| Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?)))?
+        .collect::<Result<Vec<(String, u32)>, rusqlite::Error>>()?;
+
+    let mut items = Vec::new();
+    for (kind, id) in entries {
+        let item = if kind == "book" {
+            let (book, deleted_at) = trashed_book(conn, id)?;
+            TrashItem {
+                kind: TrashKind::Book,
+                deleted_at,
+                record: TrashedRecord::Book(book),
+            }
+        } else {
+            let (reading, deleted_at) = trashed_reading(conn, id)?;
+            TrashItem {
+                kind: TrashKind::Reading,
+                deleted_at,
+                record: TrashedRecord::Reading(reading),
+            }
+        };
+        items.push(item);
+    }
+
+    Ok(Page {
+        items,
+        total,
+        limit: page.limit,
+        offset: page.offset,
+    })
+}
+
+/**
+Takes the book out of the trash, along with the readings that were
+deleted with it, and returns it. A book whose ISBN has been given to
+another book since is a `Conflict`.
+*/
+pub fn restore_book(conn: &Connection, id: u32, actor: Option<&str>) -> Result<Book, ApiError> {
+    let (book, _) = trashed_book(conn, id)?;
+    check_isbn_is_free(conn, &book)?;
+
+    let mut stmt = conn.prepare("SELECT id FROM reading WHERE book = :id AND deleted_with_book = 1 ORDER BY id;")?;
+    let readings = stmt
+        .query_map_named(&[(":id", &id)], |row| row.get(0))?
+        .collect::<Result<Vec<u32>, rusqlite::Error>>()?;
+    conn.execute_named(
+        "UPDATE reading SET deleted_at = NULL, deleted_with_book = 0
+WHERE book = :id AND deleted_with_book = 1;",
+        &[(":id", &id)],
+    )?;
+    conn.execute_named("UPDATE book SET deleted_at = NULL WHERE id = :id;", &[(":id", &id)])?;
+
+    let restored = query_book_by_id(conn, id)?;
+    history::record(conn, Entity::Book, id, Action::Restore, None, Some(&restored), actor)?;
+    for reading in readings {
+        let restored = query_reading_by_id(conn, reading)?;
+        history::record(conn, Entity::Reading, reading, Action::Restore, None, Some(&restored), actor)?;
+    }
+    Ok(restored)
+}
+
+/**
+Takes the reading out of the trash and returns it. A reading whose book
+is in the trash is a `Conflict`, the book has to be restored first.
+*/
+pub fn restore_reading(conn: &Connection, id: u32, actor: Option<&str>) -> Result<Reading, ApiError> {
+    let (reading, _) = trashed_reading(conn, id)?;
+    if let Some(book) = reading.book() {
+        if query_book_by_id(conn, book).is_err() {
+            return Err(ApiError::Conflict(format!(
+                "the reading's book {} is in the trash, restore the book first",
+                book
+            )));
+        }
+    }
+
+    conn.execute_named(
+        "UPDATE reading SET deleted_at = NULL, deleted_with_book = 0 WHERE id = :id;",
+        &[(":id", &id)],
+    )?;
+    let restored = query_reading_by_id(conn, id)?;
+    history::record(conn, Entity::Reading, id, Action::Restore, None, Some(&restored), actor)?;
+    Ok(restored)
+}
+
+/// Takes the record of the given kind out of the trash, see `restore_book` and `restore_reading`.
+pub fn restore(conn: &Connection, kind: TrashKind, id: u32, actor: Option<&str>) -> Result<TrashedRecord, ApiError> {
+    match kind {
+        TrashKind::Book => restore_book(conn, id, actor).map(TrashedRecord::Book),
+        TrashKind::Reading => restore_reading(conn, id, actor).map(TrashedRecord::Reading),
+    }
+}
+
+/**
+Erases the records that have been in the trash for longer than
+`retention`, along with any readings of the books erased.
+*/
+pub fn purge_trash(conn: &Connection, retention: Duration) -> Result<Purged, ApiError> {
+    let cutoff = (Utc::now() - retention).format(common::TIMESTAMP_FORMAT).to_string();
+    let readings = conn.execute_named(
+        "DELETE FROM reading WHERE deleted_at IS NOT NULL AND (
+    deleted_at < :cutoff
+    OR book IN (SELECT id FROM book WHERE deleted_at < :cutoff)
+);",
+        &[(":cutoff", &cutoff)],
+    )?;
+    let books = conn.execute_named("DELETE FROM book WHERE deleted_at < :cutoff;", &[(":cutoff", &cutoff)])?;
+    Ok(Purged { books, readings })
+}
+
+// Returns the book with the given id and when it was deleted, if it is in the trash
+fn trashed_book(conn: &Connection, id: u32) -> Result<(Book, String), ApiError> {
+    let sql = format!(
+        "SELECT {}, book.deleted_at FROM book WHERE id = :id AND deleted_at IS NOT NULL;",
+        BOOK_COLUMNS
+    );
+    conn.query_row_named(&sql, &[(":id", &id)], |row| Ok((Book::from_row(row)?, row.get("deleted_at")?)))
+        .optional()?
+        .ok_or(ApiError::NotFound("book"))
+}
+
+// Returns the reading with the given id and when it was deleted, if it is in the trash
+fn trashed_reading(conn: &Connection, id: u32) -> Result<(Reading, String), ApiError> {
+    let sql = format!(
+        "SELECT {}, reading.deleted_at FROM reading WHERE id = :id AND deleted_at IS NOT NULL;",
+        READING_COLUMNS
+    );
+    conn.query_row_named(&sql, &[(":id", &id)], |row| {
+        Ok((Reading::from_row(row)?, row.get("deleted_at")?))
+    })
+    .optional()?
+    .ok_or(ApiError::NotFound("reading"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::models::book::{delete_book_by_id, OnReadings};
+    use crate::api::models::reading::delete_reading_by_id;
+
+    #[test]
+    fn deleted_records_can_be_restored_until_purged() {
+        let conn = common::test_connection();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium) VALUES ('Emma', 'Jane Austen', 'paper');
+             INSERT INTO reading (book, start_date) VALUES (1, '2020-01-01');
+             INSERT INTO reading (book, start_date) VALUES (1, '2021-01-01');",
+        )
+        .unwrap();
+        let trash = |kind| query_trash(&conn, kind, &PageParams::default()).unwrap().total;
+
+        delete_reading_by_id(&conn, 2, None).unwrap();
+        delete_book_by_id(&conn, 1, OnReadings::Cascade, None).unwrap();
+        assert!(query_book_by_id(&conn, 1).is_err());
+        assert_eq!(trash(None), 3);
+        assert_eq!(trash(Some(TrashKind::Reading)), 2);
+        assert!(matches!(restore_reading(&conn, 1, None), Err(ApiError::Conflict(_))));
+
+        // Only the reading deleted along with the book comes back with it
+        restore_book(&conn, 1, None).unwrap();
+        assert!(query_reading_by_id(&conn, 1).is_ok());
+        assert_eq!(trash(None), 1);
+        restore_reading(&conn, 2, None).unwrap();
+        assert!(matches!(restore_reading(&conn, 2, None), Err(ApiError::NotFound("reading"))));
+
+        delete_book_by_id(&conn, 1, OnReadings::Cascade, None).unwrap();
+        assert_eq!(purge_trash(&conn, Duration::days(30)).unwrap(), Purged::default());
+        conn.execute_batch("UPDATE book SET deleted_at = '2000-01-01T00:00:00Z';").unwrap();
+        assert_eq!(purge_trash(&conn, Duration::days(30)).unwrap(), Purged { books: 1, readings: 2 });
+        assert_eq!(trash(None), 0);
+    }
+}
@@ -0,0 +1,150 @@
+/*!
+
+# version
+
+Optimistic concurrency for books and readings. Every record has a
+`version` that each write bumps by one, and the version is what clients
+see as the record's ETag (`"3"`). A client that sends the ETag it last
+saw back in `If-Match` only has its write applied if nobody else has
+written the record since, instead of silently overwriting their change.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::api::error::ApiError;
+
+/// What an `If-Match` header asks of the record being written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Precondition {
+    /// No `If-Match` header, the write always goes ahead.
+    Unconditional,
+    /// `If-Match: *`, the record only has to exist.
+    Exists,
+    /// The record's current ETag has to be one of these versions.
+    OneOf(Vec<u32>),
+}
+
+impl Precondition {
+    /**
+    Reads an `If-Match` header. Tags that aren't versions of ours, and
+    weak tags, which `If-Match` never matches, are dropped, so a header
+    holding only those can't be satisfied.
+    */
+    pub fn from_if_match(header: Option<&str>) -> Precondition {
+        match header.map(str::trim) {
+            None => Precondition::Unconditional,
+            Some("*") => Precondition::Exists,
+            Some(tags) => Precondition::OneOf(
+                tags.split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.starts_with("W/"))
+                    .filter_map(parse_etag)
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn allows(&self, version: u32) -> bool {
+        match self {
+            Precondition::Unconditional | Precondition::Exists => true,
+            Precondition::OneOf(versions) => versions.contains(&version),
+        }
+    }
+}
+
+/// The ETag header value for a version of a record.
+pub fn etag(version: u32) -> String {
+    format!("\"{}\"", version)
+}
+
+fn parse_etag(tag: &str) -> Option<u32> {
+    tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+/**
+Returns true if an `If-None-Match` header matches the given version, in
+which case a GET can answer 304 Not Modified. Unlike `If-Match`, weak
+tags are compared too.
+*/
+pub fn none_match_hits(header: Option<&str>, version: u32) -> bool {
+    match header.map(str::trim) {
+        None => false,
+        Some("*") => true,
+        Some(tags) => tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .filter_map(parse_etag)
+            .any(|tag| tag == version),
+    }
+}
+
+/**
+Checks the precondition against the current version of the row of
+`table` with the given id. A row that doesn't exist fails every
+precondition but `Unconditional`. Run this in the same transaction as
+the write it guards, so the version can't change in between.
+*/
+pub fn check(conn: &Connection, table: &str, id: u32, precondition: &Precondition) -> Result<(), ApiError> {
+    if *precondition == Precondition::Unconditional {
+        return Ok(());
+    }
+
+    // `table` always comes from the model, never from the client
+    let sql = format!("SELECT version FROM {} WHERE id = :id;", table);
+    let current: Option<u32> = conn
+        .query_row_named(&sql, &[(":id", &id)], |row| row.get(0))
+        .optional()?;
+    match current {
+        Some(version) if precondition.allows(version) => Ok(()),
+        Some(version) => Err(ApiError::PreconditionFailed(format!(
+            "the {} has been changed since, its current ETag is {}",
+            table,
+            etag(version)
+        ))),
+        None => Err(ApiError::PreconditionFailed(format!(
+            "there is no {} with id {}",
+            table, id
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_match_headers_are_parsed() {
+        assert_eq!(Precondition::from_if_match(None), Precondition::Unconditional);
+        assert_eq!(Precondition::from_if_match(Some("*")), Precondition::Exists);
+        assert_eq!(
+            Precondition::from_if_match(Some(r#""3", W/"4", "abc", "5""#)),
+            Precondition::OneOf(vec![3, 5])
+        );
+        assert!(!Precondition::from_if_match(Some("bogus")).allows(1));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert!(none_match_hits(Some(r#"W/"2""#), 2));
+        assert!(none_match_hits(Some(r#""1", "2""#), 2));
+        assert!(none_match_hits(Some("*"), 7));
+        assert!(!none_match_hits(Some(r#""1""#), 2));
+        assert!(!none_match_hits(None, 2));
+    }
+
+    #[test]
+    fn writes_bump_the_version_checked_against() {
+        let conn = crate::api::models::common::test_connection();
+        conn.execute_batch("INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');")
+            .unwrap();
+        let seen = Precondition::from_if_match(Some(r#""1""#));
+        assert!(check(&conn, "book", 1, &seen).is_ok());
+
+        let patch = crate::api::models::patch::Patch::from_body(None, br#"{"rating": 4}"#).unwrap();
+        let book = crate::api::models::book::patch_book(&conn, 1, &patch, None).unwrap();
+        assert_eq!(book.version(), 2);
+        assert!(matches!(check(&conn, "book", 1, &seen), Err(ApiError::PreconditionFailed(_))));
+        assert!(matches!(check(&conn, "book", 2, &Precondition::Exists), Err(ApiError::PreconditionFailed(_))));
+    }
+}
@@ -0,0 +1,125 @@
+/*!
+
+# state
+
+`AppState` is everything a request handler needs besides the request
+itself. It is built once in `main` and then cloned into every route
+through `crate::routes::filters::with_state`, so cloning it has to stay
+cheap (the pool is reference counted internally).
+
+rusqlite is a blocking library, so database work must never run
+directly on the async executor's threads, where a slow query would
+stall every other request scheduled on that thread. `AppState::run`
+moves the work onto tokio's blocking thread pool instead, and is the
+only way controllers should touch the database. Whatever goes wrong,
+in the work itself or in getting it to run, comes back as an `ApiError`.
+
+!*/
+
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::api::error::ApiError;
+use crate::config::Config;
+
+pub type DbPool = r2d2::Pool<SqliteConnectionManager>;
+
+/// How long a connection waits on a locked database before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a request waits for a free connection from the pool.
+const POOL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: DbPool,
+    pub body_limits: BodyLimits,
+}
+
+/// The largest request bodies the routes accept, in bytes. Set from the
+/// `*_body_limit_kb` settings, see `crate::config`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyLimits {
+    /// For routes that write one record.
+    pub record: u64,
+    /// For routes that write a note, which can be much longer.
+    pub note: u64,
+    /// For /batch, which writes many records at once.
+    pub batch: u64,
+}
+
+impl BodyLimits {
+    pub fn from_config(config: &Config) -> BodyLimits {
+        let bytes = |kb: u32| u64::from(kb) * 1024;
+        BodyLimits {
+            record: bytes(config.record_body_limit_kb),
+            note: bytes(config.note_body_limit_kb),
+            batch: bytes(config.batch_body_limit_kb),
+        }
+    }
+}
+
+impl AppState {
+    pub fn new(config: &Config) -> Result<AppState, r2d2::Error> {
+        let manager = SqliteConnectionManager::file(&config.database_path)
+            .with_init(configure_connection);
+        let pool = r2d2::Pool::builder()
+            .max_size(config.pool_size)
+            .connection_timeout(POOL_TIMEOUT)
+            .build(manager)?;
+        Ok(AppState {
+            pool,
+            body_limits: BodyLimits::from_config(config),
+        })
+    }
+
+    /**
+    Runs `work` with a connection from the pool on tokio's blocking thread
+    pool and waits for it without blocking the executor. Waiting for a free
+    connection happens on the blocking thread too, since `Pool::get` blocks.
+
+    No connection becoming free before the pool timeout is an
+    `ApiError::Unavailable`, and work that panicked is an `ApiError::Internal`.
+    */
+    pub async fn run<F, T>(&self, work: F) -> Result<T, ApiError>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, ApiError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let mut conn = pool
+                .get()
+                .map_err(|err| ApiError::Unavailable(err.to_string()))?;
+            work(&mut conn)
+        });
+        match task.await {
+            Ok(result) => result,
+            Err(join_err) => Err(ApiError::Internal(format!("database task failed: {}", join_err))),
+        }
+    }
+}
+
+/**
+Opens a single connection outside of the pool, configured the same way
+as the pooled ones. Used on startup to check the database and run
+migrations before any requests are accepted.
+*/
+pub fn open_connection(path: &Path) -> Result<Connection, rusqlite::Error> {
+    let mut conn = Connection::open(path)?;
+    configure_connection(&mut conn)?;
+    Ok(conn)
+}
+
+/**
+Applies the pragmas every connection should have. WAL lets readers
+keep going while a write is in progress, and the busy timeout makes a
+writer wait for the lock instead of failing immediately with
+`SQLITE_BUSY` when another connection is writing. sqlite only enforces
+foreign keys on connections that ask for it, so every one of ours does.
+*/
+fn configure_connection(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+    conn.busy_timeout(BUSY_TIMEOUT)
+}
@@ -0,0 +1,549 @@
+/*!
+
+# config
+
+This module figures out where alexandria-db keeps its database and
+which address the server listens on. Every setting can come from four
+places, and a later source always wins over an earlier one:
+
+1. The built in defaults (the old hardcoded values). The database
+   path has none: it has to be given one of the other ways, or the
+   server refuses to start.
+2. A TOML config file, given with `--config <path>` or the
+   `ALEXANDRIA_CONFIG` environment variable.
+3. Environment variables (`ALEXANDRIA_DATABASE_PATH`,
+   `ALEXANDRIA_BIND_ADDRESS`, `ALEXANDRIA_PORT`,
+   `ALEXANDRIA_AUTO_MIGRATE`, `ALEXANDRIA_POOL_SIZE`,
+   `ALEXANDRIA_TRASH_RETENTION_DAYS`, `ALEXANDRIA_RECORD_BODY_LIMIT_KB`,
+   `ALEXANDRIA_NOTE_BODY_LIMIT_KB`, `ALEXANDRIA_BATCH_BODY_LIMIT_KB`).
+4. Command line flags (`--database`, `--bind`, `--port`,
+   `--no-auto-migrate`, `--pool-size`, `--trash-retention-days`,
+   `--record-body-limit-kb`, `--note-body-limit-kb`,
+   `--batch-body-limit-kb`).
+
+A config file looks like this:
+
+```toml
+database_path = "/var/lib/alexandria/library.db"
+bind_address = "0.0.0.0"
+port = 8080
+auto_migrate = true
+pool_size = 8
+trash_retention_days = 30
+record_body_limit_kb = 64
+note_body_limit_kb = 512
+batch_body_limit_kb = 1024
+```
+
+The body limits cap the size of request bodies in kilobytes: one for
+the routes that write a single record, one for notes (see
+`crate::api::models::note`) and one for `/batch`. A larger body gets a
+413 error.
+
+There is deliberately no default database, since a relative default
+would be looked up from wherever the server happens to be started (`/`
+under systemd) and quietly create an empty database there.
+
+A relative `database_path` inside a config file is resolved against
+the directory the config file lives in, not the working directory, so
+the server behaves the same no matter where it is started from.
+
+Besides settings, the command line also picks what to do. With no
+subcommand the server starts, and `migrate` inspects or upgrades the
+database schema instead (see `crate::migrations`).
+
+!*/
+
+use serde::Deserialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_BIND_ADDRESS: [u8; 4] = [127, 0, 0, 1];
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_POOL_SIZE: u32 = 8;
+const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+const DEFAULT_RECORD_BODY_LIMIT_KB: u32 = 64;
+const DEFAULT_NOTE_BODY_LIMIT_KB: u32 = 512;
+const DEFAULT_BATCH_BODY_LIMIT_KB: u32 = 1024;
+
+pub const USAGE: &str = "Usage: alexandria-db [OPTIONS]
+       alexandria-db migrate [OPTIONS] (--status | --up | --to <version>)
+
+Options:
+    --config <path>      Read settings from a TOML config file
+    --database <path>    Path to the sqlite database file (required)
+    --bind <address>     Address to listen on
+    --port <port>        Port to listen on
+    --no-auto-migrate    Don't upgrade the database schema on startup
+    --pool-size <n>      Number of database connections to keep open
+    --trash-retention-days <n>
+                         Days deleted records stay in the trash, 0 keeps them
+    --record-body-limit-kb <n>
+                         Largest body accepted when writing a record
+    --note-body-limit-kb <n>
+                         Largest body accepted when writing a note
+    --batch-body-limit-kb <n>
+                         Largest body accepted by /batch
+    --help               Print this message
+
+Migrate options:
+    --status             List migrations and whether they have been applied
+    --up                 Apply every pending migration
+    --to <version>       Apply pending migrations up to and including <version>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub database_path: PathBuf,
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Whether the server applies pending migrations when it starts.
+    pub auto_migrate: bool,
+    /// How many sqlite connections the server keeps in its pool.
+    pub pool_size: u32,
+    /// How many days deleted records stay in the trash before they are
+    /// purged for good. 0 keeps them until they are restored.
+    pub trash_retention_days: u32,
+    /// The largest request body, in kilobytes, accepted by routes that write one record.
+    pub record_body_limit_kb: u32,
+    /// The largest request body, in kilobytes, accepted by routes that write a note.
+    pub note_body_limit_kb: u32,
+    /// The largest request body, in kilobytes, accepted by /batch.
+    pub batch_body_limit_kb: u32,
+}
+
+/// What the process was asked to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve,
+    Migrate(MigrateAction),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrateAction {
+    Status,
+    Up,
+    To(u32),
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read from disk.
+    Io(PathBuf, std::io::Error),
+    /// The config file was read but is not valid TOML for `FileConfig`.
+    Parse(PathBuf, toml::de::Error),
+    /// A setting was present but its value could not be understood.
+    InvalidValue { source: String, value: String },
+    /// A command line argument was not recognized or was missing its value.
+    InvalidArgument(String),
+    /// No database path was set in the config file, the environment or the flags.
+    MissingDatabasePath,
+    /// `--help` was passed, the caller should print `USAGE` and exit.
+    HelpRequested,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => {
+                write!(f, "could not read config file {}: {}", path.display(), err)
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "could not parse config file {}: {}", path.display(), err)
+            }
+            ConfigError::InvalidValue { source, value } => {
+                write!(f, "invalid value {:?} for {}", value, source)
+            }
+            ConfigError::InvalidArgument(arg) => write!(f, "{}\n\n{}", arg, USAGE),
+            ConfigError::MissingDatabasePath => write!(
+                f,
+                "no database given, set database_path in the config file, \
+                 the ALEXANDRIA_DATABASE_PATH environment variable or the --database flag"
+            ),
+            ConfigError::HelpRequested => write!(f, "{}", USAGE),
+        }
+    }
+}
+
+/// The settings that may appear in a config file. Every key is optional
+/// so a file only has to mention what it wants to change.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    database_path: Option<PathBuf>,
+    bind_address: Option<IpAddr>,
+    port: Option<u16>,
+    auto_migrate: Option<bool>,
+    pool_size: Option<u32>,
+    trash_retention_days: Option<u32>,
+    record_body_limit_kb: Option<u32>,
+    note_body_limit_kb: Option<u32>,
+    batch_body_limit_kb: Option<u32>,
+}
+
+/// The settings that were given as command line flags.
+#[derive(Debug, Default)]
+struct CliArgs {
+    config: Option<PathBuf>,
+    database_path: Option<PathBuf>,
+    bind_address: Option<String>,
+    port: Option<String>,
+    pool_size: Option<String>,
+    trash_retention_days: Option<String>,
+    record_body_limit_kb: Option<String>,
+    note_body_limit_kb: Option<String>,
+    batch_body_limit_kb: Option<String>,
+    no_auto_migrate: bool,
+    migrate: bool,
+    migrate_action: Option<MigrateAction>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            // There is no default database, `Config::load` insists on one being set
+            database_path: PathBuf::new(),
+            bind_address: IpAddr::from(DEFAULT_BIND_ADDRESS),
+            port: DEFAULT_PORT,
+            auto_migrate: true,
+            pool_size: DEFAULT_POOL_SIZE,
+            trash_retention_days: DEFAULT_TRASH_RETENTION_DAYS,
+            record_body_limit_kb: DEFAULT_RECORD_BODY_LIMIT_KB,
+            note_body_limit_kb: DEFAULT_NOTE_BODY_LIMIT_KB,
+            batch_body_limit_kb: DEFAULT_BATCH_BODY_LIMIT_KB,
+        }
+    }
+}
+
+impl Config {
+    /**
+    Builds the configuration for this process from the real command line
+    and environment, along with the command that was asked for. See the
+    module documentation for the precedence rules.
+    */
+    pub fn from_env() -> Result<(Config, Command), ConfigError> {
+        Config::load(std::env::args().skip(1), |key| std::env::var(key).ok())
+    }
+
+    /**
+    Builds a configuration from the given arguments (without the program
+    name) and an environment lookup function. Split out from `from_env`
+    so the precedence rules can be tested without touching the real
+    process environment.
+    */
+    fn load<I, E>(args: I, env: E) -> Result<(Config, Command), ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let cli = parse_args(args)?;
+        let mut config = Config::default();
+
+        let config_file = cli
+            .config
+            .clone()
+            .or_else(|| env("ALEXANDRIA_CONFIG").map(PathBuf::from));
+        if let Some(path) = config_file {
+            config.apply_file(&path)?;
+        }
+
+        if let Some(path) = env("ALEXANDRIA_DATABASE_PATH") {
+            config.database_path = PathBuf::from(path);
+        }
+        if let Some(addr) = env("ALEXANDRIA_BIND_ADDRESS") {
+            config.bind_address = parse_value("ALEXANDRIA_BIND_ADDRESS", addr)?;
+        }
+        if let Some(port) = env("ALEXANDRIA_PORT") {
+            config.port = parse_value("ALEXANDRIA_PORT", port)?;
+        }
+        if let Some(auto_migrate) = env("ALEXANDRIA_AUTO_MIGRATE") {
+            config.auto_migrate = parse_value("ALEXANDRIA_AUTO_MIGRATE", auto_migrate)?;
+        }
+        if let Some(pool_size) = env("ALEXANDRIA_POOL_SIZE") {
+            config.pool_size = parse_positive("ALEXANDRIA_POOL_SIZE", pool_size)?;
+        }
+        if let Some(days) = env("ALEXANDRIA_TRASH_RETENTION_DAYS") {
+            config.trash_retention_days = parse_value("ALEXANDRIA_TRASH_RETENTION_DAYS", days)?;
+        }
+        if let Some(limit) = env("ALEXANDRIA_RECORD_BODY_LIMIT_KB") {
+            config.record_body_limit_kb = parse_positive("ALEXANDRIA_RECORD_BODY_LIMIT_KB", limit)?;
+        }
+        if let Some(limit) = env("ALEXANDRIA_NOTE_BODY_LIMIT_KB") {
+            config.note_body_limit_kb = parse_positive("ALEXANDRIA_NOTE_BODY_LIMIT_KB", limit)?;
+        }
+        if let Some(limit) = env("ALEXANDRIA_BATCH_BODY_LIMIT_KB") {
+            config.batch_body_limit_kb = parse_positive("ALEXANDRIA_BATCH_BODY_LIMIT_KB", limit)?;
+        }
+
+        if let Some(path) = cli.database_path {
+            config.database_path = path;
+        }
+        if let Some(addr) = cli.bind_address {
+            config.bind_address = parse_value("--bind", addr)?;
+        }
+        if let Some(port) = cli.port {
+            config.port = parse_value("--port", port)?;
+        }
+        if let Some(pool_size) = cli.pool_size {
+            config.pool_size = parse_positive("--pool-size", pool_size)?;
+        }
+        if let Some(days) = cli.trash_retention_days {
+            config.trash_retention_days = parse_value("--trash-retention-days", days)?;
+        }
+        if let Some(limit) = cli.record_body_limit_kb {
+            config.record_body_limit_kb = parse_positive("--record-body-limit-kb", limit)?;
+        }
+        if let Some(limit) = cli.note_body_limit_kb {
+            config.note_body_limit_kb = parse_positive("--note-body-limit-kb", limit)?;
+        }
+        if let Some(limit) = cli.batch_body_limit_kb {
+            config.batch_body_limit_kb = parse_positive("--batch-body-limit-kb", limit)?;
+        }
+        if cli.no_auto_migrate {
+            config.auto_migrate = false;
+        }
+
+        let command = match (cli.migrate, cli.migrate_action) {
+            (false, None) => Command::Serve,
+            (true, Some(action)) => Command::Migrate(action),
+            (true, None) => {
+                return Err(ConfigError::InvalidArgument(
+                    "migrate needs one of --status, --up or --to <version>".to_string(),
+                ))
+            }
+            (false, Some(_)) => {
+                return Err(ConfigError::InvalidArgument(
+                    "--status, --up and --to can only be used with migrate".to_string(),
+                ))
+            }
+        };
+
+        if config.database_path.as_os_str().is_empty() {
+            return Err(ConfigError::MissingDatabasePath);
+        }
+
+        Ok((config, command))
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        let file: FileConfig =
+            toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+
+        if let Some(db_path) = file.database_path {
+            self.database_path = match path.parent() {
+                Some(dir) if db_path.is_relative() => dir.join(db_path),
+                _ => db_path,
+            };
+        }
+        if let Some(addr) = file.bind_address {
+            self.bind_address = addr;
+        }
+        if let Some(port) = file.port {
+            self.port = port;
+        }
+        if let Some(auto_migrate) = file.auto_migrate {
+            self.auto_migrate = auto_migrate;
+        }
+        if let Some(pool_size) = file.pool_size {
+            self.pool_size = parse_positive("pool_size", pool_size.to_string())?;
+        }
+        if let Some(days) = file.trash_retention_days {
+            self.trash_retention_days = days;
+        }
+        if let Some(limit) = file.record_body_limit_kb {
+            self.record_body_limit_kb = parse_positive("record_body_limit_kb", limit.to_string())?;
+        }
+        if let Some(limit) = file.note_body_limit_kb {
+            self.note_body_limit_kb = parse_positive("note_body_limit_kb", limit.to_string())?;
+        }
+        if let Some(limit) = file.batch_body_limit_kb {
+            self.batch_body_limit_kb = parse_positive("batch_body_limit_kb", limit.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(source: &str, value: String) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        source: source.to_string(),
+        value,
+    })
+}
+
+/// For settings that can't be 0: a pool needs at least one connection
+/// to be of any use, and a body limit of 0 would turn away every body.
+fn parse_positive(source: &str, value: String) -> Result<u32, ConfigError> {
+    match parse_value(source, value.clone())? {
+        0 => Err(ConfigError::InvalidValue {
+            source: source.to_string(),
+            value,
+        }),
+        size => Ok(size),
+    }
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<CliArgs, ConfigError> {
+    let mut cli = CliArgs::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Accept both `--port 8080` and `--port=8080`
+        let (flag, inline_value) = match arg.find('=') {
+            Some(idx) if arg.starts_with("--") => {
+                (arg[..idx].to_string(), Some(arg[idx + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        // Arguments that don't take a value
+        match flag.as_str() {
+            "--help" | "-h" => return Err(ConfigError::HelpRequested),
+            "migrate" if !cli.migrate => {
+                cli.migrate = true;
+                continue;
+            }
+            "--no-auto-migrate" => {
+                cli.no_auto_migrate = true;
+                continue;
+            }
+            "--status" => {
+                set_migrate_action(&mut cli, MigrateAction::Status)?;
+                continue;
+            }
+            "--up" => {
+                set_migrate_action(&mut cli, MigrateAction::Up)?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => {
+                return Err(ConfigError::InvalidArgument(format!(
+                    "Missing value for {}",
+                    flag
+                )))
+            }
+        };
+
+        match flag.as_str() {
+            "--config" => cli.config = Some(PathBuf::from(value)),
+            "--database" => cli.database_path = Some(PathBuf::from(value)),
+            "--bind" => cli.bind_address = Some(value),
+            "--port" => cli.port = Some(value),
+            "--pool-size" => cli.pool_size = Some(value),
+            "--trash-retention-days" => cli.trash_retention_days = Some(value),
+            "--record-body-limit-kb" => cli.record_body_limit_kb = Some(value),
+            "--note-body-limit-kb" => cli.note_body_limit_kb = Some(value),
+            "--batch-body-limit-kb" => cli.batch_body_limit_kb = Some(value),
+            "--to" => {
+                let version = parse_value("--to", value)?;
+                set_migrate_action(&mut cli, MigrateAction::To(version))?;
+            }
+            _ => {
+                return Err(ConfigError::InvalidArgument(format!(
+                    "Unrecognized argument: {}",
+                    arg
+                )))
+            }
+        }
+    }
+
+    Ok(cli)
+}
+
+fn set_migrate_action(cli: &mut CliArgs, action: MigrateAction) -> Result<(), ConfigError> {
+    if cli.migrate_action.is_some() {
+        return Err(ConfigError::InvalidArgument(
+            "Only one of --status, --up or --to can be given".to_string(),
+        ));
+    }
+    cli.migrate_action = Some(action);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_are_used_when_nothing_is_set() {
+        let (config, command) = Config::load(args(&["--database", "/srv/library.db"]), |_| None).unwrap();
+        let expected = Config {
+            database_path: PathBuf::from("/srv/library.db"),
+            ..Config::default()
+        };
+        assert_eq!(config, expected);
+        assert_eq!(command, Command::Serve);
+
+        // Except for the database, which has to be given
+        let no_database = Config::load(Vec::new(), |_| None);
+        assert!(matches!(no_database, Err(ConfigError::MissingDatabasePath)));
+    }
+
+    #[test]
+    fn flags_override_environment_and_file() {
+        let dir = std::env::temp_dir().join("alexandria-config-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("alexandria.toml");
+        std::fs::write(
+            &file,
+            "database_path = \"library.db\"\nbind_address = \"0.0.0.0\"\nport = 9000\n",
+        )
+        .unwrap();
+
+        let mut env = HashMap::new();
+        env.insert("ALEXANDRIA_CONFIG", file.to_str().unwrap().to_string());
+        env.insert("ALEXANDRIA_PORT", "9001".to_string());
+        let lookup = |key: &str| env.get(key).cloned();
+
+        // The file sets everything, the environment overrides the port
+        let (config, _) = Config::load(Vec::new(), lookup).unwrap();
+        assert_eq!(config.database_path, dir.join("library.db"));
+        assert_eq!(config.bind_address, "0.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(config.port, 9001);
+
+        // And flags override the environment
+        let (config, _) =
+            Config::load(args(&["--port=9002", "--database", "/tmp/x.db"]), lookup).unwrap();
+        assert_eq!(config.port, 9002);
+        assert_eq!(config.database_path, PathBuf::from("/tmp/x.db"));
+    }
+
+    #[test]
+    fn bad_values_are_reported() {
+        let bad_port = Config::load(args(&["--port", "eighty"]), |_| None);
+        assert!(matches!(bad_port, Err(ConfigError::InvalidValue { .. })));
+
+        let no_body = Config::load(args(&["--note-body-limit-kb=0"]), |_| None);
+        assert!(matches!(no_body, Err(ConfigError::InvalidValue { .. })));
+
+        let unknown = Config::load(args(&["--colour", "blue"]), |_| None);
+        assert!(matches!(unknown, Err(ConfigError::InvalidArgument(_))));
+
+        let missing_file = Config::load(args(&["--config", "/does/not/exist.toml"]), |_| None);
+        assert!(matches!(missing_file, Err(ConfigError::Io(..))));
+    }
+
+    #[test]
+    fn migrate_subcommand_is_parsed() {
+        let (config, command) =
+            Config::load(args(&["migrate", "--to", "3", "--database", "a.db"]), |_| None).unwrap();
+        assert_eq!(command, Command::Migrate(MigrateAction::To(3)));
+        assert_eq!(config.database_path, PathBuf::from("a.db"));
+
+        let no_action = Config::load(args(&["migrate"]), |_| None);
+        assert!(matches!(no_action, Err(ConfigError::InvalidArgument(_))));
+
+        let two_actions = Config::load(args(&["migrate", "--up", "--status"]), |_| None);
+        assert!(matches!(two_actions, Err(ConfigError::InvalidArgument(_))));
+    }
+}
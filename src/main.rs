@@ -1,10 +1,160 @@
 mod routes;
 mod api;
+mod config;
+mod migrations;
+
+use api::models::trash;
+use api::state::{self, AppState};
+use config::{Command, Config, ConfigError, MigrateAction};
+use std::time::Duration;
+
+/// How often the trash is checked for records past their retention.
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() {
-    let master_route = routes::master_route::generate_master_route();
+    let (config, command) = match Config::from_env() {
+        Ok(parsed) => parsed,
+        Err(ConfigError::HelpRequested) => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("alexandria-db: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let mut conn = match state::open_connection(&config.database_path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            eprintln!(
+                "alexandria-db: could not open database {}: {}",
+                config.database_path.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if let Command::Migrate(action) = command {
+        if let Err(err) = run_migrate_command(&mut conn, action) {
+            eprintln!("alexandria-db: migration failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(err) = prepare_schema(&mut conn, config.auto_migrate) {
+        eprintln!(
+            "alexandria-db: could not prepare database {}: {}",
+            config.database_path.display(),
+            err
+        );
+        std::process::exit(1);
+    }
+    drop(conn);
+
+    let app_state = match AppState::new(&config) {
+        Ok(app_state) => app_state,
+        Err(err) => {
+            eprintln!("alexandria-db: could not create connection pool: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if config.trash_retention_days > 0 {
+        tokio::spawn(purge_trash_periodically(app_state.clone(), config.trash_retention_days));
+    }
+
+    let master_route = routes::master_route::generate_master_route(app_state);
     
-    warp::serve(master_route).run(([127,0,0,1], 8080)).await;
+    warp::serve(master_route).run((config.bind_address, config.port)).await;
 }
 
+/**
+Makes sure the schema is at the version this build expects before the
+server starts taking requests. With auto migration turned off, an out
+of date schema is an error instead, so that upgrades can be rolled out
+by hand with `alexandria-db migrate`.
+*/
+fn prepare_schema(
+    conn: &mut rusqlite::Connection,
+    auto_migrate: bool,
+) -> Result<(), migrations::MigrationError> {
+    if auto_migrate {
+        for version in migrations::migrate_up(conn)? {
+            println!("Applied migration {}", version);
+        }
+        return Ok(());
+    }
+
+    let current = migrations::current_version(conn)?;
+    let latest = migrations::latest_version();
+    if current < latest {
+        return Err(migrations::MigrationError::Pending { current, latest });
+    } else if current > latest {
+        return Err(migrations::MigrationError::TooNew { current, latest });
+    }
+    Ok(())
+}
+
+/**
+Purges the records that have been in the trash for longer than
+`retention_days`, once on startup and then every
+`TRASH_PURGE_INTERVAL`. A purge that fails is reported and retried on
+the next round.
+*/
+async fn purge_trash_periodically(state: AppState, retention_days: u32) {
+    let retention = chrono::Duration::days(retention_days.into());
+    let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let purged = state
+            .run(move |conn| {
+                let tx = conn.transaction()?;
+                let purged = trash::purge_trash(&tx, retention)?;
+                tx.commit()?;
+                Ok(purged)
+            })
+            .await;
+        match purged {
+            Ok(purged) if purged.books > 0 || purged.readings > 0 => println!(
+                "Purged {} book(s) and {} reading(s) from the trash",
+                purged.books, purged.readings
+            ),
+            Ok(_) => {}
+            Err(err) => eprintln!("alexandria-db: could not purge the trash: {}", err),
+        }
+    }
+}
+
+fn run_migrate_command(
+    conn: &mut rusqlite::Connection,
+    action: MigrateAction,
+) -> Result<(), migrations::MigrationError> {
+    let applied = match action {
+        MigrateAction::Status => {
+            println!(
+                "Schema version {} (latest is {})",
+                migrations::current_version(conn)?,
+                migrations::latest_version()
+            );
+            for (migration, is_applied) in migrations::status(conn)? {
+                let mark = if is_applied { "x" } else { " " };
+                println!("  [{}] {:04} {}", mark, migration.version, migration.description);
+            }
+            return Ok(());
+        }
+        MigrateAction::Up => migrations::migrate_up(conn)?,
+        MigrateAction::To(version) => migrations::migrate_to(conn, version)?,
+    };
+
+    if applied.is_empty() {
+        println!("Nothing to do, the database is already up to date");
+    }
+    for version in applied {
+        println!("Applied migration {}", version);
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+-- The original schema. Databases created before migrations existed
+-- already have these tables, so this only creates what is missing.
+CREATE TABLE IF NOT EXISTS "book" (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`title`	TEXT NOT NULL,
+	`author`	TEXT NOT NULL,
+	`pages`	INTEGER,
+	`genre`	TEXT,
+	`medium`	TEXT NOT NULL CHECK(medium IN ('paper', 'ebook', 'audio')),
+	`rating`	INTEGER CHECK(rating IN (1,2,3,4,5)),
+	`notes`	TEXT
+);
+
+CREATE TABLE IF NOT EXISTS "reading" (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`book`	INTEGER,
+	`start_date`	TEXT NOT NULL,
+	`end_date`	TEXT,
+	`notes`	TEXT
+);
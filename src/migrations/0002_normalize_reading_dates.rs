@@ -0,0 +1,41 @@
+use chrono::NaiveDate;
+use rusqlite::{Transaction, NO_PARAMS};
+
+/**
+Readings were originally written with month/day/year dates, which
+can't be compared or range filtered as text. This rewrites every date
+that parses that way as YYYY-MM-DD. Anything that doesn't parse is
+left alone rather than guessed at.
+*/
+pub fn up(tx: &Transaction) -> Result<(), rusqlite::Error> {
+    let mut select = tx.prepare("SELECT id, start_date, end_date FROM reading;")?;
+    let rows = select
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut update = tx.prepare(
+        "UPDATE reading SET start_date = :start_date, end_date = :end_date WHERE id = :id;",
+    )?;
+    for (id, start_date, end_date) in rows {
+        let start_date = normalize(&start_date).unwrap_or(start_date);
+        let end_date = end_date.map(|date| normalize(&date).unwrap_or(date));
+        update.execute_named(&[
+            (":id", &id as &dyn rusqlite::ToSql),
+            (":start_date", &start_date),
+            (":end_date", &end_date),
+        ])?;
+    }
+    Ok(())
+}
+
+fn normalize(date: &str) -> Option<String> {
+    NaiveDate::parse_from_str(date, "%m/%d/%Y")
+        .ok()
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
@@ -0,0 +1,4 @@
+-- Every write to a record bumps its version, which clients see as the
+-- record's ETag and send back in If-Match to detect lost updates.
+ALTER TABLE book ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE reading ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
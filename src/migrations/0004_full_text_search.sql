@@ -0,0 +1,48 @@
+-- Full-text indexes over the free text of books and readings. Both are
+-- external content tables, they only hold the index and read the text
+-- itself from book and reading, which the triggers keep them in sync with.
+CREATE VIRTUAL TABLE book_fts USING fts5(
+	title, author, notes,
+	content='book', content_rowid='id',
+	tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER book_fts_after_insert AFTER INSERT ON book BEGIN
+	INSERT INTO book_fts (rowid, title, author, notes)
+	VALUES (new.id, new.title, new.author, new.notes);
+END;
+
+CREATE TRIGGER book_fts_after_delete AFTER DELETE ON book BEGIN
+	INSERT INTO book_fts (book_fts, rowid, title, author, notes)
+	VALUES ('delete', old.id, old.title, old.author, old.notes);
+END;
+
+CREATE TRIGGER book_fts_after_update AFTER UPDATE OF title, author, notes ON book BEGIN
+	INSERT INTO book_fts (book_fts, rowid, title, author, notes)
+	VALUES ('delete', old.id, old.title, old.author, old.notes);
+	INSERT INTO book_fts (rowid, title, author, notes)
+	VALUES (new.id, new.title, new.author, new.notes);
+END;
+
+CREATE VIRTUAL TABLE reading_fts USING fts5(
+	notes,
+	content='reading', content_rowid='id',
+	tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER reading_fts_after_insert AFTER INSERT ON reading BEGIN
+	INSERT INTO reading_fts (rowid, notes) VALUES (new.id, new.notes);
+END;
+
+CREATE TRIGGER reading_fts_after_delete AFTER DELETE ON reading BEGIN
+	INSERT INTO reading_fts (reading_fts, rowid, notes) VALUES ('delete', old.id, old.notes);
+END;
+
+CREATE TRIGGER reading_fts_after_update AFTER UPDATE OF notes ON reading BEGIN
+	INSERT INTO reading_fts (reading_fts, rowid, notes) VALUES ('delete', old.id, old.notes);
+	INSERT INTO reading_fts (rowid, notes) VALUES (new.id, new.notes);
+END;
+
+-- Index everything that was written before the tables existed
+INSERT INTO book_fts (book_fts) VALUES ('rebuild');
+INSERT INTO reading_fts (reading_fts) VALUES ('rebuild');
@@ -0,0 +1,207 @@
+use rusqlite::{OptionalExtension, Transaction, NO_PARAMS};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+const CREATE_TABLES: &str = "
+CREATE TABLE author (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`name`	TEXT NOT NULL,
+	`name_key`	TEXT NOT NULL UNIQUE,
+	`notes`	TEXT,
+	`version`	INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE book_author (
+	`book`	INTEGER NOT NULL REFERENCES book(id),
+	`author`	INTEGER NOT NULL REFERENCES author(id),
+	`role`	TEXT NOT NULL DEFAULT 'author'
+		CHECK(role IN ('author', 'translator', 'editor', 'illustrator')),
+	`position`	INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (book, author, role)
+);
+
+CREATE INDEX book_author_by_author ON book_author (author);
+
+CREATE TRIGGER book_author_after_book_delete AFTER DELETE ON book BEGIN
+	DELETE FROM book_author WHERE book = old.id;
+END;
+";
+
+/**
+Creates the author and book_author tables and credits every existing
+book with the people its `author` text names, so "Tolkien, J.R.R." and
+"J. R. R. Tolkien" end up as one author. The text itself is left as it
+was written.
+
+The name parsing below is a copy of `models::author` as it was when
+this migration was released, so that changes to the model can't change
+what the migration does. Leave it be even if the model moves on.
+*/
+pub fn up(tx: &Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute_batch(CREATE_TABLES)?;
+
+    let mut select = tx.prepare("SELECT id, author FROM book ORDER BY id;")?;
+    let books = select
+        .query_map(NO_PARAMS, |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut insert = tx.prepare(
+        "INSERT INTO book_author (book, author, role, position) VALUES (:book, :author, :role, :position);",
+    )?;
+    for (id, text) in books {
+        let mut credits: Vec<(u32, &str)> = Vec::new();
+        for (name, role) in parse_credits(&text) {
+            let author = find_or_create(tx, &name)?;
+            if !credits.contains(&(author, role)) {
+                credits.push((author, role));
+            }
+        }
+        for (position, (author, role)) in credits.iter().enumerate() {
+            insert.execute_named(&[
+                (":book", &id as &dyn rusqlite::ToSql),
+                (":author", author),
+                (":role", role),
+                (":position", &(position as u32)),
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+const NAME_SUFFIXES: &[&str] = &["jr", "sr", "ii", "iii", "iv"];
+
+fn find_or_create(tx: &Transaction, name: &str) -> Result<u32, rusqlite::Error> {
+    let name = display_name(name);
+    let key = name_key(&name);
+    let existing: Option<u32> = tx
+        .query_row_named("SELECT id FROM author WHERE name_key = :key;", &[(":key", &key)], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    match existing {
+        Some(id) => Ok(id),
+        None => {
+            tx.execute_named(
+                "INSERT INTO author (name, name_key) VALUES (:name, :key);",
+                &[(":name", &name), (":key", &key)],
+            )?;
+            Ok(tx.last_insert_rowid() as u32)
+        }
+    }
+}
+
+fn parse_credits(text: &str) -> Vec<(String, &'static str)> {
+    let mut credits: Vec<(String, &'static str)> = Vec::new();
+    for part in split_names(text) {
+        let (name, role) = strip_role(part);
+        let name = display_name(name);
+        if name_key(&name).is_empty() {
+            continue;
+        }
+        let duplicate = credits
+            .iter()
+            .any(|(other, other_role)| *other_role == role && name_key(other) == name_key(&name));
+        if !duplicate {
+            credits.push((name, role));
+        }
+    }
+    credits
+}
+
+fn name_key(name: &str) -> String {
+    fold(&display_name(name))
+}
+
+fn display_name(name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut parts = name.splitn(2, ',');
+    let (last, first) = match (parts.next(), parts.next()) {
+        (Some(last), Some(first)) if !is_suffix(first) => (last.trim(), first.trim()),
+        _ => return name,
+    };
+    if first.is_empty() {
+        last.to_string()
+    } else {
+        format!("{} {}", first, last)
+    }
+}
+
+fn split_names(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    for part in text.split([';', '&']) {
+        for part in split_on_and(part) {
+            if looks_inverted(part) {
+                names.push(part);
+            } else {
+                names.extend(part.split(','));
+            }
+        }
+    }
+    names
+}
+
+fn split_on_and(text: &str) -> Vec<&str> {
+    let lower = text.to_ascii_lowercase();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    while let Some(found) = lower[start..].find(" and ") {
+        parts.push(&text[start..start + found]);
+        start += found + " and ".len();
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn looks_inverted(text: &str) -> bool {
+    let mut parts = text.split(',');
+    let (left, right) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(left), Some(right), None) => (left, right),
+        _ => return false,
+    };
+    let words = |side: &str| side.split_whitespace().count();
+    let has_initial = right
+        .split_whitespace()
+        .any(|word| word.trim_end_matches('.').chars().count() == 1 || word.matches('.').count() > 1);
+    words(left) == 1 || words(right) == 1 || has_initial || is_suffix(right)
+}
+
+fn is_suffix(text: &str) -> bool {
+    let word = text.trim().trim_end_matches('.').to_lowercase();
+    NAME_SUFFIXES.contains(&word.as_str())
+}
+
+fn strip_role(text: &str) -> (&str, &'static str) {
+    let text = text.trim();
+    if let Some(open) = text.rfind('(') {
+        if text.ends_with(')') {
+            let role = match text[open + 1..text.len() - 1].trim().to_lowercase().as_str() {
+                "author" => Some("author"),
+                "translator" | "trans." | "tr." => Some("translator"),
+                "editor" | "ed." | "eds." | "editors" => Some("editor"),
+                "illustrator" | "ill." | "illus." => Some("illustrator"),
+                _ => None,
+            };
+            if let Some(role) = role {
+                return (text[..open].trim(), role);
+            }
+        }
+    }
+    (text, "author")
+}
+
+fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'œ' => folded.push_str("oe"),
+            'þ' => folded.push_str("th"),
+            'ø' => folded.push('o'),
+            'ł' => folded.push('l'),
+            'đ' | 'ð' => folded.push('d'),
+            c if c.is_alphanumeric() => folded.push(c),
+            _ => folded.push(' '),
+        }
+    }
+    folded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
@@ -0,0 +1,26 @@
+-- Series of books. A book can belong to more than one series (a
+-- sub-series and the larger one around it, say), each with its own
+-- position, which may be fractional for novellas set between volumes.
+CREATE TABLE series (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`name`	TEXT NOT NULL,
+	`notes`	TEXT,
+	`version`	INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE book_series (
+	`book`	INTEGER NOT NULL REFERENCES book(id),
+	`series`	INTEGER NOT NULL REFERENCES series(id),
+	`position`	REAL,
+	PRIMARY KEY (series, book)
+);
+
+CREATE INDEX book_series_by_book ON book_series (book);
+
+CREATE TRIGGER book_series_after_book_delete AFTER DELETE ON book BEGIN
+	DELETE FROM book_series WHERE book = old.id;
+END;
+
+CREATE TRIGGER book_series_after_series_delete AFTER DELETE ON series BEGIN
+	DELETE FROM book_series WHERE series = old.id;
+END;
@@ -0,0 +1,89 @@
+use rusqlite::{Transaction, NO_PARAMS};
+
+/// The placeholder genre of books without one.
+const NO_GENRE: &str = "(no genres listed)";
+
+const CREATE_TABLES: &str = "
+CREATE TABLE tag (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`name`	TEXT NOT NULL UNIQUE COLLATE NOCASE
+);
+
+CREATE TABLE book_tag (
+	`book`	INTEGER NOT NULL REFERENCES book(id),
+	`tag`	INTEGER NOT NULL REFERENCES tag(id),
+	PRIMARY KEY (book, tag)
+);
+
+CREATE INDEX book_tag_by_tag ON book_tag (tag);
+
+CREATE TRIGGER book_tag_after_book_delete AFTER DELETE ON book BEGIN
+	DELETE FROM book_tag WHERE book = old.id;
+END;
+
+CREATE TRIGGER book_tag_after_tag_delete AFTER DELETE ON tag BEGIN
+	DELETE FROM book_tag WHERE tag = old.id;
+END;
+
+CREATE TABLE shelf (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`name`	TEXT NOT NULL UNIQUE COLLATE NOCASE,
+	`description`	TEXT,
+	`version`	INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE shelf_book (
+	`shelf`	INTEGER NOT NULL REFERENCES shelf(id),
+	`book`	INTEGER NOT NULL REFERENCES book(id),
+	`position`	INTEGER NOT NULL,
+	PRIMARY KEY (shelf, book)
+);
+
+CREATE INDEX shelf_book_by_book ON shelf_book (book);
+
+CREATE TRIGGER shelf_book_after_book_delete AFTER DELETE ON book BEGIN
+	DELETE FROM shelf_book WHERE book = old.id;
+END;
+
+CREATE TRIGGER shelf_book_after_shelf_delete AFTER DELETE ON shelf BEGIN
+	DELETE FROM shelf_book WHERE shelf = old.id;
+END;
+";
+
+/**
+Creates the tag and shelf tables and tags every existing book with its
+genres, so "Comedy|Drama" becomes the tags Comedy and Drama. The
+`genre` text itself is left as it was written.
+
+The genre splitting below is a copy of `models::tag` as it was when
+this migration was released, so that changes to the model can't change
+what the migration does. Leave it be even if the model moves on.
+*/
+pub fn up(tx: &Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute_batch(CREATE_TABLES)?;
+
+    let mut select = tx.prepare("SELECT id, genre FROM book WHERE genre IS NOT NULL ORDER BY id;")?;
+    let books = select
+        .query_map(NO_PARAMS, |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut insert_tag = tx.prepare("INSERT INTO tag (name) VALUES (:name) ON CONFLICT (name) DO NOTHING;")?;
+    let mut link = tx.prepare(
+        "INSERT INTO book_tag (book, tag) SELECT :book, id FROM tag WHERE name = :name
+ON CONFLICT (book, tag) DO NOTHING;",
+    )?;
+    for (id, genre) in books {
+        for name in genre_tags(&genre) {
+            insert_tag.execute_named(&[(":name", &name)])?;
+            link.execute_named(&[(":book", &id as &dyn rusqlite::ToSql), (":name", &name)])?;
+        }
+    }
+    Ok(())
+}
+
+fn genre_tags(genre: &str) -> Vec<String> {
+    genre
+        .split(['|', ',', ';'])
+        .map(|name| name.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case(NO_GENRE))
+        .collect()
+}
@@ -0,0 +1,9 @@
+-- Edition details for books. The ISBN is stored as a bare ISBN-13 (see
+-- models::isbn), and an edition can only be added once.
+ALTER TABLE book ADD COLUMN `isbn` TEXT;
+ALTER TABLE book ADD COLUMN `publisher` TEXT;
+ALTER TABLE book ADD COLUMN `year` INTEGER;
+ALTER TABLE book ADD COLUMN `language` TEXT;
+ALTER TABLE book ADD COLUMN `edition` TEXT;
+
+CREATE UNIQUE INDEX book_by_isbn ON book (isbn);
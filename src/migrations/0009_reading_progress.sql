@@ -0,0 +1,19 @@
+-- A log of how far into the book a reading has got. Each entry records
+-- either a page or a percentage (audiobooks and ebooks often only have
+-- the latter), and is timestamped in UTC as YYYY-MM-DDTHH:MM:SSZ so
+-- entries sort by time as text.
+CREATE TABLE reading_progress (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`reading`	INTEGER NOT NULL REFERENCES reading(id),
+	`logged_at`	TEXT NOT NULL,
+	`page`	INTEGER CHECK(page >= 0),
+	`percent`	REAL CHECK(percent BETWEEN 0 AND 100),
+	`note`	TEXT,
+	CHECK((page IS NULL) != (percent IS NULL))
+);
+
+CREATE INDEX reading_progress_by_reading ON reading_progress (reading, logged_at);
+
+CREATE TRIGGER reading_progress_after_reading_delete AFTER DELETE ON reading BEGIN
+	DELETE FROM reading_progress WHERE reading = old.id;
+END;
@@ -0,0 +1,45 @@
+-- Rebuilds reading with a foreign key on its book, since sqlite can't
+-- add one to an existing table. Readings of books that were deleted
+-- before the key existed are kept but detached, with a null book.
+UPDATE reading SET book = NULL WHERE book NOT IN (SELECT id FROM book);
+
+CREATE TABLE reading_new (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`book`	INTEGER REFERENCES book(id),
+	`start_date`	TEXT NOT NULL,
+	`end_date`	TEXT,
+	`notes`	TEXT,
+	`version`	INTEGER NOT NULL DEFAULT 1
+);
+
+INSERT INTO reading_new (id, book, start_date, end_date, notes, version)
+SELECT id, book, start_date, end_date, notes, version FROM reading;
+
+-- Keep ids of deleted readings from being handed out again
+UPDATE sqlite_sequence SET seq = (SELECT seq FROM sqlite_sequence WHERE name = 'reading')
+WHERE name = 'reading_new' AND EXISTS (SELECT 1 FROM sqlite_sequence WHERE name = 'reading');
+
+DROP TABLE reading;
+ALTER TABLE reading_new RENAME TO reading;
+
+CREATE INDEX reading_by_book ON reading (book);
+
+-- Dropping the old table dropped its triggers along with it
+CREATE TRIGGER reading_fts_after_insert AFTER INSERT ON reading BEGIN
+	INSERT INTO reading_fts (rowid, notes) VALUES (new.id, new.notes);
+END;
+
+CREATE TRIGGER reading_fts_after_delete AFTER DELETE ON reading BEGIN
+	INSERT INTO reading_fts (reading_fts, rowid, notes) VALUES ('delete', old.id, old.notes);
+END;
+
+CREATE TRIGGER reading_fts_after_update AFTER UPDATE OF notes ON reading BEGIN
+	INSERT INTO reading_fts (reading_fts, rowid, notes) VALUES ('delete', old.id, old.notes);
+	INSERT INTO reading_fts (rowid, notes) VALUES (new.id, new.notes);
+END;
+
+CREATE TRIGGER reading_progress_after_reading_delete AFTER DELETE ON reading BEGIN
+	DELETE FROM reading_progress WHERE reading = old.id;
+END;
+
+INSERT INTO reading_fts (reading_fts) VALUES ('rebuild');
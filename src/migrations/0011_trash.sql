@@ -0,0 +1,16 @@
+-- Deleting a book or a reading moves it to the trash instead of erasing
+-- it: deleted_at is set to the UTC time of the deletion, formatted as
+-- YYYY-MM-DDTHH:MM:SSZ, and every query leaves such rows out until they
+-- are restored or purged for good.
+ALTER TABLE book ADD COLUMN `deleted_at` TEXT;
+ALTER TABLE reading ADD COLUMN `deleted_at` TEXT;
+-- Set on readings that went to the trash because their book did, which
+-- come back out when the book is restored
+ALTER TABLE reading ADD COLUMN `deleted_with_book` INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX book_by_deleted_at ON book (deleted_at);
+CREATE INDEX reading_by_deleted_at ON reading (deleted_at);
+
+-- A book in the trash doesn't keep its ISBN from being used again
+DROP INDEX book_by_isbn;
+CREATE UNIQUE INDEX book_by_isbn ON book (isbn) WHERE deleted_at IS NULL;
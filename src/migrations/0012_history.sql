@@ -0,0 +1,25 @@
+-- Every write to a book or a reading adds a revision to this table,
+-- with the record as JSON before and after the write. before is null
+-- for a creation and after for a deletion. Revisions are numbered per
+-- record from 1, and the table is never changed after the fact, even
+-- when the record is purged from the trash.
+CREATE TABLE history (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`entity`	TEXT NOT NULL CHECK(entity IN ('book', 'reading')),
+	`entity_id`	INTEGER NOT NULL,
+	`revision`	INTEGER NOT NULL,
+	`action`	TEXT NOT NULL CHECK(action IN ('create', 'update', 'delete', 'restore', 'revert')),
+	`actor`	TEXT,
+	`changed_at`	TEXT NOT NULL,
+	`before`	TEXT,
+	`after`	TEXT,
+	UNIQUE (entity, entity_id, revision)
+);
+
+CREATE TRIGGER history_before_update BEFORE UPDATE ON history BEGIN
+	SELECT RAISE(ABORT, 'history is append-only');
+END;
+
+CREATE TRIGGER history_before_delete BEFORE DELETE ON history BEGIN
+	SELECT RAISE(ABORT, 'history is append-only');
+END;
@@ -0,0 +1,26 @@
+-- Long-form notes in Markdown, on a book or on a reading, kept apart
+-- from the short notes column so they can run to tens of kilobytes
+-- without weighing down every listing of books. Times are UTC as
+-- YYYY-MM-DDTHH:MM:SSZ.
+CREATE TABLE note (
+	`id`	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+	`book`	INTEGER REFERENCES book(id),
+	`reading`	INTEGER REFERENCES reading(id),
+	`body`	TEXT NOT NULL,
+	`created_at`	TEXT NOT NULL,
+	`updated_at`	TEXT NOT NULL,
+	`version`	INTEGER NOT NULL DEFAULT 1,
+	CHECK((book IS NULL) != (reading IS NULL))
+);
+
+CREATE INDEX note_by_book ON note (book);
+CREATE INDEX note_by_reading ON note (reading);
+
+-- Purging a book or a reading from the trash takes its notes with it
+CREATE TRIGGER note_after_book_delete AFTER DELETE ON book BEGIN
+	DELETE FROM note WHERE book = old.id;
+END;
+
+CREATE TRIGGER note_after_reading_delete AFTER DELETE ON reading BEGIN
+	DELETE FROM note WHERE reading = old.id;
+END;
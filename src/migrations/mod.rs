@@ -0,0 +1,368 @@
+/*!
+
+# migrations
+
+This module owns the database schema. Every change to the schema is a
+numbered `Migration`, and the number of the last migration applied to
+a database file is stored in that file's `PRAGMA user_version`. An
+empty file starts at version 0, so running every migration in order
+creates the whole schema from scratch, and an older file only runs the
+migrations it hasn't seen yet.
+
+Migrations only go forward. Each one runs inside its own transaction
+together with the `user_version` bump, so a migration that fails
+leaves the file at the previous version instead of half upgraded.
+Foreign keys aren't enforced while migrations run, so that tables can
+be rebuilt, but each migration has to leave every reference intact
+before it is committed.
+
+To change the schema, add a new entry to the end of `MIGRATIONS`.
+Never edit a migration that has already been released, databases in
+the wild have already recorded it as applied.
+
+!*/
+
+use rusqlite::{Connection, OptionalExtension, Transaction, NO_PARAMS};
+use std::fmt;
+
+#[path = "0002_normalize_reading_dates.rs"]
+mod normalize_reading_dates;
+#[path = "0005_authors.rs"]
+mod authors;
+#[path = "0007_tags_and_shelves.rs"]
+mod tags_and_shelves;
+
+pub enum Step {
+    /// A batch of SQL statements, usually `include_str!`ed from this directory.
+    Sql(&'static str),
+    /// For data migrations that are easier to express in Rust than SQL.
+    Rust(fn(&Transaction) -> Result<(), rusqlite::Error>),
+}
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub step: Step,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create book and reading tables",
+        step: Step::Sql(include_str!("0001_create_book_and_reading.sql")),
+    },
+    Migration {
+        version: 2,
+        description: "store reading dates as YYYY-MM-DD",
+        step: Step::Rust(normalize_reading_dates::up),
+    },
+    Migration {
+        version: 3,
+        description: "add a version to books and readings",
+        step: Step::Sql(include_str!("0003_add_record_versions.sql")),
+    },
+    Migration {
+        version: 4,
+        description: "full-text search over books and readings",
+        step: Step::Sql(include_str!("0004_full_text_search.sql")),
+    },
+    Migration {
+        version: 5,
+        description: "authors credited on books with a role",
+        step: Step::Rust(authors::up),
+    },
+    Migration {
+        version: 6,
+        description: "series of books with a reading order",
+        step: Step::Sql(include_str!("0006_series.sql")),
+    },
+    Migration {
+        version: 7,
+        description: "tags and shelves, with genres turned into tags",
+        step: Step::Rust(tags_and_shelves::up),
+    },
+    Migration {
+        version: 8,
+        description: "ISBN, publisher, year, language and edition of books",
+        step: Step::Sql(include_str!("0008_book_editions.sql")),
+    },
+    Migration {
+        version: 9,
+        description: "a log of reading progress",
+        step: Step::Sql(include_str!("0009_reading_progress.sql")),
+    },
+    Migration {
+        version: 10,
+        description: "a foreign key from readings to their book",
+        step: Step::Sql(include_str!("0010_reading_book_foreign_key.sql")),
+    },
+    Migration {
+        version: 11,
+        description: "a trash for deleted books and readings",
+        step: Step::Sql(include_str!("0011_trash.sql")),
+    },
+    Migration {
+        version: 12,
+        description: "an append-only history of book and reading changes",
+        step: Step::Sql(include_str!("0012_history.sql")),
+    },
+    Migration {
+        version: 13,
+        description: "long-form Markdown notes on books and readings",
+        step: Step::Sql(include_str!("0013_notes.sql")),
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(rusqlite::Error),
+    /// The requested target version doesn't match any known migration.
+    UnknownVersion(u32),
+    /// The requested target is older than the database, which would need a downgrade.
+    Downgrade { current: u32, target: u32 },
+    /// The database was migrated by a newer build of alexandria-db.
+    TooNew { current: u32, latest: u32 },
+    /// The database needs migrations that weren't allowed to run.
+    Pending { current: u32, latest: u32 },
+    /// The migration left rows in `table` referring to records that don't exist.
+    BrokenReferences { version: u32, table: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Database(err) => write!(f, "database error: {}", err),
+            MigrationError::UnknownVersion(version) => write!(
+                f,
+                "there is no migration {}, the latest is {}",
+                version,
+                latest_version()
+            ),
+            MigrationError::Downgrade { current, target } => write!(
+                f,
+                "the database is at version {} and migrations can't be undone (asked for {})",
+                current, target
+            ),
+            MigrationError::TooNew { current, latest } => write!(
+                f,
+                "the database is at version {} but this build only knows up to version {}",
+                current, latest
+            ),
+            MigrationError::Pending { current, latest } => write!(
+                f,
+                "the database is at version {} but this build expects {}, \
+                 run `alexandria-db migrate --up` first",
+                current, latest
+            ),
+            MigrationError::BrokenReferences { version, table } => write!(
+                f,
+                "migration {} left rows in {} referring to records that don't exist",
+                version, table
+            ),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Database(err)
+    }
+}
+
+/// The version a database ends up at after every known migration has run.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn current_version(conn: &Connection) -> Result<u32, rusqlite::Error> {
+    conn.query_row("PRAGMA user_version;", NO_PARAMS, |row| row.get(0))
+}
+
+/**
+Returns every known migration paired with whether it has already been
+applied to the given database.
+*/
+pub fn status(conn: &Connection) -> Result<Vec<(&'static Migration, bool)>, MigrationError> {
+    let current = current_version(conn)?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|migration| (migration, migration.version <= current))
+        .collect())
+}
+
+/// Applies every migration the database hasn't seen yet.
+pub fn migrate_up(conn: &mut Connection) -> Result<Vec<u32>, MigrationError> {
+    migrate_to(conn, latest_version())
+}
+
+/**
+Applies migrations in order until the database is at `target`, and
+returns the versions that were applied. Asking for the version the
+database is already at is not an error, it simply applies nothing.
+*/
+pub fn migrate_to(conn: &mut Connection, target: u32) -> Result<Vec<u32>, MigrationError> {
+    let current = current_version(conn)?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(MigrationError::TooNew { current, latest });
+    }
+    if target > latest {
+        return Err(MigrationError::UnknownVersion(target));
+    }
+    if target < current {
+        return Err(MigrationError::Downgrade { current, target });
+    }
+
+    // Changing foreign_keys is a no-op inside a transaction, so it is
+    // switched off around all of them and back on if it was on before
+    let enforced: bool = conn.query_row("PRAGMA foreign_keys;", NO_PARAMS, |row| row.get(0))?;
+    conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+    let applied = apply_migrations(conn, current, target);
+    if enforced {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    }
+    applied
+}
+
+fn apply_migrations(conn: &mut Connection, current: u32, target: u32) -> Result<Vec<u32>, MigrationError> {
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| m.version > current && m.version <= target)
+    {
+        let tx = conn.transaction()?;
+        match migration.step {
+            Step::Sql(sql) => tx.execute_batch(sql)?,
+            Step::Rust(step) => step(&tx)?,
+        }
+        let broken: Option<String> = tx
+            .query_row("PRAGMA foreign_key_check;", NO_PARAMS, |row| row.get(0))
+            .optional()?;
+        if let Some(table) = broken {
+            return Err(MigrationError::BrokenReferences {
+                version: migration.version,
+                table,
+            });
+        }
+        // PRAGMA doesn't take bound parameters, but version is a u32 we control
+        tx.execute_batch(&format!("PRAGMA user_version = {};", migration.version))?;
+        tx.commit()?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrating_an_empty_database_creates_the_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+
+        let applied = migrate_up(&mut conn).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+
+        // Running it again is a no-op
+        assert!(migrate_up(&mut conn).unwrap().is_empty());
+
+        conn.execute(
+            "INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');",
+            NO_PARAMS,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn pre_migration_databases_keep_their_data() {
+        // The schema as it existed before migrations, from src/db_storage/dummy.db
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE book (id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+                title TEXT NOT NULL, author TEXT NOT NULL, pages INTEGER, genre TEXT,
+                medium TEXT NOT NULL, rating INTEGER, notes TEXT);
+             CREATE TABLE reading (id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+                book INTEGER, start_date TEXT NOT NULL, end_date TEXT, notes TEXT);
+             INSERT INTO book (title, author, medium) VALUES ('Dune', 'Frank Herbert', 'paper');
+             INSERT INTO book (title, author, medium) VALUES ('Dune Messiah', 'Herbert, Frank', 'paper');
+             INSERT INTO reading (book, start_date, end_date) VALUES (1, '11/20/2020', NULL);
+             INSERT INTO reading (book, start_date, end_date) VALUES (7, '01/02/2019', NULL);",
+        )
+        .unwrap();
+
+        migrate_up(&mut conn).unwrap();
+        let title: String = conn
+            .query_row("SELECT title FROM book WHERE id = 1;", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(title, "Dune");
+        // Both spellings of the author's name are credited to the same person
+        let credits: Vec<(u32, String)> = conn
+            .prepare("SELECT book, name FROM book_author JOIN author ON author.id = book_author.author ORDER BY book;")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(credits, vec![(1, "Frank Herbert".to_string()), (2, "Frank Herbert".to_string())]);
+        let start_date: String = conn
+            .query_row("SELECT start_date FROM reading WHERE id = 1;", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(start_date, "2020-11-20");
+        // The reading of a book deleted long ago is kept, without the book
+        let book: Option<u32> = conn
+            .query_row("SELECT book FROM reading WHERE id = 2;", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(book, None);
+    }
+
+    #[test]
+    fn genres_are_migrated_into_tags() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 6).unwrap();
+        conn.execute_batch(
+            "INSERT INTO book (title, author, medium, genre) VALUES ('Dune', 'Frank Herbert', 'paper', 'Sci-Fi|Adventure');
+             INSERT INTO book (title, author, medium, genre) VALUES ('Emma', 'Jane Austen', 'paper', ' Comedy ;  Romance, sci-fi');
+             INSERT INTO book (title, author, medium, genre) VALUES ('Notes', 'Anonymous', 'paper', '(no genres listed)');
+             INSERT INTO book (title, author, medium) VALUES ('Untitled', 'Anonymous', 'paper');",
+        )
+        .unwrap();
+
+        migrate_to(&mut conn, 7).unwrap();
+        let tags: Vec<String> = conn
+            .prepare("SELECT name FROM tag ORDER BY id;")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["Sci-Fi", "Adventure", "Comedy", "Romance"]);
+        // Tags are matched without regard to case, so "sci-fi" is the tag "Sci-Fi"
+        let links: Vec<(u32, String)> = conn
+            .prepare("SELECT book, name FROM book_tag JOIN tag ON tag.id = book_tag.tag ORDER BY book, tag.id;")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = [(1, "Sci-Fi"), (1, "Adventure"), (2, "Sci-Fi"), (2, "Comedy"), (2, "Romance")];
+        assert_eq!(links, expected.iter().map(|(book, name)| (*book, name.to_string())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn invalid_targets_are_rejected() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_up(&mut conn).unwrap();
+
+        assert!(matches!(
+            migrate_to(&mut conn, latest_version() + 1),
+            Err(MigrationError::UnknownVersion(_))
+        ));
+        assert!(matches!(
+            migrate_to(&mut conn, 0),
+            Err(MigrationError::Downgrade { .. })
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+use std::convert::Infallible;
+use warp::Filter;
+
+use crate::api::controllers::batch;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_actor, with_state};
+
+const BATCH_ROOT: &str = "batch";
+
+/**
+
+batch#run maps to POST requests on the path /batch and runs the list
+of operations in the body in one transaction. The body has its own
+limit (batch_body_limit_kb), larger than a single record's, to fit a
+full batch.
+
+See the documentation for batch::batch_handler() for details on what
+this route returns.
+
+**/
+pub fn run(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BATCH_ROOT)
+        .and(warp::path::end())
+	.and(warp::post())
+	.and(body_limit(state.body_limits.batch))
+	.and(warp::body::json())
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|body: serde_json::Value, actor: Option<String>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(batch::batch_handler(state, body, actor).await)
+	})
+}
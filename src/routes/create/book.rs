@@ -1,16 +1,19 @@
+use std::convert::Infallible;
 use warp::Filter;
 use serde_json;
 use std::collections::HashMap;
 
 use crate::api::controllers::book;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_actor, with_state};
 
 const CREATE_ROOT: &str = "create";
 const BOOK_ROOT: &str = "book";
 
-pub fn new_book() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn new_book(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     /* Notes:
-    content_length_limit imposes a restriction on the content-length
-    HTTP header in bytes (1024 * 4 is about 4kB)
+    body_limit turns away bodies larger than the configured limit for
+    writing a record (record_body_limit_kb) with a 413 error
 
     warp::body::json() causes the filter to pass a HashMap into its
     closure.
@@ -19,15 +22,17 @@ pub fn new_book() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejec
     warp::path(CREATE_ROOT)
 	.and(warp::path(BOOK_ROOT))
 	.and(warp::post())
-	.and(warp::body::content_length_limit(1024 * 4))
+	.and(body_limit(state.body_limits.record))
 	.and(warp::body::json())
-	.map(|body: HashMap<String, serde_json::Value>| {
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|body: HashMap<String, serde_json::Value>, actor: Option<String>, state: AppState| async move {
             // Have to turn the payload back into a string
             // because otherwise I would have to manually
             // parse the HashMap and I don't want to do that.
             // I wasn't aware Warp used serde_json internally
             // to do this when I first wrote this endpoint
             let body = serde_json::to_string(&body).unwrap();
-            book::create_book_handler(body)
+            Ok::<_, Infallible>(book::create_book_handler(state, body, actor).await)
 	})
 } 
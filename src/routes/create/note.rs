@@ -0,0 +1,61 @@
+use std::convert::Infallible;
+use warp::Filter;
+use warp::hyper::body::Bytes;
+
+use crate::api::controllers::note;
+use crate::api::models::history::Entity;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_state};
+
+/**
+
+note#for_book maps to POST requests on the path /book/id/:id/notes and
+adds the Markdown note in the body to the book.
+
+See the documentation for note::create_note_handler() for details on
+what this route returns.
+
+**/
+pub fn for_book(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    for_record("book", Entity::Book, state)
+}
+
+/**
+
+note#for_reading maps to POST requests on the path
+/reading/id/:id/notes and adds the Markdown note in the body to the
+reading.
+
+See the documentation for note::create_note_handler() for details on
+what this route returns.
+
+**/
+pub fn for_reading(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    for_record("reading", Entity::Reading, state)
+}
+
+fn for_record(
+    root: &'static str,
+    owner: Entity,
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    /* Notes:
+    Notes get a limit of their own (note_body_limit_kb), well above the
+    one for records, and are read as raw bytes since they can be sent
+    as Markdown rather than JSON.
+    */
+
+    warp::path(root)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("notes"))
+        .and(warp::path::end())
+	.and(warp::post())
+	.and(body_limit(state.body_limits.note))
+	.and(warp::header::optional::<String>("content-type"))
+	.and(warp::body::bytes())
+	.and(with_state(state))
+	.and_then(move |id: u32, content_type: Option<String>, body: Bytes, state: AppState| async move {
+            Ok::<_, Infallible>(note::create_note_handler(state, owner, id, content_type, body.to_vec()).await)
+	})
+}
@@ -1,14 +1,17 @@
 use crate::api::controllers::reading;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_actor, with_state};
 use std::collections::HashMap;
+use std::convert::Infallible;
 use warp::Filter;
 
 const CREATE_ROOT: &str = "create";
 const READINGS_ROOT: &str = "reading";
 
-pub fn new_reading() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn new_reading(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     /* Notes:
-    content_length_limit imposes a restriction on the content-length
-    HTTP header in bytes (1024 * 4 is about 4kB)
+    body_limit turns away bodies larger than the configured limit for
+    writing a record (record_body_limit_kb) with a 413 error
 
     warp::body::json() causes the filter to pass a HashMap into its
     closure.
@@ -17,10 +20,12 @@ pub fn new_reading() -> impl Filter<Extract = impl warp::Reply, Error = warp::Re
     warp::path(CREATE_ROOT)
         .and(warp::path(READINGS_ROOT))
         .and(warp::post())
-        .and(warp::body::content_length_limit(1024 * 4))
+        .and(body_limit(state.body_limits.record))
         .and(warp::body::json())
-        .map(|body: HashMap<String, serde_json::Value>| {
+        .and(with_actor())
+        .and(with_state(state))
+        .and_then(|body: HashMap<String, serde_json::Value>, actor: Option<String>, state: AppState| async move {
             let body = serde_json::to_string(&body).unwrap();
-            reading::create_reading_handler(body)
+            Ok::<_, Infallible>(reading::create_reading_handler(state, body, actor).await)
         })
 }
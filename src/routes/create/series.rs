@@ -0,0 +1,22 @@
+use crate::api::controllers::series;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_state};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::Filter;
+
+const CREATE_ROOT: &str = "create";
+const SERIES_ROOT: &str = "series";
+
+pub fn new_series(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(CREATE_ROOT)
+        .and(warp::path(SERIES_ROOT))
+        .and(warp::post())
+        .and(body_limit(state.body_limits.record))
+        .and(warp::body::json())
+        .and(with_state(state))
+        .and_then(|body: HashMap<String, serde_json::Value>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(series::create_series_handler(state, body).await)
+        })
+}
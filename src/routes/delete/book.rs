@@ -1,14 +1,32 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
 use warp::Filter;
 use crate::api::controllers::book;
+use crate::api::state::AppState;
+use crate::routes::filters::{with_actor, with_state};
 
 const BOOK_ROOT: &str = "book";
 
-pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+/**
+
+book#by_id maps to DELETE requests on the path /book/id/:id and
+accepts the optional query parameter on_readings.
+
+See the documentation for book_api::delete_book_handler() for details
+on what this route returns.
+
+**/
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(BOOK_ROOT)
         .and(warp::path("id"))
         .and(warp::path::param())
+        .and(warp::path::end())
         .and(warp::delete())
-        .map(|id: u32| {
-	    book::delete_book_handler(id)
+        .and(warp::query::query())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_actor())
+        .and(with_state(state))
+        .and_then(|id: u32, params: HashMap<String, String>, if_match: Option<String>, actor: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(book::delete_book_handler(state, id, params, if_match, actor).await)
 	})
 }
@@ -1,2 +1,7 @@
+pub mod author;
 pub mod book;
+pub mod note;
 pub mod reading;
+pub mod series;
+pub mod shelf;
+pub mod tag;
@@ -0,0 +1,28 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::note;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const NOTE_ROOT: &str = "note";
+
+/**
+
+note#by_id maps to DELETE requests on the path /note/id/:id.
+
+See the documentation for note::delete_note_handler() for details on
+what this route returns.
+
+**/
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(NOTE_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(note::delete_note_handler(state, id, if_match).await)
+	})
+}
@@ -1,14 +1,36 @@
+use std::convert::Infallible;
 use warp::Filter;
 use crate::api::controllers::reading;
+use crate::api::state::AppState;
+use crate::routes::filters::{with_actor, with_state};
 
 const READING_ROOT: &str = "reading";
 
-pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(READING_ROOT)
         .and(warp::path("id"))
         .and(warp::path::param())
+        .and(warp::path::end())
         .and(warp::delete())
-        .map(|id: u32| {
-	    reading::delete_reading_handler(id)
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_actor())
+        .and(with_state(state))
+        .and_then(|id: u32, if_match: Option<String>, actor: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(reading::delete_reading_handler(state, id, if_match, actor).await)
+	})
+}
+
+pub fn progress_entry(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READING_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("progress"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, entry: u32, if_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(reading::delete_progress_handler(state, id, entry, if_match).await)
 	})
 }
@@ -0,0 +1,41 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::series;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const SERIES_ROOT: &str = "series";
+
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(series::delete_series_handler(state, id, if_match).await)
+	})
+}
+
+/**
+
+series#remove_book maps to DELETE requests on the path
+/series/id/:id/books/:book and takes the book out of the series.
+
+**/
+pub fn remove_book(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("books"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, book: u32, if_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(series::remove_book_from_series_handler(state, id, book, if_match).await)
+	})
+}
@@ -0,0 +1,20 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::shelf;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const SHELF_ROOT: &str = "shelf";
+
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SHELF_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(shelf::delete_shelf_handler(state, id, if_match).await)
+	})
+}
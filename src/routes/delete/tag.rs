@@ -0,0 +1,19 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::tag;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const TAG_ROOT: &str = "tag";
+
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(TAG_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(tag::delete_tag_handler(state, id).await)
+	})
+}
@@ -0,0 +1,43 @@
+use std::convert::Infallible;
+use warp::Filter;
+
+use crate::api::error::ApiError;
+use crate::api::state::AppState;
+
+/**
+Hands a clone of the application state to the filter chain, so that
+route closures can take an `AppState` argument alongside their path
+parameters and request bodies.
+*/
+pub fn with_state(
+    state: AppState,
+) -> impl Filter<Extract = (AppState,), Error = Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
+/**
+Extracts who is making the request from the optional X-Actor header,
+for the history of the records it changes (see `models::history`).
+*/
+pub fn with_actor() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("x-actor")
+}
+
+/**
+Rejects requests whose body is longer than `limit` bytes with an
+`ApiError::PayloadTooLarge` naming the limit, so the client gets a 413
+that says how much the route accepts. Bodies without a Content-Length
+are still held to the limit by warp, which answers them with its own
+rejections.
+*/
+pub fn body_limit(limit: u64) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and_then(move |length: Option<u64>| async move {
+            match length {
+                Some(length) if length > limit => Err(warp::reject::custom(ApiError::PayloadTooLarge(limit))),
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(warp::body::content_length_limit(limit))
+}
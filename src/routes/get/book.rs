@@ -1,23 +1,31 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
 use warp::Filter;
-use crate::api::controllers::book;
+use crate::api::controllers::{author, book, history, tag};
+use crate::api::models::history::Entity;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
 
 const BOOK_ROOT: &str = "book";
 
 /** 
 
-book#all maps to the path /book/all
+book#all maps to the path /book/all and accepts the optional query
+parameters limit, offset, sort and order.
 
-Currently this route always returns 200 with no body for any get
-request. But in the future this route is intended to return all rows
-from the book table (all book objects) in the sqlite database. This
-functionality may be revised in the future.
+See the documentation for book_api::all_books_handler() for details
+on what this route returns.
 
 **/
-pub fn all() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn all(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(BOOK_ROOT)
         .and(warp::path("all"))
 	.and(warp::get())
-        .map(|| format!("Tried to get all books!"))
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(book::all_books_handler(state, params).await)
+	})
 }
 
 /** 
@@ -29,13 +37,16 @@ See the documentation for book_api::book_by_id_handler() for details
 on what this route returns.
 
 **/
-pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(BOOK_ROOT)
         .and(warp::path("id"))
         .and(warp::path::param())
+        .and(warp::path::end())
 	.and(warp::get())
-        .map(|id: u32| {
-	    book::book_by_id_handler(id)
+	.and(warp::header::optional::<String>("if-none-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_none_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(book::book_by_id_handler(state, id, if_none_match).await)
 	})
 }
 
@@ -43,36 +54,149 @@ pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejectio
 
 book#by_title maps to the path /book/title/:title where :title is a
 string corresponding to the title column of the book table in sqlite.
+It accepts the optional query parameters fuzzy and min_score.
 
-Currently this route always returns 200 with no body for any get
-request. But in the future this route is intended to return any rows
-from the book table (book objects) where the title matches the
-supplied parameter.
+See the documentation for book_api::books_by_title_handler() for
+details on what this route returns.
 
 **/
-pub fn by_title() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_title(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(BOOK_ROOT)
         .and(warp::path("title"))
         .and(warp::path::param())
 	.and(warp::get())
-        .map(|title: String| format!("Tried to get book with title: {}", title))
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|title: String, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(book::books_by_title_handler(state, title, params).await)
+	})
 }
 /** 
 
 book#by_author() maps to the path /book/author/:author where :author
 is a string corresponding to the author column of the book toble in
-sqlite.
+sqlite. It accepts the optional query parameters fuzzy and min_score.
 
-Currently this route always returns 200 with no body for any get
-request. But in the future this route is intended to return any rows
-from the book table (book objects) where the author matches the
-supplied parameter.
+See the documentation for book_api::books_by_author_handler() for
+details on what this route returns.
 
 **/
-pub fn by_author() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_author(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(BOOK_ROOT)
         .and(warp::path("author"))
         .and(warp::path::param())
 	.and(warp::get())
-        .map(|author: String| format!("Tried to get book with author: {}", author))
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|author: String, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(book::books_by_author_handler(state, author, params).await)
+	})
+}
+
+/** 
+
+book#by_isbn maps to the path /book/isbn/:isbn where :isbn is an
+ISBN-10 or ISBN-13, with or without hyphens.
+
+See the documentation for book::book_by_isbn_handler() for details on
+what this route returns.
+
+**/
+pub fn by_isbn(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("isbn"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+	.and(warp::get())
+	.and(warp::header::optional::<String>("if-none-match"))
+        .and(with_state(state))
+        .and_then(|isbn: String, if_none_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(book::book_by_isbn_handler(state, isbn, if_none_match).await)
+	})
+}
+
+/** 
+
+book#isbn maps to the path /isbn/:isbn, which checks an ISBN and
+converts it between its ISBN-10 and ISBN-13 forms.
+
+See the documentation for book::isbn_handler() for details on what
+this route returns.
+
+**/
+pub fn isbn() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path("isbn")
+        .and(warp::path::param())
+        .and(warp::path::end())
+	.and(warp::get())
+        .and_then(|isbn: String| async move {
+	    Ok::<_, Infallible>(book::isbn_handler(isbn).await)
+	})
+}
+
+/** 
+
+book#authors maps to the path /book/id/:id/authors, the people
+credited on the book with that id.
+
+See the documentation for author::book_credits_handler() for details
+on what this route returns.
+
+**/
+pub fn authors(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("authors"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(author::book_credits_handler(state, id).await)
+	})
+}
+
+/** 
+
+book#tags maps to the path /book/id/:id/tags, the tags of the book
+with that id.
+
+See the documentation for tag::book_tags_handler() for details on what
+this route returns.
+
+**/
+pub fn tags(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("tags"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(tag::book_tags_handler(state, id).await)
+	})
+}
+
+/** 
+
+book#history maps to the path /book/id/:id/history and accepts the
+optional query parameters limit and offset.
+
+See the documentation for history::history_handler() for details on
+what this route returns.
+
+**/
+pub fn history(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("history"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|id: u32, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(history::history_handler(state, Entity::Book, id, params).await)
+	})
 }
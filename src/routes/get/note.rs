@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::note;
+use crate::api::models::history::Entity;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const NOTE_ROOT: &str = "note";
+
+/**
+
+note#of_book maps to the path /book/id/:id/notes and accepts the
+optional query parameters limit and offset.
+
+See the documentation for note::notes_handler() for details on what
+this route returns.
+
+**/
+pub fn of_book(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    of_record("book", Entity::Book, state)
+}
+
+/**
+
+note#of_reading maps to the path /reading/id/:id/notes and accepts the
+optional query parameters limit and offset.
+
+See the documentation for note::notes_handler() for details on what
+this route returns.
+
+**/
+pub fn of_reading(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    of_record("reading", Entity::Reading, state)
+}
+
+fn of_record(
+    root: &'static str,
+    owner: Entity,
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(root)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("notes"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(move |id: u32, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(note::notes_handler(state, owner, id, params).await)
+	})
+}
+
+/**
+
+note#by_id maps to the path /note/id/:id.
+
+See the documentation for note::get_note_handler() for details on what
+this route returns.
+
+**/
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(NOTE_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(note::get_note_handler(state, id).await)
+	})
+}
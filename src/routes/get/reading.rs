@@ -1,23 +1,32 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
 use warp::Filter;
-use crate::api::controllers::reading;
+use crate::api::controllers::{history, reading};
+use crate::api::models::history::Entity;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
 
 const READINGS_ROOT: &str = "reading";
 
 /** 
 
-reading#all maps to the path /reading/all
+reading#all maps to the path /reading/all and accepts the optional
+query parameters limit, offset, started_after, finished_before,
+in_progress and expand.
 
-Currently this route always returns 200 with no body for any get
-request. But in the future this route is intended to return all rows
-from the reading table (all reading objects) in the sqlite
-database. This functionality may be revised in the future.
+See the documentation for reading_api::all_readings_handler() for
+details on what this route returns.
 
 **/
-pub fn all() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn all(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(READINGS_ROOT)
         .and(warp::path("all"))
 	.and(warp::get())
-        .map(|| format!("Tried to get all readings!"))
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(reading::all_readings_handler(state, params).await)
+	})
 }
 
 /** 
@@ -29,52 +38,104 @@ See the documentation for reading_api::reading_by_id_handler() for details
 on what this route returns.
 
 **/
-pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(READINGS_ROOT)
         .and(warp::path("id"))
         .and(warp::path::param())
+        .and(warp::path::end())
 	.and(warp::get())
-        .map(|id: u32| {
-	   reading::reading_by_id_handler(id) 
+	.and(warp::header::optional::<String>("if-none-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_none_match: Option<String>, state: AppState| async move {
+	   Ok::<_, Infallible>(reading::reading_by_id_handler(state, id, if_none_match).await)
 	})
 }
 
 /** 
 
 reading#by_title maps to the path /reading/title/:title where :title
-is a string corresponding to the title column of the reading table in
-sqlite.
+is a string matched against the title of the book each reading refers
+to. It accepts the optional query parameters fuzzy and min_score.
 
-Currently this route always returns 200 with no body for any get
-request. But in the future this route is intended to return any rows
-from the reading table (reading objects) where the title matches the
-supplied parameter.
+See the documentation for reading_api::readings_by_title_handler() for
+details on what this route returns.
 
 **/
-pub fn by_title() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_title(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(READINGS_ROOT)
         .and(warp::path("title"))
         .and(warp::path::param())
 	.and(warp::get())
-        .map(|title: String| format!("Tried to get readings for the book with title: {}", title))
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|title: String, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(reading::readings_by_title_handler(state, title, params).await)
+	})
 }
 
 /** 
 
-reading#by_author maps to the path /reading/title/:author where
-:author is a string corresponding to the author column of the reading
-table in sqlite.
+reading#by_author maps to the path /reading/author/:author where
+:author is a string matched against the author of the book each
+reading refers to. It accepts the optional query parameters fuzzy and
+min_score.
 
-Currently this route always returns 200 with no body for any get
-request. But in the future this route is intended to return any rows
-from the reading table (reading objects) where the author matches the
-supplied parameter.
+See the documentation for reading_api::readings_by_author_handler()
+for details on what this route returns.
 
 **/
-pub fn by_author() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_author(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(READINGS_ROOT)
         .and(warp::path("author"))
         .and(warp::path::param())
 	.and(warp::get())
-        .map(|author: String| format!("Tried to get readings for the author: {}", author))
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|author: String, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(reading::readings_by_author_handler(state, author, params).await)
+	})
+}
+
+/** 
+
+reading#progress maps to the path /reading/id/:id/progress.
+
+See the documentation for reading_api::reading_progress_handler() for
+details on what this route returns.
+
+**/
+pub fn progress(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READINGS_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("progress"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	   Ok::<_, Infallible>(reading::reading_progress_handler(state, id).await)
+	})
+}
+
+/** 
+
+reading#history maps to the path /reading/id/:id/history and accepts the
+optional query parameters limit and offset.
+
+See the documentation for history::history_handler() for details on
+what this route returns.
+
+**/
+pub fn history(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READINGS_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("history"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|id: u32, params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(history::history_handler(state, Entity::Reading, id, params).await)
+	})
 }
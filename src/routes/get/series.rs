@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::series;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const SERIES_ROOT: &str = "series";
+
+/** 
+
+series#all maps to the path /series/all and accepts the optional query
+parameters limit and offset.
+
+See the documentation for series::all_series_handler() for details on
+what this route returns.
+
+**/
+pub fn all(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("all"))
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(series::all_series_handler(state, params).await)
+	})
+}
+
+/** 
+
+series#by_id maps to the path /series/id/:id where :id is a positive
+integer corresponding to a row id in sqlite.
+
+See the documentation for series::series_by_id_handler() for details
+on what this route returns.
+
+**/
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+	.and(warp::get())
+	.and(warp::header::optional::<String>("if-none-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_none_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(series::series_by_id_handler(state, id, if_none_match).await)
+	})
+}
+
+/** 
+
+series#books maps to the path /series/id/:id/books, the books of the
+series with that id in reading order.
+
+See the documentation for series::series_books_handler() for details
+on what this route returns.
+
+**/
+pub fn books(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("books"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(series::series_books_handler(state, id).await)
+	})
+}
+
+/** 
+
+series#next maps to the path /series/id/:id/next, the first book of
+the series with that id that hasn't been read.
+
+See the documentation for series::next_in_series_handler() for details
+on what this route returns.
+
+**/
+pub fn next(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("next"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(series::next_in_series_handler(state, id).await)
+	})
+}
+
+/** 
+
+series#next_in_started maps to the path /series/next, the next unread
+book of every series that has been started.
+
+See the documentation for series::next_in_started_series_handler() for
+details on what this route returns.
+
+**/
+pub fn next_in_started(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("next"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|state: AppState| async move {
+	    Ok::<_, Infallible>(series::next_in_started_series_handler(state).await)
+	})
+}
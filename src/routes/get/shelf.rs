@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::shelf;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const SHELF_ROOT: &str = "shelf";
+
+/** 
+
+shelf#all maps to the path /shelf/all and accepts the optional query
+parameters limit and offset.
+
+See the documentation for shelf::all_shelves_handler() for details on
+what this route returns.
+
+**/
+pub fn all(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SHELF_ROOT)
+        .and(warp::path("all"))
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(shelf::all_shelves_handler(state, params).await)
+	})
+}
+
+/** 
+
+shelf#by_id maps to the path /shelf/id/:id where :id is a positive
+integer corresponding to a row id in sqlite.
+
+See the documentation for shelf::shelf_by_id_handler() for details on
+what this route returns.
+
+**/
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SHELF_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+	.and(warp::get())
+	.and(warp::header::optional::<String>("if-none-match"))
+        .and(with_state(state))
+        .and_then(|id: u32, if_none_match: Option<String>, state: AppState| async move {
+	    Ok::<_, Infallible>(shelf::shelf_by_id_handler(state, id, if_none_match).await)
+	})
+}
+
+/** 
+
+shelf#books maps to the path /shelf/id/:id/books, the books on the
+shelf with that id in order.
+
+See the documentation for shelf::shelf_books_handler() for details on
+what this route returns.
+
+**/
+pub fn books(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SHELF_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("books"))
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: u32, state: AppState| async move {
+	    Ok::<_, Infallible>(shelf::shelf_books_handler(state, id).await)
+	})
+}
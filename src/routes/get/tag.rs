@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::tag;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const TAG_ROOT: &str = "tag";
+
+/** 
+
+tag#all maps to the path /tag/all and accepts the optional query
+parameters limit and offset.
+
+See the documentation for tag::all_tags_handler() for details on what
+this route returns.
+
+**/
+pub fn all(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(TAG_ROOT)
+        .and(warp::path("all"))
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(tag::all_tags_handler(state, params).await)
+	})
+}
@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::trash;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
+
+const TRASH_ROOT: &str = "trash";
+
+/** 
+
+trash#all maps to the path /trash and accepts the optional query
+parameters type, limit and offset.
+
+See the documentation for trash::trash_handler() for details on what
+this route returns.
+
+**/
+pub fn all(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(TRASH_ROOT)
+        .and(warp::path::end())
+	.and(warp::get())
+        .and(warp::query::query())
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
+	    Ok::<_, Infallible>(trash::trash_handler(state, params).await)
+	})
+}
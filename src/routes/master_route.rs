@@ -16,103 +16,243 @@ These functions contain no real logic to speak of, but more serve as
 containers for organizing the different routes and combining them in
 a neat fashion.
 
+Every generator takes the `AppState` built in `main` and passes a clone
+of it down to the routes that need to reach the database.
+
+Whatever no route accepts is handed to `recover::handle_rejection`, so
+the final route never rejects, it always answers (with a JSON error if
+nothing else).
+
 !*/
 
+use std::convert::Infallible;
 use warp::Filter;
 
+use crate::api::state::AppState;
 use crate::routes::create;
 use crate::routes::delete;
 use crate::routes::get;
 use crate::routes::update;
+use crate::routes::recover;
 use crate::routes::search;
 
 fn generate_create_routes(
+    state: AppState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     /* CREATE routes */
-    let new_book = create::book::new_book();
-    let new_reading = create::reading::new_reading();
+    let new_book = create::book::new_book(state.clone());
+    let new_reading = create::reading::new_reading(state.clone());
+    let new_author = create::author::new_author(state.clone());
+    let new_series = create::series::new_series(state.clone());
+    let new_shelf = create::shelf::new_shelf(state.clone());
+    let book_note = create::note::for_book(state.clone());
+    let reading_note = create::note::for_reading(state.clone());
+    let batch = create::batch::run(state);
 
-    new_book.or(new_reading)
+    new_book
+        .or(new_reading)
+        .or(new_author)
+        .or(new_series)
+        .or(new_shelf)
+        .or(book_note)
+        .or(reading_note)
+        .or(batch)
 }
 
 fn generate_get_routes(
+    state: AppState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
 {
     /* READ (get) routes */
     // For book objects
-    let all_books = get::book::all();
-    let book_by_id = get::book::by_id();
-    let book_by_title = get::book::by_title();
-    let book_by_author = get::book::by_author();
+    let all_books = get::book::all(state.clone());
+    let book_by_id = get::book::by_id(state.clone());
+    let book_by_title = get::book::by_title(state.clone());
+    let book_by_author = get::book::by_author(state.clone());
+    let book_authors = get::book::authors(state.clone());
+    let book_tags = get::book::tags(state.clone());
+    let book_by_isbn = get::book::by_isbn(state.clone());
+    let isbn = get::book::isbn();
+    let book_history = get::book::history(state.clone());
 
     let book_routes = all_books
         .or(book_by_id)
         .or(book_by_title)
-        .or(book_by_author);
+        .or(book_by_author)
+        .or(book_authors)
+        .or(book_tags)
+        .or(book_by_isbn)
+        .or(isbn)
+        .or(book_history);
 
     // For reading objects
-    let all_readings = get::reading::all();
-    let reading_by_id = get::reading::by_id();
-    let readings_by_title = get::reading::by_title();
-    let readings_by_author = get::reading::by_author();
+    let all_readings = get::reading::all(state.clone());
+    let reading_by_id = get::reading::by_id(state.clone());
+    let readings_by_title = get::reading::by_title(state.clone());
+    let readings_by_author = get::reading::by_author(state.clone());
+    let reading_progress = get::reading::progress(state.clone());
+    let reading_history = get::reading::history(state.clone());
 
     let reading_routes = all_readings
         .or(reading_by_id)
         .or(readings_by_title)
-        .or(readings_by_author);
+        .or(readings_by_author)
+        .or(reading_progress)
+        .or(reading_history);
+
+    // For author objects
+    let all_authors = get::author::all(state.clone());
+    let author_by_id = get::author::by_id(state.clone());
+    let author_books = get::author::books(state.clone());
+
+    let author_routes = all_authors.or(author_by_id).or(author_books);
+
+    // For series objects
+    let all_series = get::series::all(state.clone());
+    let series_by_id = get::series::by_id(state.clone());
+    let series_books = get::series::books(state.clone());
+    let next_in_series = get::series::next(state.clone());
+    let next_in_started_series = get::series::next_in_started(state.clone());
+
+    let series_routes = all_series
+        .or(series_by_id)
+        .or(series_books)
+        .or(next_in_series)
+        .or(next_in_started_series);
 
-    book_routes.or(reading_routes)
+    // For tags and shelves
+    let all_tags = get::tag::all(state.clone());
+    let all_shelves = get::shelf::all(state.clone());
+    let shelf_by_id = get::shelf::by_id(state.clone());
+    let shelf_books = get::shelf::books(state.clone());
+
+    let shelf_routes = all_tags.or(all_shelves).or(shelf_by_id).or(shelf_books);
+
+    // For notes on books and readings
+    let book_notes = get::note::of_book(state.clone());
+    let reading_notes = get::note::of_reading(state.clone());
+    let note_by_id = get::note::by_id(state.clone());
+
+    let note_routes = book_notes.or(reading_notes).or(note_by_id);
+
+    // For deleted books and readings
+    let trash_routes = get::trash::all(state);
+
+    book_routes
+        .or(reading_routes)
+        .or(author_routes)
+        .or(series_routes)
+        .or(shelf_routes)
+        .or(note_routes)
+        .or(trash_routes)
 }
 
 fn generate_update_routes(
+    state: AppState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     /* UPDATE routes */
     // For book objects
-    let book_by_id = update::book::by_id();
-    let book_routes = book_by_id;
+    let book_by_id = update::book::by_id(state.clone());
+    let patch_book = update::book::patch_by_id(state.clone());
+    let book_authors = update::book::set_authors(state.clone());
+    let book_tags = update::book::set_tags(state.clone());
+    let revert_book = update::book::revert(state.clone());
+    let book_routes = book_by_id.or(patch_book).or(book_authors).or(book_tags).or(revert_book);
 
     // For reading objects
-    let reading_by_id = update::reading::by_id();
-    let reading_routes = reading_by_id;
+    let reading_by_id = update::reading::by_id(state.clone());
+    let patch_reading = update::reading::patch_by_id(state.clone());
+    let log_progress = update::reading::log_progress(state.clone());
+    let revert_reading = update::reading::revert(state.clone());
+    let reading_routes = reading_by_id.or(patch_reading).or(log_progress).or(revert_reading);
 
-    // The variables book_routes and reading_routes will become useful
-    // when there are other endpoints to include. They are redundant for now.
+    // For author objects
+    let author_routes = update::author::by_id(state.clone());
+
+    // For series objects
+    let series_by_id = update::series::by_id(state.clone());
+    let series_book = update::series::put_book(state.clone());
+    let series_routes = series_by_id.or(series_book);
+
+    // For shelf objects
+    let shelf_by_id = update::shelf::by_id(state.clone());
+    let change_shelf_books = update::shelf::change_books(state.clone());
+    let set_shelf_books = update::shelf::set_books(state.clone());
+    let shelf_routes = shelf_by_id.or(change_shelf_books).or(set_shelf_books);
+
+    // For notes
+    let note_routes = update::note::by_id(state.clone());
+
+    // For deleted books and readings
+    let trash_routes = update::trash::restore(state);
 
     // All update routes
-    book_routes.or(reading_routes)
+    book_routes
+        .or(reading_routes)
+        .or(author_routes)
+        .or(series_routes)
+        .or(shelf_routes)
+        .or(note_routes)
+        .or(trash_routes)
 }
 
 fn generate_delete_routes(
+    state: AppState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     /* DELETE routes */
     // For book objects
-    let book_by_id = delete::book::by_id();
+    let book_by_id = delete::book::by_id(state.clone());
     let book_routes = book_by_id;
 
     // For reading objects
-    let reading_by_id = delete::reading::by_id();
-    let reading_routes = reading_by_id;
+    let reading_by_id = delete::reading::by_id(state.clone());
+    let progress_entry = delete::reading::progress_entry(state.clone());
+    let reading_routes = reading_by_id.or(progress_entry);
+
+    // For author objects
+    let author_by_id = delete::author::by_id(state.clone());
+    let author_routes = author_by_id;
+
+    // For series objects
+    let series_by_id = delete::series::by_id(state.clone());
+    let series_book = delete::series::remove_book(state.clone());
+    let series_routes = series_by_id.or(series_book);
+
+    // For tags and shelves
+    let tag_by_id = delete::tag::by_id(state.clone());
+    let shelf_by_id = delete::shelf::by_id(state.clone());
+    let shelf_routes = tag_by_id.or(shelf_by_id);
+
+    // For notes
+    let note_routes = delete::note::by_id(state);
 
     // The variables book_routes and reading_routes will become useful
     // when there are other endpoints to include. They are redundant for now.
 
     // All delete routes
-    book_routes.or(reading_routes)
+    book_routes
+        .or(reading_routes)
+        .or(author_routes)
+        .or(series_routes)
+        .or(shelf_routes)
+        .or(note_routes)
 }
 
 fn generate_search_routes(
+    state: AppState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
 
-    search::search::search_content()
+    search::search::search_content(state)
 }
 
 pub fn generate_master_route(
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let create_routes = generate_create_routes();
-    let read_routes = generate_get_routes();
-    let update_routes = generate_update_routes();
-    let delete_routes = generate_delete_routes();
-    let search_routes = generate_search_routes();
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = Infallible> + Clone {
+    let create_routes = generate_create_routes(state.clone());
+    let read_routes = generate_get_routes(state.clone());
+    let update_routes = generate_update_routes(state.clone());
+    let delete_routes = generate_delete_routes(state.clone());
+    let search_routes = generate_search_routes(state);
 
     /* Final route */
     create_routes
@@ -120,4 +260,5 @@ pub fn generate_master_route(
         .or(update_routes)
         .or(delete_routes)
         .or(search_routes)
+        .recover(recover::handle_rejection)
 }
@@ -4,4 +4,6 @@ pub mod update;
 pub mod delete;
 pub mod search;
 
+pub mod filters;
+pub mod recover;
 pub mod master_route;
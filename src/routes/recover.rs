@@ -0,0 +1,53 @@
+/*!
+
+# recover
+
+Requests that don't make it as far as a controller are rejected by
+warp's filters instead, and warp's own responses for those are plain
+text. `handle_rejection` turns every rejection into the same JSON error
+body the controllers use, so a client sees one error format whether
+its request had a bad id or a malformed body.
+
+!*/
+
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::Rejection;
+
+use crate::api::error::{error_response, ApiError, ErrorBody};
+
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl warp::Reply, Infallible> {
+    if let Some(error) = rejection.find::<ApiError>() {
+        return Ok(error.to_response());
+    }
+
+    let (status, code, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "route_not_found", String::from("No route matches that path"))
+    } else if let Some(err) = rejection.find::<warp::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, "invalid_body", err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "invalid_query", err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::PayloadTooLarge>() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::LengthRequired>() {
+        (StatusCode::LENGTH_REQUIRED, "length_required", err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::UnsupportedMediaType>() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", err.to_string())
+    } else if let Some(err) = rejection.find::<warp::reject::MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", err.to_string())
+    } else {
+        println!("Unhandled rejection: {:?}", rejection);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            String::from("The request could not be handled"),
+        )
+    };
+
+    let body = ErrorBody {
+        code: code.to_string(),
+        message,
+        field: None,
+    };
+    Ok(error_response(status, &body))
+}
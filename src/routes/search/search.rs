@@ -1,7 +1,10 @@
+use std::convert::Infallible;
 use warp::Filter;
 use std::collections::HashMap;
 
 use crate::api::controllers::search;
+use crate::api::state::AppState;
+use crate::routes::filters::with_state;
 
 const SEARCH_ROOT: &str = "search";
 const BOOK_ROOT: &str = "books";
@@ -14,7 +17,7 @@ wizardry happening here is too much for my brain. However, query() is
 basically just a wrapper on top of serde_urlencoded::from_str, so any type
 that would work there is acceptable for the param in map.
 */
-pub fn search_content() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn search_content(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     let books_search = warp::path(BOOK_ROOT);
     let readings_search = warp::path(READING_ROOT);
 
@@ -22,17 +25,19 @@ pub fn search_content() -> impl Filter<Extract = impl warp::Reply, Error = warp:
         .and(warp::get())
         .and(books_search)
         .and(warp::query::query())
-        .map(|params: HashMap<String, String>| {
+        .and(with_state(state.clone()))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
             println!("Params are {:#?}", params);
-            search::search_books_handler(params)
+            Ok::<_, Infallible>(search::search_books_handler(state, params).await)
         });
     let readings_search_route = warp::path(SEARCH_ROOT)
         .and(warp::get())
         .and(readings_search)
         .and(warp::query::query())
-        .map(|params: HashMap<String, String>| {
+        .and(with_state(state))
+        .and_then(|params: HashMap<String, String>, state: AppState| async move {
             println!("Params are {:#?}", params);
-            search::search_readings_handler(params)
+            Ok::<_, Infallible>(search::search_readings_handler(state, params).await)
         });
 
     book_search_route.or(readings_search_route)
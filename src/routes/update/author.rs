@@ -0,0 +1,24 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::author;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_actor, with_state};
+use std::collections::HashMap;
+
+const UPDATE_ROOT: &str = "update";
+const AUTHOR_ROOT: &str = "author";
+
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(UPDATE_ROOT)
+        .and(warp::path(AUTHOR_ROOT))
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|if_match: Option<String>, body: HashMap<String, serde_json::Value>, actor: Option<String>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(author::update_author_handler(state, body, if_match, actor).await)
+        })
+}
@@ -1,18 +1,126 @@
+use std::convert::Infallible;
 use warp::Filter;
-use crate::api::controllers::book;
+use warp::hyper::body::Bytes;
+use crate::api::controllers::{author, book, history, tag};
+use crate::api::models::history::Entity;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_actor, with_state};
 use std::collections::HashMap;
 
 const UPDATE_ROOT: &str = "update";
 const BOOK_ROOT: &str = "book";
 
-pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(UPDATE_ROOT)
         .and(warp::path(BOOK_ROOT))
         .and(warp::put())
-	.and(warp::body::content_length_limit(1024 * 4))
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
 	.and(warp::body::json())
-	.map(|body: HashMap<String, serde_json::Value>| {
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|if_match: Option<String>, body: HashMap<String, serde_json::Value>, actor: Option<String>, state: AppState| async move {
             let body = serde_json::to_string(&body).unwrap();
-            book::update_book_handler(body)
+            Ok::<_, Infallible>(book::update_book_handler(state, body, if_match, actor).await)
+        })
+}
+
+/**
+
+book#patch maps to PATCH requests on the path /book/id/:id and changes
+only the fields given in the body, which is a JSON merge patch or a
+JSON Patch depending on the Content-Type.
+
+**/
+pub fn patch_by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::patch())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("content-type"))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(with_actor())
+	.and(warp::body::bytes())
+	.and(with_state(state))
+	.and_then(|id: u32, content_type: Option<String>, if_match: Option<String>, actor: Option<String>, body: Bytes, state: AppState| async move {
+            Ok::<_, Infallible>(book::patch_book_handler(state, id, content_type, if_match, actor, body.to_vec()).await)
+        })
+}
+
+/**
+
+book#set_authors maps to PUT requests on the path /book/id/:id/authors
+and replaces the people credited on the book with the JSON array of
+credits in the body.
+
+**/
+pub fn set_authors(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("authors"))
+        .and(warp::path::end())
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|id: u32, if_match: Option<String>, body: serde_json::Value, actor: Option<String>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(author::set_book_credits_handler(state, id, body, if_match, actor).await)
+        })
+}
+
+/**
+
+book#set_tags maps to PUT requests on the path /book/id/:id/tags and
+replaces the book's tags with the JSON array of names in the body,
+honoring If-Match against the book's ETag.
+
+**/
+pub fn set_tags(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("tags"))
+        .and(warp::path::end())
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_state(state))
+	.and_then(|id: u32, if_match: Option<String>, body: serde_json::Value, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(tag::set_book_tags_handler(state, id, body, if_match).await)
+        })
+}
+
+/**
+
+book#revert maps to POST requests on the path
+/book/id/:id/history/:revision/revert and puts the book back the way
+it was after that revision.
+
+See the documentation for history::revert_handler() for details on
+what this route returns.
+
+**/
+pub fn revert(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(BOOK_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("history"))
+        .and(warp::path::param())
+        .and(warp::path("revert"))
+        .and(warp::path::end())
+        .and(warp::post())
+	.and(warp::header::optional::<String>("if-match"))
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|id: u32, revision: u32, if_match: Option<String>, actor: Option<String>, state: AppState| async move {
+            Ok::<_, Infallible>(history::revert_handler(state, Entity::Book, id, revision, if_match, actor).await)
         })
 }
@@ -0,0 +1,33 @@
+use std::convert::Infallible;
+use warp::Filter;
+use warp::hyper::body::Bytes;
+use crate::api::controllers::note;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_state};
+
+const NOTE_ROOT: &str = "note";
+
+/**
+
+note#by_id maps to PUT requests on the path /note/id/:id and replaces
+the note's Markdown with the body.
+
+See the documentation for note::update_note_handler() for details on
+what this route returns.
+
+**/
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(NOTE_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::put())
+	.and(body_limit(state.body_limits.note))
+	.and(warp::header::optional::<String>("content-type"))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::bytes())
+	.and(with_state(state))
+	.and_then(|id: u32, content_type: Option<String>, if_match: Option<String>, body: Bytes, state: AppState| async move {
+            Ok::<_, Infallible>(note::update_note_handler(state, id, content_type, if_match, body.to_vec()).await)
+        })
+}
@@ -1,19 +1,102 @@
+use std::convert::Infallible;
 use warp::Filter;
+use warp::hyper::body::Bytes;
 use std::collections::HashMap;
 
-use crate::api::controllers::reading;
+use crate::api::controllers::{history, reading};
+use crate::api::models::history::Entity;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_actor, with_state};
 
 const UPDATE_ROOT: &str = "update";
 const READING_ROOT: &str = "reading";
 
-pub fn by_id() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(UPDATE_ROOT)
         .and(warp::path(READING_ROOT))
         .and(warp::put())
-	.and(warp::body::content_length_limit(1024 * 4))
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
 	.and(warp::body::json())
-	.map(|body: HashMap<String, serde_json::Value>| {
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|if_match: Option<String>, body: HashMap<String, serde_json::Value>, actor: Option<String>, state: AppState| async move {
             let body = serde_json::to_string(&body).unwrap();
-            reading::update_reading_handler(body)
+            Ok::<_, Infallible>(reading::update_reading_handler(state, body, if_match, actor).await)
+        })
+}
+
+/**
+
+reading#patch maps to PATCH requests on the path /reading/id/:id and changes
+only the fields given in the body, which is a JSON merge patch or a
+JSON Patch depending on the Content-Type.
+
+**/
+pub fn patch_by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READING_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::patch())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("content-type"))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(with_actor())
+	.and(warp::body::bytes())
+	.and(with_state(state))
+	.and_then(|id: u32, content_type: Option<String>, if_match: Option<String>, actor: Option<String>, body: Bytes, state: AppState| async move {
+            Ok::<_, Infallible>(reading::patch_reading_handler(state, id, content_type, if_match, actor, body.to_vec()).await)
+        })
+}
+
+/**
+
+reading#log_progress maps to POST requests on the path
+/reading/id/:id/progress and adds the entry in the body to the
+reading's progress log.
+
+**/
+pub fn log_progress(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READING_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("progress"))
+        .and(warp::path::end())
+        .and(warp::post())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_state(state))
+	.and_then(|id: u32, if_match: Option<String>, body: HashMap<String, serde_json::Value>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(reading::log_progress_handler(state, id, body, if_match).await)
+        })
+}
+
+/**
+
+reading#revert maps to POST requests on the path
+/reading/id/:id/history/:revision/revert and puts the reading back the way
+it was after that revision.
+
+See the documentation for history::revert_handler() for details on
+what this route returns.
+
+**/
+pub fn revert(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READING_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("history"))
+        .and(warp::path::param())
+        .and(warp::path("revert"))
+        .and(warp::path::end())
+        .and(warp::post())
+	.and(warp::header::optional::<String>("if-match"))
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|id: u32, revision: u32, if_match: Option<String>, actor: Option<String>, state: AppState| async move {
+            Ok::<_, Infallible>(history::revert_handler(state, Entity::Reading, id, revision, if_match, actor).await)
         })
 }
@@ -0,0 +1,49 @@
+use std::convert::Infallible;
+use warp::Filter;
+use warp::hyper::body::Bytes;
+use crate::api::controllers::series;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_state};
+use std::collections::HashMap;
+
+const UPDATE_ROOT: &str = "update";
+const SERIES_ROOT: &str = "series";
+
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(UPDATE_ROOT)
+        .and(warp::path(SERIES_ROOT))
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_state(state))
+	.and_then(|if_match: Option<String>, body: HashMap<String, serde_json::Value>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(series::update_series_handler(state, body, if_match).await)
+        })
+}
+
+/**
+
+series#put_book maps to PUT requests on the path
+/series/id/:id/books/:book and puts the book in the series, at the
+position given in the optional body.
+
+**/
+pub fn put_book(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SERIES_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("books"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::bytes())
+	.and(with_state(state))
+	.and_then(|id: u32, book: u32, if_match: Option<String>, body: Bytes, state: AppState| async move {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            Ok::<_, Infallible>(series::put_book_in_series_handler(state, id, book, body, if_match).await)
+        })
+}
@@ -0,0 +1,71 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::shelf;
+use crate::api::state::AppState;
+use crate::routes::filters::{body_limit, with_state};
+use std::collections::HashMap;
+
+const UPDATE_ROOT: &str = "update";
+const SHELF_ROOT: &str = "shelf";
+
+pub fn by_id(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(UPDATE_ROOT)
+        .and(warp::path(SHELF_ROOT))
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_state(state))
+	.and_then(|if_match: Option<String>, body: HashMap<String, serde_json::Value>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(shelf::update_shelf_handler(state, body, if_match).await)
+        })
+}
+
+/**
+
+shelf#change_books maps to POST requests on the path
+/shelf/id/:id/books and adds the books listed under `add` in the body
+to the shelf, and takes those under `remove` off it.
+
+**/
+pub fn change_books(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SHELF_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("books"))
+        .and(warp::path::end())
+        .and(warp::post())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_state(state))
+	.and_then(|id: u32, if_match: Option<String>, body: HashMap<String, serde_json::Value>, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(shelf::change_shelf_books_handler(state, id, body, if_match).await)
+        })
+}
+
+/**
+
+shelf#set_books maps to PUT requests on the path /shelf/id/:id/books
+and replaces the books on the shelf with the JSON array of book ids in
+the body, in that order.
+
+**/
+pub fn set_books(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(SHELF_ROOT)
+        .and(warp::path("id"))
+        .and(warp::path::param())
+        .and(warp::path("books"))
+        .and(warp::path::end())
+        .and(warp::put())
+	.and(body_limit(state.body_limits.record))
+	.and(warp::header::optional::<String>("if-match"))
+	.and(warp::body::json())
+	.and(with_state(state))
+	.and_then(|id: u32, if_match: Option<String>, body: serde_json::Value, state: AppState| async move {
+            let body = serde_json::to_string(&body).unwrap();
+            Ok::<_, Infallible>(shelf::set_shelf_books_handler(state, id, body, if_match).await)
+        })
+}
@@ -0,0 +1,30 @@
+use std::convert::Infallible;
+use warp::Filter;
+use crate::api::controllers::trash;
+use crate::api::state::AppState;
+use crate::routes::filters::{with_actor, with_state};
+
+const TRASH_ROOT: &str = "trash";
+
+/**
+
+trash#restore maps to POST requests on the path /trash/:type/:id/restore
+where :type is book or reading, and takes that record out of the trash.
+
+See the documentation for trash::restore_handler() for details on what
+this route returns.
+
+**/
+pub fn restore(state: AppState) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(TRASH_ROOT)
+        .and(warp::path::param())
+        .and(warp::path::param())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(warp::post())
+	.and(with_actor())
+	.and(with_state(state))
+	.and_then(|kind: String, id: u32, actor: Option<String>, state: AppState| async move {
+            Ok::<_, Infallible>(trash::restore_handler(state, kind, id, actor).await)
+        })
+}